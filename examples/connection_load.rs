@@ -0,0 +1,91 @@
+// Opens many idle connections to a running TCP server, then times queries
+// while they stay open.
+//
+//     cargo run --release --example connection_load -- [address] [connections] [queries]
+//
+// Defaults to 127.0.0.1:6969, 10000 connections and 200 queries, against a
+// server without user accounts. Raise the open file limit of both processes
+// first (`ulimit -n 65536`), and the server's `server.max_connections` when
+// going past its default.
+
+use std::env;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+
+/// Connections being opened at once, so the listen backlog never overflows.
+const CONNECTING_AT_ONCE: usize = 256;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let address = args.first().cloned().unwrap_or_else(|| "127.0.0.1:6969".to_string());
+    let connections: usize = args.get(1).map(|n| n.parse()).transpose()?.unwrap_or(10_000);
+    let queries: usize = args.get(2).map(|n| n.parse()).transpose()?.unwrap_or(200);
+
+    let started = Instant::now();
+    let connecting = Arc::new(Semaphore::new(CONNECTING_AT_ONCE));
+    let mut tasks = Vec::with_capacity(connections);
+    for _ in 0..connections {
+        let address = address.clone();
+        let permit = Arc::clone(&connecting).acquire_owned().await?;
+        tasks.push(tokio::spawn(async move {
+            let connection = connect(&address).await;
+            drop(permit);
+            connection
+        }));
+    }
+    let mut open = Vec::with_capacity(connections);
+    for task in tasks {
+        open.push(task.await??);
+    }
+    println!("Opened {} connections in {:.2?}", open.len(), started.elapsed());
+
+    // Queries go over the idle connections in turn, so each one is checked
+    // to still be served.
+    let mut latencies = Vec::with_capacity(queries);
+    for i in 0..queries {
+        let index = i * open.len().max(1) / queries.max(1);
+        let connection = &mut open[index];
+        let sent = Instant::now();
+        connection.get_mut().write_all(b"SHOW TABLES\n").await?;
+        read_reply(connection).await?;
+        latencies.push(sent.elapsed());
+    }
+    latencies.sort();
+    if !latencies.is_empty() {
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        println!("{} queries with {} connections open: p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+            latencies.len(), open.len(), percentile(50), percentile(99), percentile(100));
+    }
+
+    let started = Instant::now();
+    drop(open);
+    println!("Closed them in {:.2?}", started.elapsed());
+    Ok(())
+}
+
+/// Connects and reads the welcome text.
+async fn connect(address: &str) -> std::io::Result<BufReader<TcpStream>> {
+    let mut connection = BufReader::new(TcpStream::connect(address).await?);
+    let mut line = String::new();
+    for _ in 0..2 {
+        line.clear();
+        connection.read_line(&mut line).await?;
+        if line.starts_with("Error") {
+            return Err(std::io::Error::other(line.trim().to_string()));
+        }
+    }
+    Ok(connection)
+}
+
+/// Reads the one-line reply to SHOW TABLES.
+async fn read_reply(connection: &mut BufReader<TcpStream>) -> std::io::Result<()> {
+    let mut line = String::new();
+    if connection.read_line(&mut line).await? == 0 || !line.starts_with("Tables:") {
+        return Err(std::io::Error::other(format!("Unexpected reply: {:?}", line)));
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+// Builds a table of typed rows, saves it as a data file with each codec and
+// as a paged directory, and reports the size of each along with the time and
+// peak memory of opening it and running one query.
+//
+//     cargo run --release --example row_storage -- [rows] [dir]
+//
+// Defaults to 1,000,000 rows in a temporary directory. Each file is opened
+// in a child process so that its peak memory is measured on its own; peak
+// memory is read from /proc and so is only reported on Linux.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+use atom::database::paged::PagedEngine;
+use atom::database::{ColumnType, Compression, FileOptions};
+use atom::utils::persistence::{file_size, read_snapshot, write_snapshot};
+use atom::{Row, Storage, StorageEngine};
+
+const CODECS: [Compression; 3] = [Compression::None, Compression::Lz4, Compression::Zstd];
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let [mode, path] = args.as_slice() && (mode == "--open" || mode == "--open-paged") {
+        return open(mode == "--open-paged", Path::new(path));
+    }
+    let rows: usize = args.first().map(|n| n.parse()).transpose()?.unwrap_or(1_000_000);
+    let dir = args.get(1).map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join(format!("atom-row-storage-{}", std::process::id())));
+    std::fs::create_dir_all(&dir)?;
+
+    let started = Instant::now();
+    let mut storage = StorageEngine::new();
+    fill(&mut storage, rows)?;
+    println!("Built {} rows in {:.1} s", rows, started.elapsed().as_secs_f64());
+
+    println!("{:<12} {:>10} {:>10} {:>12}", "file", "size", "open+query", "peak memory");
+    for codec in CODECS {
+        let path = dir.join(format!("rows-{}.bin", codec));
+        write_snapshot(&storage, &path, &FileOptions { compression: codec, ..FileOptions::default() })?;
+        report(&format!("{}", codec), &path, "--open")?;
+    }
+    let paged = dir.join("rows.pages");
+    let _ = std::fs::remove_dir_all(&paged);
+    let (mut engine, _) = PagedEngine::open(&paged, 256)?;
+    engine.merge(&storage, false)?;
+    engine.checkpoint()?;
+    drop(engine);
+    report("paged", &paged, "--open-paged")?;
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+fn fill(storage: &mut StorageEngine, rows: usize) -> Result<(), String> {
+    let columns = ["id", "name", "email", "age", "score", "active"].map(String::from).to_vec();
+    let types = vec![ColumnType::Integer, ColumnType::Text, ColumnType::Text, ColumnType::Integer, ColumnType::Float, ColumnType::Boolean];
+    storage.create_typed_table("people", columns, types)?;
+    for n in 0..rows {
+        let mut row = Row::new();
+        row.insert("id".to_string(), n.to_string());
+        row.insert("name".to_string(), format!("user{}", n));
+        row.insert("email".to_string(), format!("user{}@example.com", n));
+        row.insert("age".to_string(), (18 + n % 60).to_string());
+        row.insert("score".to_string(), format!("{}.{}", n % 100, n % 7));
+        row.insert("active".to_string(), (n % 3 == 0).to_string());
+        storage.insert_row("people", row)?;
+    }
+    Ok(())
+}
+
+/// Opens `path` in a child process and prints the measurements it reports.
+fn report(label: &str, path: &Path, mode: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new(env::current_exe()?).arg(mode).arg(path).output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned().into());
+    }
+    let measured = String::from_utf8_lossy(&output.stdout);
+    let (seconds, peak) = measured.trim().split_once(' ').unwrap_or(("?", "?"));
+    println!("{:<12} {:>7.1} MB {:>8} s {:>12}", label, file_size(path) as f64 / 1e6, seconds, peak);
+    Ok(())
+}
+
+/// Child side of `report`: opens the database, runs a query, and prints the
+/// time taken and the process's peak memory.
+fn open(paged: bool, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let storage: Box<dyn Storage> = if paged {
+        Box::new(PagedEngine::open(path, 256)?.0)
+    } else {
+        Box::new(read_snapshot(path, &Default::default())?)
+    };
+    let found = storage.select_where("people", "email", "user500000@example.com")?;
+    let seconds = started.elapsed().as_secs_f64();
+    drop(found);
+    println!("{:.2} {}", seconds, peak_memory().unwrap_or_else(|| "?".to_string()));
+    Ok(())
+}
+
+fn peak_memory() -> Option<String> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: f64 = status.lines().find_map(|line| line.strip_prefix("VmHWM:"))?.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(format!("{:.0} MB", kb / 1e3))
+}
@@ -0,0 +1,302 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use crate::config::{ConfigOverrides, EngineKind, PersistenceMode};
+use crate::database::dump::DEFAULT_BATCH_SIZE;
+use crate::utils::csv::CsvOptions;
+use super::format::OutputFormat;
+
+#[derive(Debug, Parser)]
+#[command(name = "atom", version, about = "atomDB - A Rust Database with Immediate Autosave")]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Same as `atom serve`
+    #[arg(long, short = 's', hide = true)]
+    pub server: bool,
+
+    /// Same as `atom serve --shell`
+    #[arg(long, short = 'b', hide = true)]
+    pub both: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GlobalArgs {
+    /// Config file (default: $ATOM_CONFIG, or ./atom.toml if present)
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Path of the data file (sets both --data-dir and --data-file)
+    #[arg(long, global = true, value_name = "FILE")]
+    pub data: Option<PathBuf>,
+
+    /// Directory holding the data file
+    #[arg(long, global = true, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Data file name inside the data directory
+    #[arg(long, global = true, value_name = "NAME")]
+    pub data_file: Option<String>,
+
+    /// Storage engine: memory or paged
+    #[arg(long, global = true, value_name = "ENGINE")]
+    pub engine: Option<EngineKind>,
+
+    /// autosave, on_exit or memory
+    #[arg(long, global = true, value_name = "MODE")]
+    pub persistence: Option<PersistenceMode>,
+
+    /// Reject write statements and never save the data file
+    #[arg(long, global = true)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the TCP and RESP servers
+    Serve(ServeArgs),
+    /// Start the interactive shell (the default when no command is given)
+    Shell,
+    /// Execute statements from the command line, a file or stdin, then exit
+    Exec(ExecArgs),
+    /// Import tables from a database file, or rows from a CSV/JSON file, into the data file
+    Import(ImportArgs),
+    /// Export the database, or one table as CSV/JSON, to a file or stdout
+    Export(ExportArgs),
+    /// Write the database as a SQL script of CREATE TABLE and INSERT statements
+    Dump(DumpArgs),
+    /// Replay a SQL dump into the data file
+    Restore(RestoreArgs),
+    /// Rebuild the data file from a backup and the write log, up to an LSN or time
+    Recover(RecoverArgs),
+    /// Apply or revert numbered .sql migrations
+    Migrate(MigrateArgs),
+    /// Verify that the data file loads and that every row matches its table
+    Check,
+    /// Rewrite the data file, dropping expired keys
+    Compact,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ServeArgs {
+    /// Listen address for the servers
+    #[arg(long, value_name = "ADDRESS")]
+    pub bind: Option<String>,
+
+    /// TCP server port
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// RESP (Redis protocol) port
+    #[arg(long)]
+    pub resp_port: Option<u16>,
+
+    /// Also run the interactive shell
+    #[arg(long)]
+    pub shell: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExecArgs {
+    /// Statements to execute, separated by `;`
+    #[arg(conflicts_with = "file")]
+    pub sql: Option<String>,
+
+    /// Read `;`-terminated statements from a file (`-` for stdin)
+    #[arg(long, short = 'f', value_name = "FILE")]
+    pub file: Option<PathBuf>,
+
+    /// Stop at the first failing statement (the default)
+    #[arg(long, conflicts_with = "continue_on_error")]
+    pub stop_on_error: bool,
+
+    /// Keep going after a statement fails
+    #[arg(long = "continue")]
+    pub continue_on_error: bool,
+
+    /// Result format (default: the shell format from the config)
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// File to import
+    pub file: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = FileFormat::Json)]
+    pub format: FileFormat,
+
+    /// Overwrite tables that already exist
+    #[arg(long)]
+    pub replace: bool,
+
+    #[command(flatten)]
+    pub table_args: TableArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Output file (default: stdout)
+    #[arg(long, short = 'o', value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = FileFormat::Json)]
+    pub format: FileFormat,
+
+    #[command(flatten)]
+    pub table_args: TableArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct DumpArgs {
+    /// Output file (default: stdout)
+    #[arg(long, short = 'o', value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Only dump this table (repeatable)
+    #[arg(long = "table", value_name = "NAME")]
+    pub tables: Vec<String>,
+
+    /// Rows per INSERT statement
+    #[arg(long, default_value_t = DEFAULT_BATCH_SIZE)]
+    pub batch_size: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// SQL dump to replay (`-` for stdin)
+    pub file: PathBuf,
+
+    /// Overwrite tables that already exist
+    #[arg(long)]
+    pub replace: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RecoverArgs {
+    /// Backup written by `BACKUP TO`
+    pub backup: PathBuf,
+
+    /// Write log to replay (default: the one next to the data file)
+    #[arg(long, value_name = "FILE")]
+    pub log: Option<PathBuf>,
+
+    /// Stop after the change with this LSN
+    #[arg(long, value_name = "LSN")]
+    pub until_lsn: Option<u64>,
+
+    /// Stop after the last change made at or before this UTC time
+    /// (YYYY-MM-DDTHH:MM:SSZ or Unix seconds)
+    #[arg(long, value_name = "TIME")]
+    pub until: Option<String>,
+
+    /// Overwrite an existing data file
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    /// Directory of NNNN_name.up.sql / NNNN_name.down.sql files
+    #[arg(long, global = true, value_name = "DIR", default_value = "migrations")]
+    pub dir: PathBuf,
+
+    #[command(subcommand)]
+    pub action: MigrateAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// Apply pending migrations
+    Up {
+        /// Stop after this version
+        #[arg(long, value_name = "VERSION")]
+        to: Option<u64>,
+    },
+    /// Revert the most recently applied migrations
+    Down {
+        /// Number of migrations to revert
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// List migrations and whether they have been applied
+    Status,
+}
+
+#[derive(Debug, Args)]
+pub struct TableArgs {
+    /// Table to load or dump (required with csv and ndjson; makes json per-table)
+    #[arg(long, value_name = "NAME")]
+    pub table: Option<String>,
+
+    /// The first CSV line holds column names
+    #[arg(long)]
+    pub header: bool,
+
+    /// CSV field delimiter
+    #[arg(long, default_value_t = ',')]
+    pub delimiter: char,
+
+    /// CSV quote character
+    #[arg(long, default_value_t = '"')]
+    pub quote: char,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FileFormat {
+    /// Pretty-printed JSON of the whole database, or an array of rows with --table
+    Json,
+    /// The bincode format used for the data file
+    Binary,
+    /// Rows of a single table (needs --table)
+    Csv,
+    /// One JSON object per row of a single table (needs --table)
+    Ndjson,
+}
+
+impl TableArgs {
+    pub fn table(&self) -> Result<&str, String> {
+        self.table.as_deref().ok_or_else(|| "CSV and NDJSON import and export need --table".to_string())
+    }
+
+    pub fn options(&self) -> CsvOptions {
+        CsvOptions {
+            delimiter: self.delimiter,
+            quote: self.quote,
+            header: self.header,
+        }
+    }
+}
+
+impl Cli {
+    pub fn overrides(&self) -> ConfigOverrides {
+        let mut overrides = ConfigOverrides {
+            config_path: self.global.config.clone(),
+            data_dir: self.global.data_dir.clone(),
+            data_file: self.global.data_file.clone(),
+            engine: self.global.engine,
+            persistence: self.global.persistence,
+            read_only: self.global.read_only.then_some(true),
+            ..ConfigOverrides::default()
+        };
+
+        if let Some(path) = &self.global.data {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            overrides.data_dir = Some(dir.map_or_else(|| PathBuf::from("."), |dir| dir.to_path_buf()));
+            if let Some(file) = path.file_name() {
+                overrides.data_file = Some(file.to_string_lossy().into_owned());
+            }
+        }
+
+        if let Some(Command::Serve(serve)) = &self.command {
+            overrides.bind_address = serve.bind.clone();
+            overrides.port = serve.port;
+            overrides.resp_port = serve.resp_port;
+        }
+        overrides
+    }
+}
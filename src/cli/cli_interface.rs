@@ -1,108 +1,228 @@
-use std::io::{self, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
-use crate::database::{StorageEngine};
-use crate::query::{ QueryEngine };
+use std::time::Instant;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use crate::config::Config;
+use crate::database::{lock_storage, SharedStorage, Storage};
+use crate::query::{is_complete, split_statements, Cursors, PreparedStatements, QueryEngine, QueryResult};
 use crate::server::tcp_server::start_tcp_server;
-use crate::utils::demo_data::autosave_database;
+use crate::utils::persistence::{autosave_database, final_save_database, load_database};
+use super::completion::SqlHelper;
+use super::format::{render, OutputFormat, FORMAT_NAMES};
 
-pub fn run_cli(storage: Arc<Mutex<StorageEngine>>) {
+pub fn run_cli(storage: SharedStorage, config: Arc<Config>) {
     println!("atomDB CLI Mode");
     println!("Type 'help' for commands or 'quit' to exit.");
-    println!("Database autosaves after every write operation.");
-    
-    loop {
-        print!("atomDB> ");
-        io::stdout().flush().unwrap();
-        
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        let input = input.trim();
-        
-        if input.is_empty() {
-            continue;
+    println!("End statements with ';'. Tab completes keywords, tables and columns.");
+    println!("Data file: {} (engine: {}, persistence: {})", config.data_path().display(), config.storage.engine, config.storage.persistence);
+
+    let mut editor: Editor<SqlHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Cannot start line editor: {}", e);
+            return;
         }
-        
-        if input.eq_ignore_ascii_case("quit") || input.eq_ignore_ascii_case("exit") {
-            let storage_guard = storage.lock().unwrap();
-            autosave_database(&storage_guard);
-            println!("Final autosave completed. Goodbye!");
-            break;
+    };
+    editor.set_helper(Some(SqlHelper::new(Arc::clone(&storage))));
+    let history_path = config.history_path();
+    let _ = editor.load_history(&history_path);
+
+    let mut format = config.shell.format;
+    let mut cursors = Cursors::default();
+    let mut prepared = PreparedStatements::default();
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { "atomDB> " } else { "    ..> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            },
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                break;
+            }
+        };
+
+        if buffer.is_empty() {
+            let command = line.trim().trim_end_matches(';').trim();
+            if command.is_empty() {
+                continue;
+            }
+            if let Some(name) = command.strip_prefix("\\format") {
+                let _ = editor.add_history_entry(line.trim());
+                change_format(&mut format, name.trim());
+                continue;
+            }
+            if is_meta_command(command) {
+                let _ = editor.add_history_entry(line.trim());
+                if command.eq_ignore_ascii_case("quit") || command.eq_ignore_ascii_case("exit") {
+                    break;
+                }
+                run_meta_command(command, &storage, &config);
+                continue;
+            }
         }
-        
-        if input.eq_ignore_ascii_case("help") {
-            show_help();
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+
+        if buffer.len() > config.limits.max_query_length {
+            println!("Error: Query exceeds the maximum length of {} bytes", config.limits.max_query_length);
+            buffer.clear();
             continue;
         }
 
-        if input.eq_ignore_ascii_case("server") {
-            println!("Starting TCP server...");
-            let storage_clone = Arc::clone(&storage);
-            thread::spawn(move || {
-                start_tcp_server(storage_clone);
-            });
-            println!("TCP server started on port 4000. You can now connect via TCP.");
+        if !is_complete(&buffer) {
             continue;
         }
-        
-        if input.eq_ignore_ascii_case("load") {
-            handle_load_command(&storage);
-            continue;
+
+        let _ = editor.add_history_entry(buffer.trim());
+        for statement in split_statements(&buffer) {
+            execute_query(&storage, &config, &mut cursors, &mut prepared, &statement.text, format);
         }
-        
-        execute_query(&storage, input);
+        buffer.clear();
+    }
+
+    if let Err(e) = editor.save_history(&history_path) {
+        eprintln!("Cannot save history to {}: {}", history_path.display(), e);
+    }
+
+    let mut storage_guard = lock_storage(&storage);
+    if final_save_database(storage_guard.as_mut(), &config) {
+        println!("Final autosave completed.");
+    }
+    println!("Goodbye!");
+}
+
+fn change_format(format: &mut OutputFormat, name: &str) {
+    if name.is_empty() {
+        println!("Output format is {} (available: {})", format, FORMAT_NAMES);
+        return;
+    }
+    match name.parse() {
+        Ok(new_format) => {
+            *format = new_format;
+            println!("Output format is now {}", format);
+        },
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+fn is_meta_command(command: &str) -> bool {
+    ["quit", "exit", "help", "server", "load"].iter().any(|meta| command.eq_ignore_ascii_case(meta))
+}
+
+fn run_meta_command(command: &str, storage: &SharedStorage, config: &Arc<Config>) {
+    if command.eq_ignore_ascii_case("help") {
+        show_help();
+    } else if command.eq_ignore_ascii_case("server") {
+        println!("Starting TCP server...");
+        let storage_clone = Arc::clone(storage);
+        let config_clone = Arc::clone(config);
+        thread::spawn(move || {
+            start_tcp_server(storage_clone, config_clone);
+        });
+        println!("TCP server started on {}. You can now connect via TCP.", config.listen_address());
+    } else if command.eq_ignore_ascii_case("load") {
+        handle_load_command(storage, config);
     }
 }
 
 fn show_help() {
     println!(r#"
 Available Commands:
-  CREATE TABLE <name> (col1, col2, ...)  - Create a new table
+  CREATE TABLE <name> (col1 [type], ...) - Create a new table
+                                          (types: TEXT, INTEGER, FLOAT, BOOLEAN)
   INSERT INTO <table> (col1=val1, ...)   - Insert a row
   SELECT * FROM <table>                  - Select all rows
   SELECT * FROM <table> WHERE col=val    - Select with condition
+  SELECT * FROM <table> WHERE rowid > 10 ORDER BY rowid DESC
+                                        - Select a range of row IDs
+  DECLARE <cursor> CURSOR FOR SELECT ... - Open a cursor over a SELECT
+  FETCH [NEXT | ALL | <count>] FROM <cursor>
+                                        - Read the next rows of a cursor
+  CLOSE <cursor> | CLOSE ALL            - Close cursors
+  PREPARE <name> AS SELECT ... WHERE col = $1
+                                        - Prepare a SELECT or INSERT with parameters
+  EXECUTE <name>(value, ...)            - Run a prepared statement
+  DEALLOCATE <name> | DEALLOCATE ALL    - Drop prepared statements
   DESCRIBE <table>                       - Show table columns
   SHOW TABLES                           - List all tables
   DROP TABLE <table>                    - Delete a table
+  COPY <table> FROM 'file.csv' [WITH HEADER] [DELIMITER ','] [QUOTE '"']
+                                        - Load rows from a CSV file
+  COPY <table> TO 'file.csv' [WITH HEADER] - Write rows to a CSV file
+  COPY <table> FROM|TO 'file' JSON|NDJSON - Load or write rows as JSON
+  BACKUP TO 'file'                      - Write a consistent snapshot
   LOAD                                  - Load database from binary file
+  CREATE USER <name> PASSWORD 'pw'      - Add a server login
+  ALTER USER <name> PASSWORD 'pw'       - Change a user's password
+  DROP USER <name>                      - Remove a server login
+  CREATE ROLE <name> / DROP ROLE <name> - Manage roles
+  GRANT SELECT, INSERT ON <table> TO <user or role>
+                                        - Grant table privileges
+  GRANT ADMIN|<role> TO <user or role>  - Grant admin rights or a role
+  REVOKE ... FROM <user or role>        - Take back a grant
   SERVER                                - Start TCP server mode
-  quit/exit                             - Exit the CLI
+  quit/exit                             - Exit the CLI (or press Ctrl-D)
+  \format [name]                        - Show or set the result format
+                                          (table, ascii, csv, tsv, json, jsonl, vertical)
 
+Statements end with ';' and may span several lines; Ctrl-C discards a partial statement.
 Note: Database autosaves after every write operation
 "#);
 }
 
-fn handle_load_command(storage: &Arc<Mutex<StorageEngine>>) {
-    let result = StorageEngine::load_from_binary_file("database.bin");
+fn handle_load_command(storage: &SharedStorage, config: &Config) {
+    let result = load_database(config);
     match result {
         Ok(loaded_storage) => {
-            *storage.lock().unwrap() = loaded_storage;
+            *lock_storage(storage) = loaded_storage;
             println!("Database loaded successfully");
         },
         Err(e) => println!("Error loading: {}", e),
     }
 }
 
-fn execute_query(storage: &Arc<Mutex<StorageEngine>>, input: &str) {
-    let is_write_operation = input.to_lowercase().starts_with("create") || 
-                           input.to_lowercase().starts_with("insert") || 
-                           input.to_lowercase().starts_with("drop");
-    
-    let response = {
-        let mut storage_guard = storage.lock().unwrap();
-        let mut query_engine = QueryEngine::new(&mut storage_guard);
-        let result = query_engine.execute(input);
-        
-        if is_write_operation && result.is_ok() {
-            autosave_database(&storage_guard);
-            println!("Database autosaved");
-        }
-        
-        result
-    };
-    
-    match response {
-        Ok(result) => println!("{}", result),
+fn execute_query(storage: &SharedStorage, config: &Config, cursors: &mut Cursors, prepared: &mut PreparedStatements, input: &str, format: OutputFormat) {
+    let started = Instant::now();
+    match run_statement(storage, config, cursors, prepared, input) {
+        Ok((result, saved)) => {
+            let elapsed = started.elapsed();
+            if saved {
+                println!("Database autosaved");
+            }
+            println!("{}", render(&result, format, elapsed).trim_end());
+        },
         Err(err) => println!("Error: {}", err),
     }
 }
+
+/// Executes one statement, autosaving after successful writes. Returns the
+/// output together with whether the data file was saved.
+pub fn run_statement(storage: &SharedStorage, config: &Config, cursors: &mut Cursors, prepared: &mut PreparedStatements, input: &str) -> Result<(QueryResult, bool), String> {
+    let mut storage_guard = lock_storage(storage);
+    let is_write = QueryEngine::is_write_query(input) || prepared.is_write_query(input);
+    let result = execute_statement(storage_guard.as_mut(), config, cursors, prepared, input)?;
+
+    let saved = is_write && autosave_database(storage_guard.as_mut(), config);
+    Ok((result, saved))
+}
+
+/// Executes one statement without saving, enforcing read-only mode.
+/// `cursors` and `prepared` hold what the session has declared so far.
+pub fn execute_statement(storage: &mut dyn Storage, config: &Config, cursors: &mut Cursors, prepared: &mut PreparedStatements, input: &str) -> Result<QueryResult, String> {
+    if config.storage.read_only && (QueryEngine::is_write_query(input) || prepared.is_write_query(input)) {
+        return Err("Database is open in read-only mode".to_string());
+    }
+    QueryEngine::with_file_options(storage, config.file_options())
+        .with_deadline(config.query_deadline())
+        .with_cursors(cursors)
+        .with_prepared(prepared)
+        .execute_query(input)
+}
@@ -0,0 +1,423 @@
+use std::io::{IsTerminal, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::task::JoinError;
+use crate::config::{Config, EngineKind};
+use crate::database::{lock_storage, SharedStorage, Storage, StorageEngine, Users};
+use crate::database::copy::{export_csv, export_json, import_csv, import_json};
+use crate::database::dump::dump_sql;
+use crate::database::wal::{format_timestamp, now_millis, parse_timestamp, read_history, read_log, replay, segments, with_suffix};
+use crate::query::{split_statements, Cursors, PreparedStatements, QueryEngine};
+use crate::query::migrations;
+use crate::server::{serve_resp, serve_tcp, LoginThrottle};
+use crate::server::listener::runtime;
+use crate::server::shutdown::{wait_for_signal, Shutdown};
+use crate::server::tls::load_tls_config;
+use crate::utils::persistence::{file_size, final_save_database, flush_write_log, open_database, read_snapshot, save_database};
+use super::args::{DumpArgs, ExecArgs, ExportArgs, FileFormat, ImportArgs, MigrateAction, MigrateArgs, RecoverArgs, RestoreArgs, ServeArgs};
+use super::cli_interface::{execute_statement, run_cli};
+use super::format::render;
+
+pub fn serve(config: Arc<Config>, args: &ServeArgs) -> Result<(), String> {
+    // Report unusable TLS files before starting anything.
+    load_tls_config(&config.server)?;
+    let mut database = open_database(&config, true)?;
+    if !Users::new(database.as_mut()).is_enabled()? {
+        println!("Warning: no user accounts exist, so clients connect without logging in. \
+            Create one with CREATE USER <name> PASSWORD '<password>'.");
+    }
+    let storage = Arc::new(Mutex::new(database));
+    let runtime = runtime()?;
+
+    if args.shell {
+        println!("Starting both CLI and TCP server...");
+        let storage_for_server = Arc::clone(&storage);
+        let config_for_server = Arc::clone(&config);
+        thread::spawn(move || {
+            match serve_until_signal(runtime, &storage_for_server, &config_for_server) {
+                Ok(()) => std::process::exit(0),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        });
+        thread::sleep(Duration::from_millis(100));
+        run_cli(storage, config);
+    } else {
+        println!("Starting TCP server mode only...");
+        serve_until_signal(runtime, &storage, &config)?;
+    }
+    Ok(())
+}
+
+/// Runs both servers until SIGINT or SIGTERM, lets them answer what their
+/// clients already sent, then saves the database. A second signal stops
+/// waiting for clients.
+fn serve_until_signal(runtime: Runtime, storage: &SharedStorage, config: &Arc<Config>) -> Result<(), String> {
+    let shutdown = Shutdown::new();
+    // Failed logins count across both servers.
+    let logins = LoginThrottle::new();
+    let result = runtime.block_on(async {
+        let mut resp = tokio::spawn(serve_resp(Arc::clone(storage), Arc::clone(config), shutdown.clone(), logins.clone()));
+        let mut tcp = tokio::spawn(serve_tcp(Arc::clone(storage), Arc::clone(config), shutdown.clone(), logins));
+        let signal = tokio::select! {
+            signal = wait_for_signal() => signal.map_err(|e| format!("Cannot listen for signals: {}", e))?,
+            result = &mut resp => return server_result(result),
+            result = &mut tcp => return server_result(result),
+        };
+        println!("Received {}; shutting down", signal);
+        shutdown.trigger();
+        tokio::select! {
+            (resp, tcp) = async { tokio::join!(resp, tcp) } => server_result(resp).and(server_result(tcp)),
+            _ = wait_for_signal() => {
+                eprintln!("Received a second signal; closing open connections");
+                Ok(())
+            },
+        }
+    });
+    // Statements still running hold the lock, so saving waits for them.
+    runtime.shutdown_background();
+    let mut storage_guard = lock_storage(storage);
+    if final_save_database(storage_guard.as_mut(), config) {
+        println!("Final autosave completed.");
+    }
+    println!("Server stopped");
+    result
+}
+
+fn server_result(result: Result<Result<(), String>, JoinError>) -> Result<(), String> {
+    result.map_err(|e| e.to_string())?
+}
+
+pub fn shell(config: Arc<Config>) -> Result<(), String> {
+    let storage = Arc::new(Mutex::new(open_database(&config, true)?));
+    run_cli(storage, config);
+    Ok(())
+}
+
+/// Runs every statement of the script, saving once at the end rather than
+/// after each write.
+pub fn exec(config: Arc<Config>, args: &ExecArgs) -> Result<(), String> {
+    let script = read_script(args)?;
+    let statements = split_statements(&script);
+    if statements.is_empty() {
+        return Err("No statements to execute".to_string());
+    }
+
+    let mut storage = open_database(&config, false)?;
+    let format = args.format.unwrap_or(config.shell.format);
+    let (mut succeeded, mut failed, mut wrote) = (0, 0, false);
+    let mut cursors = Cursors::default();
+    let mut prepared = PreparedStatements::default();
+
+    for (index, statement) in statements.iter().enumerate() {
+        let started = Instant::now();
+        let is_write = QueryEngine::is_write_query(&statement.text) || prepared.is_write_query(&statement.text);
+        match execute_statement(storage.as_mut(), &config, &mut cursors, &mut prepared, &statement.text) {
+            Ok(result) => {
+                println!("{}", render(&result, format, started.elapsed()).trim_end());
+                succeeded += 1;
+                wrote |= is_write;
+            },
+            Err(e) => {
+                eprintln!("Error in statement {} (line {}): {}", index + 1, statement.line, e);
+                failed += 1;
+                if !args.continue_on_error {
+                    break;
+                }
+            }
+        }
+    }
+
+    // The script is the whole session, so this is its save on exit as well.
+    if wrote {
+        if let Err(e) = flush_write_log(storage.as_mut(), &config) {
+            eprintln!("Write log failed: {}", e);
+        }
+        final_save_database(storage.as_mut(), &config);
+    }
+
+    let skipped = statements.len() - succeeded - failed;
+    if args.sql.is_none() || statements.len() > 1 {
+        eprintln!("{} statement(s): {} succeeded, {} failed, {} skipped", statements.len(), succeeded, failed, skipped);
+    }
+
+    if failed > 0 {
+        return Err(format!("{} statement(s) failed", failed));
+    }
+    Ok(())
+}
+
+fn read_script(args: &ExecArgs) -> Result<String, String> {
+    let read_stdin = || {
+        let mut script = String::new();
+        std::io::stdin().read_to_string(&mut script).map_err(|e| format!("Cannot read stdin: {}", e))?;
+        Ok(script)
+    };
+
+    match (&args.sql, &args.file) {
+        (Some(sql), _) => Ok(sql.clone()),
+        (None, Some(path)) if path.as_os_str() == "-" => read_stdin(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read '{}': {}", path.display(), e)),
+        (None, None) if !std::io::stdin().is_terminal() => read_stdin(),
+        (None, None) => Err("Nothing to execute: pass a statement, --file, or pipe statements on stdin".to_string()),
+    }
+}
+
+pub fn import(config: Arc<Config>, args: &ImportArgs) -> Result<(), String> {
+    let imported = match args.format {
+        FileFormat::Json if args.table_args.table.is_none() => StorageEngine::load_from_file(&args.file),
+        FileFormat::Binary => read_snapshot(&args.file, config.key_ring()).map_err(Into::into),
+        FileFormat::Json | FileFormat::Csv | FileFormat::Ndjson => return import_table_file(&config, args),
+    }.map_err(|e| format!("Cannot read '{}': {}", args.file.display(), e))?;
+
+    let mut storage = open_database(&config, false)?;
+    let tables = storage.merge(&imported, args.replace)?;
+    save_database(storage.as_mut(), &config)?;
+
+    println!("Imported {} table(s) into {}: {}", tables.len(), config.data_path().display(), tables.join(", "));
+    Ok(())
+}
+
+/// Loads rows for one table in a single pass and saves once, whatever the
+/// persistence mode.
+fn import_table_file(config: &Config, args: &ImportArgs) -> Result<(), String> {
+    let table = args.table_args.table()?;
+    let input = std::fs::read_to_string(&args.file)
+        .map_err(|e| format!("Cannot read '{}': {}", args.file.display(), e))?;
+
+    let mut storage = open_database(config, false)?;
+    let report = match args.format {
+        FileFormat::Csv => import_csv(storage.as_mut(), table, &input, &args.table_args.options())?,
+        _ => import_json(storage.as_mut(), table, &input)?,
+    };
+    if report.imported > 0 {
+        save_database(storage.as_mut(), config)?;
+    }
+
+    println!("{}", report.summary(table));
+    Ok(())
+}
+
+pub fn export(config: Arc<Config>, args: &ExportArgs) -> Result<(), String> {
+    let storage = open_existing(&config)?;
+
+    let (data, exported) = match args.format {
+        FileFormat::Json if args.table_args.table.is_none() => {
+            (storage.to_json()?.into_bytes(), None)
+        },
+        FileFormat::Binary => {
+            let options = config.file_options();
+            (options.keys.seal(storage.to_binary(options.compression)?)?, None)
+        },
+        FileFormat::Json | FileFormat::Csv | FileFormat::Ndjson => {
+            let table = args.table_args.table()?;
+            let (text, rows) = match args.format {
+                FileFormat::Csv => export_csv(storage.as_ref(), table, &args.table_args.options())?,
+                format => export_json(storage.as_ref(), table, format == FileFormat::Ndjson)?,
+            };
+            (text.into_bytes(), Some(format!("{} row(s) of '{}'", rows, table)))
+        }
+    };
+
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, &data).map_err(|e| format!("Cannot write '{}': {}", path.display(), e))?;
+            let what = exported.unwrap_or_else(|| format!("{} table(s)", storage.list_tables().len()));
+            eprintln!("Exported {} to {}", what, path.display());
+        },
+        None if args.format == FileFormat::Binary => {
+            return Err("Binary export needs --output".to_string());
+        },
+        None if exported.is_some() => {
+            std::io::stdout().write_all(&data).map_err(|e| e.to_string())?;
+        },
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&data).and_then(|_| writeln!(stdout)).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn dump(config: Arc<Config>, args: &DumpArgs) -> Result<(), String> {
+    let storage = open_existing(&config)?;
+    let script = dump_sql(storage.as_ref(), &args.tables, args.batch_size)?;
+
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, &script).map_err(|e| format!("Cannot write '{}': {}", path.display(), e))?;
+            let tables = if args.tables.is_empty() { storage.list_tables().len() } else { args.tables.len() };
+            eprintln!("Dumped {} table(s) to {}", tables, path.display());
+        },
+        None => print!("{}", script),
+    }
+    Ok(())
+}
+
+/// Replays the dump into an empty database first and merges it only if every
+/// statement succeeded, so a broken dump leaves the data file untouched.
+pub fn restore(config: Arc<Config>, args: &RestoreArgs) -> Result<(), String> {
+    let script = if args.file.as_os_str() == "-" {
+        let mut script = String::new();
+        std::io::stdin().read_to_string(&mut script).map_err(|e| format!("Cannot read stdin: {}", e))?;
+        script
+    } else {
+        std::fs::read_to_string(&args.file).map_err(|e| format!("Cannot read '{}': {}", args.file.display(), e))?
+    };
+
+    let mut restored = StorageEngine::new();
+    restored.set_limits(config.storage_limits());
+    let statements = split_statements(&script);
+    for (index, statement) in statements.iter().enumerate() {
+        QueryEngine::new(&mut restored).execute_query(&statement.text)
+            .map_err(|e| format!("Statement {} (line {}): {}", index + 1, statement.line, e))?;
+    }
+
+    let mut storage = open_database(&config, false)?;
+    let tables = storage.merge(&restored, args.replace)?;
+    save_database(storage.as_mut(), &config)?;
+
+    println!("Restored {} table(s) from {} statement(s) into {}: {}",
+        tables.len(), statements.len(), config.data_path().display(), tables.join(", "));
+    Ok(())
+}
+
+/// Loads a backup and replays the write log on top of it, stopping at the
+/// requested LSN or time, then writes the result as the data file.
+pub fn recover(config: Arc<Config>, args: &RecoverArgs) -> Result<(), String> {
+    if config.storage.engine != EngineKind::Memory {
+        return Err("atom recover restores a data file and needs engine = \"memory\"".to_string());
+    }
+    let until_time = args.until.as_deref().map(parse_timestamp).transpose()?;
+    let path = config.data_path();
+    if path.exists() && !args.force {
+        return Err(format!("Data file '{}' exists; pass --force to overwrite it", path.display()));
+    }
+
+    let keys = config.key_ring();
+    let mut storage = read_snapshot(&args.backup, keys)
+        .map_err(|e| format!("Cannot load '{}': {}", args.backup.display(), e))?;
+    let base = storage.lsn();
+    if let Some(lsn) = args.until_lsn.filter(|lsn| *lsn < base) {
+        return Err(format!("The backup is already at LSN {}, past the requested LSN {}", base, lsn));
+    }
+
+    let log_path = args.log.clone().unwrap_or_else(|| config.write_log_path());
+    let records = read_history(&log_path, keys)?;
+    let last_time = replay(&mut storage, &records, args.until_lsn, until_time)?;
+
+    // Changes beyond the recovery point would be replayed when the data file
+    // is next opened, or read by a later recovery, so the configured log and
+    // any segments holding them are set aside rather than reused.
+    let config_log = config.write_log_path();
+    let mut set_aside: Vec<_> = segments(&config_log)?.into_iter()
+        .filter(|(_, last, _)| *last > storage.lsn())
+        .map(|(_, _, segment)| segment)
+        .collect();
+    if read_log(&config_log, keys)?.iter().any(|record| record.lsn > storage.lsn()) {
+        set_aside.push(config_log);
+    }
+    let seconds = (now_millis() / 1000).to_string();
+    for log in set_aside {
+        let archived = with_suffix(&log, &seconds);
+        std::fs::rename(&log, &archived).map_err(|e| format!("Cannot move '{}': {}", log.display(), e))?;
+        eprintln!("Moved the write log to {}", archived.display());
+    }
+
+    storage.set_limits(config.storage_limits());
+    save_database(&mut storage, &config)?;
+
+    let replayed = storage.lsn() - base;
+    let last = last_time.map(|time| format!(", last change at {}", format_timestamp(time))).unwrap_or_default();
+    println!("Recovered {} to LSN {}: {} change(s) replayed from {}{}",
+        path.display(), storage.lsn(), replayed, log_path.display(), last);
+    Ok(())
+}
+
+/// Applies or reverts migrations and saves once. A failing migration is
+/// rolled back, but the ones before it stay applied and are saved.
+pub fn migrate(config: Arc<Config>, args: &MigrateArgs) -> Result<(), String> {
+    let available = migrations::discover(&args.dir)?;
+    let mut storage = open_database(&config, false)?;
+
+    let (run, verb) = match args.action {
+        MigrateAction::Up { to } => (migrations::migrate_up(storage.as_mut(), &available, to), "Applied"),
+        MigrateAction::Down { steps } => (migrations::migrate_down(storage.as_mut(), &available, steps), "Reverted"),
+        MigrateAction::Status => return migration_status(storage.as_ref(), &available),
+    };
+    for label in &run.done {
+        println!("{} {}", verb, label);
+    }
+    if !run.done.is_empty() {
+        save_database(storage.as_mut(), &config)?;
+    }
+
+    match run.error {
+        Some(e) => Err(e),
+        None if run.done.is_empty() => {
+            println!("Nothing to do");
+            Ok(())
+        },
+        None => Ok(()),
+    }
+}
+
+fn migration_status(storage: &dyn Storage, available: &[migrations::Migration]) -> Result<(), String> {
+    let applied = migrations::applied(storage)?;
+    for migration in available {
+        let state = applied.iter().find(|a| a.version == migration.version)
+            .map_or("pending".to_string(), |a| format!("applied {}", a.applied_at));
+        println!("{:<40} {}", migration.label(), state);
+    }
+    for missing in applied.iter().filter(|a| !available.iter().any(|m| m.version == a.version)) {
+        println!("{:<40} applied {} (file missing)", format!("{:04}_{}", missing.version, missing.name), missing.applied_at);
+    }
+    let pending = available.iter().filter(|m| !applied.iter().any(|a| a.version == m.version)).count();
+    println!("{} migration(s), {} applied, {} pending", available.len(), applied.len(), pending);
+    Ok(())
+}
+
+pub fn check(config: Arc<Config>) -> Result<(), String> {
+    let storage = open_existing(&config)?;
+
+    let mut tables = storage.list_tables();
+    tables.sort();
+    println!("{}: {} table(s)", config.data_path().display(), tables.len());
+    for name in tables {
+        let rows = storage.select_all(name)?.len();
+        println!("  {} ({} rows)", name, rows);
+    }
+
+    let problems = storage.check();
+    if problems.is_empty() {
+        println!("OK");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    Err(format!("{} problem(s) found", problems.len()))
+}
+
+pub fn compact(config: Arc<Config>) -> Result<(), String> {
+    let mut storage = open_existing(&config)?;
+    let path = config.data_path();
+    let before = file_size(&path);
+
+    let purged = storage.compact()?;
+    save_database(storage.as_mut(), &config)?;
+
+    let after = file_size(&path);
+    println!("Compacted {}: {} -> {} bytes ({} expired key row(s) removed)", path.display(), before, after, purged);
+    Ok(())
+}
+
+fn open_existing(config: &Config) -> Result<Box<dyn Storage>, String> {
+    let path = config.data_path();
+    if !path.exists() {
+        return Err(format!("Data file '{}' does not exist", path.display()));
+    }
+    open_database(config, false)
+}
@@ -0,0 +1,94 @@
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use crate::database::SharedStorage;
+
+const KEYWORDS: &[&str] = &[
+    "CREATE", "TABLE", "INSERT", "INTO", "SELECT", "FROM", "WHERE", "ORDER", "BY", "ASC", "DESC", "BETWEEN", "AND", "ROWID", "DESCRIBE",
+    "SHOW", "TABLES", "DROP", "COPY", "TO", "WITH", "HEADER", "DELIMITER",
+    "QUOTE", "CSV", "JSON", "NDJSON", "TEXT", "INTEGER", "FLOAT", "BOOLEAN", "BACKUP", "LOAD", "USER", "ALTER", "PASSWORD", "ROLE", "GRANT", "REVOKE", "ON", "DECLARE", "CURSOR", "FOR", "FETCH", "NEXT", "CLOSE", "PREPARE", "AS", "EXECUTE", "DEALLOCATE", "ALL", "PRIVILEGES", "ADMIN", "SERVER", "HELP", "QUIT", "EXIT",
+];
+
+/// Completes keywords, table names and column names for the shell.
+pub struct SqlHelper {
+    storage: SharedStorage,
+}
+
+impl SqlHelper {
+    pub fn new(storage: SharedStorage) -> Self {
+        SqlHelper { storage }
+    }
+
+    fn candidates(&self, line: &str, prefix: &str) -> Vec<String> {
+        let lower_prefix = prefix.to_lowercase();
+        let lowercase_keywords = !prefix.is_empty() && prefix.chars().all(|c| !c.is_uppercase());
+
+        let mut candidates: Vec<String> = KEYWORDS.iter()
+            .filter(|k| k.to_lowercase().starts_with(&lower_prefix))
+            .map(|k| if lowercase_keywords { k.to_lowercase() } else { k.to_string() })
+            .collect();
+
+        let storage = match self.storage.lock() {
+            Ok(storage) => storage,
+            Err(_) => return candidates,
+        };
+
+        let tables = storage.list_tables();
+        let words: Vec<String> = line.split(|c: char| !c.is_alphanumeric() && c != '_')
+            .map(|w| w.to_lowercase())
+            .collect();
+        let mentioned: Vec<&&String> = tables.iter().filter(|t| words.contains(t)).collect();
+
+        for table in &tables {
+            if table.starts_with(&lower_prefix) {
+                candidates.push(table.to_string());
+            }
+        }
+
+        let column_sources: Vec<&&String> = if mentioned.is_empty() { tables.iter().collect() } else { mentioned };
+        for table in column_sources {
+            if let Ok(columns) = storage.describe_table(table) {
+                for column in columns {
+                    if column.starts_with(&lower_prefix) && !candidates.contains(column) {
+                        candidates.push(column.clone());
+                    }
+                }
+            }
+        }
+
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl Completer for SqlHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !c.is_alphanumeric() && *c != '_')
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let prefix = &line[start..pos];
+
+        let pairs = self.candidates(line, prefix)
+            .into_iter()
+            .map(|c| Pair { display: c.clone(), replacement: c })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for SqlHelper {
+    type Hint = String;
+}
+
+impl Highlighter for SqlHelper {}
+
+impl Validator for SqlHelper {}
+
+impl Helper for SqlHelper {}
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::query::QueryResult;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Aligned table with Unicode borders
+    #[default]
+    Table,
+    /// Aligned table with ASCII borders
+    Ascii,
+    Csv,
+    Tsv,
+    /// A JSON array of objects
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// One field per line, one block per row
+    Vertical,
+}
+
+pub const FORMAT_NAMES: &str = "table, ascii, csv, tsv, json, jsonl, vertical";
+
+struct Border {
+    horizontal: char,
+    vertical: char,
+    top: [char; 3],
+    middle: [char; 3],
+    bottom: [char; 3],
+}
+
+const UNICODE_BORDER: Border = Border {
+    horizontal: '─',
+    vertical: '│',
+    top: ['┌', '┬', '┐'],
+    middle: ['├', '┼', '┤'],
+    bottom: ['└', '┴', '┘'],
+};
+
+const ASCII_BORDER: Border = Border {
+    horizontal: '-',
+    vertical: '|',
+    top: ['+', '+', '+'],
+    middle: ['+', '+', '+'],
+    bottom: ['+', '+', '+'],
+};
+
+impl OutputFormat {
+    /// Human-oriented formats get a row count and timing footer.
+    fn has_footer(self) -> bool {
+        matches!(self, OutputFormat::Table | OutputFormat::Ascii | OutputFormat::Vertical)
+    }
+}
+
+/// Renders a query result; messages are returned unchanged.
+pub fn render(result: &QueryResult, format: OutputFormat, elapsed: Duration) -> String {
+    let columns = match result {
+        QueryResult::Rows { columns, .. } => columns,
+        QueryResult::Message(message) => return message.clone(),
+    };
+    let values = result.values();
+
+    let mut out = match format {
+        OutputFormat::Table => render_table(columns, &values, &UNICODE_BORDER),
+        OutputFormat::Ascii => render_table(columns, &values, &ASCII_BORDER),
+        OutputFormat::Csv => render_delimited(columns, &values, ','),
+        OutputFormat::Tsv => render_delimited(columns, &values, '\t'),
+        OutputFormat::Json => render_json(columns, &values),
+        OutputFormat::Jsonl => render_json_lines(columns, &values),
+        OutputFormat::Vertical => render_vertical(columns, &values),
+    };
+
+    if format.has_footer() {
+        let noun = if values.len() == 1 { "row" } else { "rows" };
+        out.push_str(&format!("({} {}, {:.3} ms)\n", values.len(), noun, elapsed.as_secs_f64() * 1000.0));
+    }
+    out
+}
+
+fn render_table(columns: &[String], values: &[Vec<String>], border: &Border) -> String {
+    let cells: Vec<Vec<String>> = values.iter()
+        .map(|row| row.iter().map(|v| escape_control(v)).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+    for row in &cells {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let rule = |[left, middle, right]: [char; 3]| {
+        let segments: Vec<String> = widths.iter()
+            .map(|w| border.horizontal.to_string().repeat(w + 2))
+            .collect();
+        format!("{}{}{}\n", left, segments.join(&middle.to_string()), right)
+    };
+    let line = |row: &[String]| {
+        let padded: Vec<String> = row.iter().zip(&widths)
+            .map(|(value, width)| format!(" {}{} ", value, " ".repeat(width - value.chars().count())))
+            .collect();
+        format!("{}{}{}\n", border.vertical, padded.join(&border.vertical.to_string()), border.vertical)
+    };
+
+    let mut out = rule(border.top);
+    out.push_str(&line(columns));
+    out.push_str(&rule(border.middle));
+    for row in &cells {
+        out.push_str(&line(row));
+    }
+    out.push_str(&rule(border.bottom));
+    out
+}
+
+fn render_delimited(columns: &[String], values: &[Vec<String>], delimiter: char) -> String {
+    let field = |value: &str| {
+        if delimiter == '\t' {
+            escape_control(value)
+        } else if value.contains([delimiter, '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+
+    let mut out = String::new();
+    for row in std::iter::once(columns).chain(values.iter().map(Vec::as_slice)) {
+        let fields: Vec<String> = row.iter().map(|v| field(v)).collect();
+        out.push_str(&fields.join(&delimiter.to_string()));
+        out.push('\n');
+    }
+    out
+}
+
+/// One row as a JSON object with its columns in the order given.
+pub fn json_object(columns: &[String], row: &[String]) -> String {
+    let fields: Vec<String> = columns.iter().zip(row)
+        .map(|(column, value)| format!("{}: {}", json_string(column), json_string(value)))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn render_json(columns: &[String], values: &[Vec<String>]) -> String {
+    if values.is_empty() {
+        return "[]\n".to_string();
+    }
+    let objects: Vec<String> = values.iter()
+        .map(|row| format!("  {}", json_object(columns, row)))
+        .collect();
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+fn render_json_lines(columns: &[String], values: &[Vec<String>]) -> String {
+    values.iter()
+        .map(|row| format!("{}\n", json_object(columns, row)))
+        .collect()
+}
+
+fn render_vertical(columns: &[String], values: &[Vec<String>]) -> String {
+    let width = columns.iter().map(|c| c.chars().count()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (index, row) in values.iter().enumerate() {
+        out.push_str(&format!("-[ RECORD {} ]{}\n", index + 1, "-".repeat(width.max(4))));
+        for (column, value) in columns.iter().zip(row) {
+            let pad = " ".repeat(width - column.chars().count());
+            out.push_str(&format!("{}{} | {}\n", column, pad, escape_control(value)));
+        }
+    }
+    out
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string())
+}
+
+fn escape_control(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t")
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" | "unicode" => Ok(OutputFormat::Table),
+            "ascii" => Ok(OutputFormat::Ascii),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" | "json-lines" => Ok(OutputFormat::Jsonl),
+            "vertical" => Ok(OutputFormat::Vertical),
+            _ => Err(format!("Unknown format '{}' (expected one of: {})", s, FORMAT_NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Table => "table",
+            OutputFormat::Ascii => "ascii",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Vertical => "vertical",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -1,3 +1,8 @@
+pub mod args;
 pub mod cli_interface;
+pub mod commands;
+pub mod completion;
+pub mod format;
 
+pub use args::Cli;
 pub use cli_interface::run_cli;
@@ -0,0 +1,3 @@
+pub mod settings;
+
+pub use settings::{Config, ConfigOverrides, EngineKind, PersistenceMode};
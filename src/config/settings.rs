@@ -0,0 +1,574 @@
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::cli::format::OutputFormat;
+use crate::database::{Compression, Deadline, FileOptions, KeyRing};
+use crate::database::encryption::EncryptionKey;
+use crate::database::engine::StorageLimits;
+use crate::database::wal::log_path;
+
+pub const DEFAULT_CONFIG_FILE: &str = "atom.toml";
+
+/// Looks up an environment variable.
+type Vars = dyn Fn(&str) -> Option<String>;
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub limits: LimitsConfig,
+    pub shell: ShellConfig,
+    /// Encryption keys, read by `load` and again only by `reload_keys`, so
+    /// that saving and logging never go back to the key files.
+    #[serde(skip)]
+    pub keys: KeyRing,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    pub resp_port: u16,
+    /// PEM certificate chain; with `tls_key_file` it turns on TLS for both
+    /// servers.
+    pub tls_cert_file: Option<PathBuf>,
+    /// PEM private key for `tls_cert_file`.
+    pub tls_key_file: Option<PathBuf>,
+    /// PEM CA certificates; when set, clients must present a certificate
+    /// signed by one of them.
+    pub tls_client_ca_file: Option<PathBuf>,
+    /// Connections each server handles at once; more are turned away.
+    pub max_connections: usize,
+    /// Seconds a client may stay silent before it is disconnected. Zero
+    /// means never.
+    pub idle_timeout_secs: u64,
+    /// Seconds a shutdown waits for open connections to finish before
+    /// closing them.
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub data_dir: PathBuf,
+    pub data_file: String,
+    pub engine: EngineKind,
+    pub persistence: PersistenceMode,
+    /// Reject write statements and never save the data file.
+    pub read_only: bool,
+    /// Append every change to a write log next to the data file, for crash
+    /// recovery and point-in-time restore.
+    pub write_log: bool,
+    /// Pages of 4 KiB the paged engine keeps in memory.
+    pub buffer_pool_pages: usize,
+    /// Codec for data files, backups and write log blocks.
+    pub compression: Compression,
+    /// File holding the key (64 hex digits) that data files, backups and
+    /// write log blocks are encrypted with. Unset means no encryption.
+    pub encryption_key_file: Option<PathBuf>,
+    /// The key in use before a rotation; files sealed with it can still be
+    /// read and are encrypted with the new key when next written.
+    pub previous_encryption_key_file: Option<PathBuf>,
+    /// Read data files, backups and write logs in the clear even though a
+    /// key is configured, and encrypt them. Meant for one start after
+    /// encryption is enabled; otherwise unencrypted files are refused.
+    pub migrate_unencrypted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Longest statement accepted from the CLI or a TCP client, in bytes.
+    pub max_query_length: usize,
+    /// Zero means unlimited.
+    pub max_tables: usize,
+    /// Zero means unlimited.
+    pub max_rows_per_table: usize,
+    /// Milliseconds a statement may run before its scan is aborted. Zero
+    /// means unlimited.
+    pub query_timeout_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShellConfig {
+    /// Defaults to `~/.atom_history`.
+    pub history_file: Option<PathBuf>,
+    /// Initial result format, changed with `\format`.
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineKind {
+    /// Every table in memory, saved as one data file.
+    Memory,
+    /// Tables in page files read through a buffer pool, for data larger
+    /// than memory.
+    Paged,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersistenceMode {
+    /// Save the data file after every write operation.
+    Autosave,
+    /// Only save when the CLI exits.
+    OnExit,
+    /// Never touch the data file.
+    Memory,
+}
+
+/// Values given on the command line; they take precedence over the
+/// environment, which takes precedence over the config file.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    pub config_path: Option<PathBuf>,
+    pub bind_address: Option<String>,
+    pub port: Option<u16>,
+    pub resp_port: Option<u16>,
+    pub data_dir: Option<PathBuf>,
+    pub data_file: Option<String>,
+    pub engine: Option<EngineKind>,
+    pub persistence: Option<PersistenceMode>,
+    pub read_only: Option<bool>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "0.0.0.0".to_string(),
+            port: 6969,
+            resp_port: 6379,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_client_ca_file: None,
+            max_connections: 10000,
+            idle_timeout_secs: 300,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            data_dir: PathBuf::from("."),
+            data_file: "database.bin".to_string(),
+            engine: EngineKind::Memory,
+            persistence: PersistenceMode::Autosave,
+            read_only: false,
+            write_log: false,
+            buffer_pool_pages: 256,
+            compression: Compression::None,
+            encryption_key_file: None,
+            previous_encryption_key_file: None,
+            migrate_unencrypted: false,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_query_length: 64 * 1024,
+            max_tables: 0,
+            max_rows_per_table: 0,
+            query_timeout_ms: 30_000,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the effective configuration: defaults, then the config file
+    /// (`--config`, `ATOM_CONFIG` or `./atom.toml`), then `ATOM_*`
+    /// environment variables, then command-line overrides.
+    pub fn load(overrides: &ConfigOverrides) -> Result<Self, String> {
+        Self::load_with(overrides, &|name| env::var(name).ok())
+    }
+
+    /// `load`, looking up environment variables through `vars`.
+    fn load_with(overrides: &ConfigOverrides, vars: &Vars) -> Result<Self, String> {
+        let config_path = overrides.config_path.clone()
+            .or_else(|| vars("ATOM_CONFIG").map(PathBuf::from));
+
+        let mut config = match config_path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(DEFAULT_CONFIG_FILE)?,
+            None => Self::default(),
+        };
+
+        config.apply_env(vars)?;
+        config.apply_overrides(overrides);
+        config.reload_keys()?;
+        Ok(config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config file '{}': {}", path.display(), e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file '{}': {}", path.display(), e))
+    }
+
+    /// The paged engine keeps a directory in place of the data file:
+    /// `database.bin` becomes `database.pages/`.
+    pub fn data_path(&self) -> PathBuf {
+        let path = self.storage.data_dir.join(&self.storage.data_file);
+        match self.storage.engine {
+            EngineKind::Memory => path,
+            EngineKind::Paged => path.with_extension("pages"),
+        }
+    }
+
+    pub fn write_log_path(&self) -> PathBuf {
+        log_path(&self.data_path())
+    }
+
+    /// Reads the encryption keys again, as after a rotation. `ATOM_ENCRYPTION_KEY`
+    /// and `ATOM_PREVIOUS_ENCRYPTION_KEY` hold keys directly and take
+    /// precedence over the key files.
+    pub fn reload_keys(&mut self) -> Result<(), String> {
+        let current = read_key("ATOM_ENCRYPTION_KEY", self.storage.encryption_key_file.as_deref())?;
+        let previous = read_key("ATOM_PREVIOUS_ENCRYPTION_KEY", self.storage.previous_encryption_key_file.as_deref())?;
+        self.keys = KeyRing::new(current, previous.into_iter().collect()).migrating(self.storage.migrate_unencrypted);
+        Ok(())
+    }
+
+    pub fn key_ring(&self) -> &KeyRing {
+        &self.keys
+    }
+
+    pub fn file_options(&self) -> FileOptions {
+        FileOptions {
+            compression: self.storage.compression,
+            keys: self.keys.clone(),
+        }
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        if let Some(path) = &self.shell.history_file {
+            return path.clone();
+        }
+        match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".atom_history"),
+            None => self.storage.data_dir.join(".atom_history"),
+        }
+    }
+
+    pub fn listen_address(&self) -> String {
+        format!("{}:{}", self.server.bind_address, self.server.port)
+    }
+
+    pub fn resp_listen_address(&self) -> String {
+        format!("{}:{}", self.server.bind_address, self.server.resp_port)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.server.idle_timeout_secs)).filter(|timeout| !timeout.is_zero())
+    }
+
+    /// The deadline for a statement starting now.
+    pub fn query_deadline(&self) -> Deadline {
+        Deadline::after(Duration::from_millis(self.limits.query_timeout_ms))
+    }
+
+    pub fn storage_limits(&self) -> StorageLimits {
+        StorageLimits {
+            max_tables: self.limits.max_tables,
+            max_rows_per_table: self.limits.max_rows_per_table,
+        }
+    }
+
+    fn apply_env(&mut self, vars: &Vars) -> Result<(), String> {
+        // DATABASE_PATH predates the ATOM_* variables and names the full file path.
+        if let Some(path) = env_var::<PathBuf>(vars, "DATABASE_PATH")? {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            self.storage.data_dir = dir.map_or_else(|| PathBuf::from("."), |dir| dir.to_path_buf());
+            if let Some(file) = path.file_name() {
+                self.storage.data_file = file.to_string_lossy().into_owned();
+            }
+        }
+
+        if let Some(value) = env_var(vars, "ATOM_BIND_ADDRESS")? {
+            self.server.bind_address = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_PORT")? {
+            self.server.port = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_RESP_PORT")? {
+            self.server.resp_port = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_TLS_CERT_FILE")? {
+            self.server.tls_cert_file = Some(value);
+        }
+        if let Some(value) = env_var(vars, "ATOM_TLS_KEY_FILE")? {
+            self.server.tls_key_file = Some(value);
+        }
+        if let Some(value) = env_var(vars, "ATOM_TLS_CLIENT_CA_FILE")? {
+            self.server.tls_client_ca_file = Some(value);
+        }
+        if let Some(value) = env_var(vars, "ATOM_MAX_CONNECTIONS")? {
+            self.server.max_connections = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_IDLE_TIMEOUT_SECS")? {
+            self.server.idle_timeout_secs = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_SHUTDOWN_TIMEOUT_SECS")? {
+            self.server.shutdown_timeout_secs = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_DATA_DIR")? {
+            self.storage.data_dir = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_DATA_FILE")? {
+            self.storage.data_file = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_ENGINE")? {
+            self.storage.engine = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_PERSISTENCE")? {
+            self.storage.persistence = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_READ_ONLY")? {
+            self.storage.read_only = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_WRITE_LOG")? {
+            self.storage.write_log = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_BUFFER_POOL_PAGES")? {
+            self.storage.buffer_pool_pages = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_COMPRESSION")? {
+            self.storage.compression = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_ENCRYPTION_KEY_FILE")? {
+            self.storage.encryption_key_file = Some(value);
+        }
+        if let Some(value) = env_var(vars, "ATOM_PREVIOUS_ENCRYPTION_KEY_FILE")? {
+            self.storage.previous_encryption_key_file = Some(value);
+        }
+        if let Some(value) = env_var(vars, "ATOM_MIGRATE_UNENCRYPTED")? {
+            self.storage.migrate_unencrypted = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_HISTORY_FILE")? {
+            self.shell.history_file = Some(value);
+        }
+        if let Some(value) = env_var(vars, "ATOM_FORMAT")? {
+            self.shell.format = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_MAX_QUERY_LENGTH")? {
+            self.limits.max_query_length = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_MAX_TABLES")? {
+            self.limits.max_tables = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_MAX_ROWS_PER_TABLE")? {
+            self.limits.max_rows_per_table = value;
+        }
+        if let Some(value) = env_var(vars, "ATOM_QUERY_TIMEOUT_MS")? {
+            self.limits.query_timeout_ms = value;
+        }
+        Ok(())
+    }
+
+    fn apply_overrides(&mut self, overrides: &ConfigOverrides) {
+        if let Some(value) = &overrides.bind_address {
+            self.server.bind_address = value.clone();
+        }
+        if let Some(value) = overrides.port {
+            self.server.port = value;
+        }
+        if let Some(value) = overrides.resp_port {
+            self.server.resp_port = value;
+        }
+        if let Some(value) = &overrides.data_dir {
+            self.storage.data_dir = value.clone();
+        }
+        if let Some(value) = &overrides.data_file {
+            self.storage.data_file = value.clone();
+        }
+        if let Some(value) = overrides.engine {
+            self.storage.engine = value;
+        }
+        if let Some(value) = overrides.persistence {
+            self.storage.persistence = value;
+        }
+        if let Some(value) = overrides.read_only {
+            self.storage.read_only = value;
+        }
+    }
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "memory" => Ok(EngineKind::Memory),
+            "paged" => Ok(EngineKind::Paged),
+            _ => Err(format!("Unknown storage engine '{}' (expected memory or paged)", s)),
+        }
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineKind::Memory => write!(f, "memory"),
+            EngineKind::Paged => write!(f, "paged"),
+        }
+    }
+}
+
+impl FromStr for PersistenceMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "autosave" => Ok(PersistenceMode::Autosave),
+            "on_exit" | "on-exit" => Ok(PersistenceMode::OnExit),
+            "memory" | "none" => Ok(PersistenceMode::Memory),
+            _ => Err(format!("Unknown persistence mode '{}' (expected autosave, on_exit or memory)", s)),
+        }
+    }
+}
+
+impl fmt::Display for PersistenceMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersistenceMode::Autosave => write!(f, "autosave"),
+            PersistenceMode::OnExit => write!(f, "on_exit"),
+            PersistenceMode::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+fn env_var<T: FromStr>(vars: &Vars, name: &str) -> Result<Option<T>, String>
+where
+    T::Err: fmt::Display,
+{
+    match vars(name) {
+        Some(value) if !value.trim().is_empty() => value.trim().parse()
+            .map(Some)
+            .map_err(|e| format!("Invalid value for {}: {}", name, e)),
+        _ => Ok(None),
+    }
+}
+
+fn read_key(variable: &str, file: Option<&Path>) -> Result<Option<EncryptionKey>, String> {
+    if let Some(value) = env::var(variable).ok().filter(|value| !value.trim().is_empty()) {
+        return EncryptionKey::parse(&value).map(Some).map_err(|e| format!("Invalid value for {}: {}", variable, e));
+    }
+    let Some(path) = file else {
+        return Ok(None);
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read key file '{}': {}", path.display(), e))?;
+    EncryptionKey::parse(&text).map(Some).map_err(|e| format!("Invalid key file '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    /// A config file in a directory of its own, removed when dropped.
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("atom-config-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("atom.toml"), contents).unwrap();
+            ConfigFile(dir)
+        }
+
+        fn path(&self) -> PathBuf {
+            self.0.join("atom.toml")
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn load(overrides: &ConfigOverrides, vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::load_with(overrides, &move |name| vars.get(name).cloned())
+    }
+
+    fn with_file(path: PathBuf) -> ConfigOverrides {
+        ConfigOverrides { config_path: Some(path), ..ConfigOverrides::default() }
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let file = ConfigFile::new("layers", "[server]\nport = 7000\nbind_address = \"127.0.0.1\"\n\n[limits]\nmax_tables = 5\n");
+        let config = load(&with_file(file.path()), &[]).unwrap();
+        assert_eq!(config.listen_address(), "127.0.0.1:7000");
+        assert_eq!(config.limits.max_tables, 5);
+        // Settings the file leaves out keep their defaults.
+        assert_eq!(config.server.resp_port, ServerConfig::default().resp_port);
+
+        let env = [("ATOM_PORT", "7100"), ("ATOM_MAX_TABLES", "6")];
+        let config = load(&with_file(file.path()), &env).unwrap();
+        assert_eq!(config.listen_address(), "127.0.0.1:7100");
+        assert_eq!(config.limits.max_tables, 6);
+
+        let overrides = ConfigOverrides { port: Some(7200), bind_address: Some("::1".to_string()), ..with_file(file.path()) };
+        let config = load(&overrides, &env).unwrap();
+        assert_eq!(config.listen_address(), "::1:7200");
+        assert_eq!(config.limits.max_tables, 6);
+    }
+
+    #[test]
+    fn config_file_comes_from_the_flag_or_the_environment() {
+        let flagged = ConfigFile::new("flag", "[server]\nport = 7001\n");
+        let named = ConfigFile::new("env", "[server]\nport = 7002\n");
+        let named_path = named.path();
+        let env = [("ATOM_CONFIG", named_path.to_str().unwrap())];
+        assert_eq!(load(&ConfigOverrides::default(), &env).unwrap().server.port, 7002);
+        assert_eq!(load(&with_file(flagged.path()), &env).unwrap().server.port, 7001);
+
+        let error = load(&with_file(flagged.0.join("missing.toml")), &[]).unwrap_err();
+        assert!(error.contains("Cannot read config file"), "{}", error);
+        let broken = ConfigFile::new("broken", "[server]\nport = \"many\"\n");
+        assert!(load(&with_file(broken.path()), &[]).unwrap_err().contains("Invalid config file"));
+    }
+
+    #[test]
+    fn data_path_comes_from_database_path_then_the_atom_variables() {
+        let file = ConfigFile::new("data-path", "");
+        let env = [("DATABASE_PATH", "/srv/atom/prod.bin")];
+        assert_eq!(load(&with_file(file.path()), &env).unwrap().data_path(), Path::new("/srv/atom/prod.bin"));
+
+        let env = [("DATABASE_PATH", "/srv/atom/prod.bin"), ("ATOM_DATA_FILE", "other.bin")];
+        let config = load(&with_file(file.path()), &env).unwrap();
+        assert_eq!(config.data_path(), Path::new("/srv/atom/other.bin"));
+
+        let overrides = ConfigOverrides { data_dir: Some(PathBuf::from("/tmp/x")), engine: Some(EngineKind::Paged), ..with_file(file.path()) };
+        let config = load(&overrides, &env).unwrap();
+        assert_eq!(config.data_path(), Path::new("/tmp/x/other.pages"));
+        assert_eq!(config.write_log_path(), log_path(&config.data_path()));
+    }
+
+    #[test]
+    fn environment_values_are_checked() {
+        let file = ConfigFile::new("env-values", "");
+        let error = load(&with_file(file.path()), &[("ATOM_PORT", "lots")]).unwrap_err();
+        assert!(error.contains("ATOM_PORT"), "{}", error);
+        assert!(load(&with_file(file.path()), &[("ATOM_ENGINE", "disk")]).unwrap_err().contains("ATOM_ENGINE"));
+
+        // Blank variables are ignored rather than rejected.
+        let config = load(&with_file(file.path()), &[("ATOM_PORT", " "), ("ATOM_READ_ONLY", " true ")]).unwrap();
+        assert_eq!(config.server.port, ServerConfig::default().port);
+        assert!(config.storage.read_only);
+    }
+}
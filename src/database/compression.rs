@@ -0,0 +1,77 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// zstd level used for snapshots and log blocks; favours speed over ratio.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Block compression applied to data files and write log blocks. The codec
+/// is recorded in what it writes, so files stay readable after the setting
+/// changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// The byte stored in file headers.
+    pub fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, String> {
+        match id {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression codec {}", id)),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|e| format!("zstd: {}", e)),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(data).map_err(|e| format!("lz4: {}", e)),
+            Compression::Zstd => zstd::stream::decode_all(data).map_err(|e| format!("zstd: {}", e)),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" | "off" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression '{}' (expected none, lz4 or zstd)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use serde_json::{Map, Number, Value};
+use crate::utils::csv::{self, CsvOptions};
+use super::storage::Storage;
+use super::schema::{ColumnType, Row};
+
+/// Rejected rows reported back to the caller, at most this many per COPY.
+const MAX_REPORTED_ERRORS: usize = 20;
+
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    pub imported: usize,
+    pub rejected: usize,
+    pub errors: Vec<String>,
+}
+
+impl CopyReport {
+    pub fn summary(&self, table: &str) -> String {
+        let mut summary = format!("Copied {} row(s) into '{}'", self.imported, table);
+        if self.rejected > 0 {
+            summary.push_str(&format!(" ({} rejected)", self.rejected));
+            for error in &self.errors {
+                summary.push_str(&format!("\n  {}", error));
+            }
+            if self.rejected > self.errors.len() {
+                summary.push_str(&format!("\n  ... and {} more", self.rejected - self.errors.len()));
+            }
+        }
+        summary
+    }
+
+    fn reject(&mut self, location: String, message: String) {
+        self.rejected += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(format!("{}: {}", location, message));
+        }
+    }
+}
+
+/// Inserts CSV rows into an existing table. Values are coerced to the column
+/// types; rows that fail are skipped and reported rather than aborting the load.
+pub fn import_csv(storage: &mut dyn Storage, table: &str, input: &str, options: &CsvOptions) -> Result<CopyReport, String> {
+    let columns = storage.describe_table(table)?.clone();
+    let mut records = csv::parse(input, options)?.into_iter();
+
+    let (positions, expected_fields): (Vec<usize>, usize) = if options.header {
+        let header = match records.next() {
+            Some(header) => header,
+            None => return Ok(CopyReport::default()),
+        };
+        let names: Vec<String> = header.fields.iter().map(|f| f.trim().to_lowercase()).collect();
+        if let Some(unknown) = names.iter().find(|name| !columns.contains(name)) {
+            return Err(format!("CSV header has column '{}' which is not in table '{}'", unknown, table));
+        }
+        let positions = columns.iter()
+            .map(|column| names.iter().position(|name| name == column)
+                .ok_or_else(|| format!("CSV header is missing column '{}'", column)))
+            .collect::<Result<_, _>>()?;
+        (positions, names.len())
+    } else {
+        ((0..columns.len()).collect(), columns.len())
+    };
+
+    let mut report = CopyReport::default();
+    for record in records {
+        if record.fields.len() != expected_fields {
+            report.reject(format!("line {}", record.line), format!("expected {} fields, found {}", expected_fields, record.fields.len()));
+            continue;
+        }
+
+        let mut row = Row::new();
+        for (column, position) in columns.iter().zip(&positions) {
+            row.insert(column.clone(), record.fields[*position].clone());
+        }
+        match storage.insert_row(table, row) {
+            Ok(_) => report.imported += 1,
+            Err(e) => report.reject(format!("line {}", record.line), e),
+        }
+    }
+    Ok(report)
+}
+
+/// Renders a table as CSV in row ID order, returning the text and the row count.
+pub fn export_csv(storage: &dyn Storage, table: &str, options: &CsvOptions) -> Result<(String, usize), String> {
+    let columns = storage.describe_table(table)?;
+    let rows = storage.select_all(table)?;
+
+    let mut output = String::new();
+    if options.header {
+        output.push_str(&csv::format_record(columns, options));
+    }
+    for (_, row) in &rows {
+        let fields: Vec<String> = columns.iter().map(|c| row.get(c).cloned().unwrap_or_default()).collect();
+        output.push_str(&csv::format_record(&fields, options));
+    }
+    Ok((output, rows.len()))
+}
+
+/// Renders a table as a JSON array, or as one object per line when `lines`
+/// is set. Typed columns become JSON numbers and booleans; empty values in
+/// them become `null`.
+pub fn export_json(storage: &dyn Storage, table: &str, lines: bool) -> Result<(String, usize), String> {
+    let columns = storage.describe_columns(table)?;
+    let rows = storage.select_all(table)?;
+
+    let objects: Vec<String> = rows.iter()
+        .map(|(_, row)| {
+            let object: Map<String, Value> = columns.iter()
+                .map(|(column, column_type)| {
+                    let value = row.get(column).map(String::as_str).unwrap_or_default();
+                    (column.clone(), to_json(value, *column_type))
+                })
+                .collect();
+            Value::Object(object).to_string()
+        })
+        .collect();
+
+    let output = if lines {
+        objects.iter().map(|object| format!("{}\n", object)).collect()
+    } else if objects.is_empty() {
+        "[]\n".to_string()
+    } else {
+        format!("[\n  {}\n]\n", objects.join(",\n  "))
+    };
+    Ok((output, rows.len()))
+}
+
+/// Loads JSON objects, one per line (NDJSON) or as a single array, into
+/// `table`. A missing table is created with the keys seen across all objects
+/// as columns and types inferred from their values; an existing table gets
+/// the rows appended, with absent keys left empty.
+pub fn import_json(storage: &mut dyn Storage, table: &str, input: &str) -> Result<CopyReport, String> {
+    let mut report = CopyReport::default();
+    let mut objects = Vec::new();
+    for (location, parsed) in json_records(input)? {
+        match parsed {
+            Ok(Value::Object(object)) => objects.push((location, object)),
+            Ok(_) => report.reject(location, "expected a JSON object".to_string()),
+            Err(e) => report.reject(location, e),
+        }
+    }
+
+    if !storage.has_table(table) {
+        let (columns, types) = infer_columns(objects.iter().map(|(_, object)| object));
+        if columns.is_empty() {
+            return Err(format!("Cannot create table '{}': no columns found in the input", table));
+        }
+        storage.create_typed_table(table, columns, types)?;
+    }
+
+    let columns = storage.describe_table(table)?.clone();
+    for (location, object) in objects {
+        let mut row = Row::new();
+        for column in &columns {
+            row.insert(column.clone(), String::new());
+        }
+
+        let mut unknown = None;
+        for (key, value) in &object {
+            let key = key.to_lowercase();
+            if !columns.contains(&key) {
+                unknown = Some(key);
+                break;
+            }
+            row.insert(key, from_json(value));
+        }
+        if let Some(key) = unknown {
+            report.reject(location, format!("unknown column '{}'", key));
+            continue;
+        }
+
+        match storage.insert_row(table, row) {
+            Ok(_) => report.imported += 1,
+            Err(e) => report.reject(location, e),
+        }
+    }
+    Ok(report)
+}
+
+type ParsedRecord = (String, Result<Value, String>);
+
+fn json_records(input: &str) -> Result<Vec<ParsedRecord>, String> {
+    if input.trim_start().starts_with('[') {
+        let values: Vec<Value> = serde_json::from_str(input).map_err(|e| format!("Invalid JSON array: {}", e))?;
+        return Ok(values.into_iter()
+            .enumerate()
+            .map(|(index, value)| (format!("record {}", index + 1), Ok(value)))
+            .collect());
+    }
+
+    Ok(input.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| (format!("line {}", index + 1), serde_json::from_str(line).map_err(|e| e.to_string())))
+        .collect())
+}
+
+/// Columns in order of first appearance. A column is INTEGER, FLOAT or
+/// BOOLEAN only if every non-null value fits; anything else is TEXT.
+fn infer_columns<'a>(objects: impl Iterator<Item = &'a Map<String, Value>>) -> (Vec<String>, Vec<ColumnType>) {
+    let mut columns: Vec<String> = Vec::new();
+    let mut types: Vec<Option<ColumnType>> = Vec::new();
+
+    for object in objects {
+        for (key, value) in object {
+            let key = key.to_lowercase();
+            let index = match columns.iter().position(|c| *c == key) {
+                Some(index) => index,
+                None => {
+                    columns.push(key);
+                    types.push(None);
+                    columns.len() - 1
+                }
+            };
+
+            let value_type = match value {
+                Value::Null => continue,
+                Value::Bool(_) => ColumnType::Boolean,
+                Value::Number(n) if n.is_i64() => ColumnType::Integer,
+                Value::Number(_) => ColumnType::Float,
+                _ => ColumnType::Text,
+            };
+            types[index] = Some(match (types[index], value_type) {
+                (None, value_type) => value_type,
+                (Some(current), value_type) if current == value_type => current,
+                (Some(ColumnType::Integer), ColumnType::Float) | (Some(ColumnType::Float), ColumnType::Integer) => ColumnType::Float,
+                _ => ColumnType::Text,
+            });
+        }
+    }
+
+    let types = types.into_iter().map(Option::unwrap_or_default).collect();
+    (columns, types)
+}
+
+fn from_json(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn to_json(value: &str, column_type: ColumnType) -> Value {
+    if value.is_empty() && column_type != ColumnType::Text {
+        return Value::Null;
+    }
+    let typed = match column_type {
+        ColumnType::Integer => value.parse::<i64>().ok().map(Value::from),
+        ColumnType::Float => value.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number),
+        ColumnType::Boolean => value.parse::<bool>().ok().map(Value::Bool),
+        ColumnType::Text => None,
+    };
+    typed.unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::StorageEngine;
+    use super::*;
+
+    fn people() -> StorageEngine {
+        let mut storage = StorageEngine::new();
+        let columns = ["name", "age", "score", "active"].map(String::from).to_vec();
+        let types = vec![ColumnType::Text, ColumnType::Integer, ColumnType::Float, ColumnType::Boolean];
+        storage.create_typed_table("people", columns, types).unwrap();
+        storage
+    }
+
+    fn values(storage: &StorageEngine, column: &str) -> Vec<String> {
+        storage.select_all("people").unwrap().into_iter().map(|(_, row)| row.get(column).cloned().unwrap_or_default()).collect()
+    }
+
+    #[test]
+    fn csv_values_are_coerced_to_the_column_types() {
+        let mut storage = people();
+        let input = "ann, 42 ,1.50,yes\nbob,,2,N\ncat,forty,1,true\ndan,1,1\n";
+        let report = import_csv(&mut storage, "people", input, &CsvOptions::default()).unwrap();
+        assert_eq!((report.imported, report.rejected), (2, 2));
+        assert_eq!(report.errors, [
+            "line 3: Column 'age': 'forty' is not a valid INTEGER",
+            "line 4: expected 4 fields, found 3",
+        ]);
+        assert_eq!(values(&storage, "age"), ["42", ""]);
+        assert_eq!(values(&storage, "score"), ["1.5", "2"]);
+        assert_eq!(values(&storage, "active"), ["true", "false"]);
+        assert!(report.summary("people").contains("(2 rejected)\n  line 3"));
+    }
+
+    #[test]
+    fn a_header_may_list_the_columns_in_any_order() {
+        let mut storage = people();
+        let options = CsvOptions { header: true, ..CsvOptions::default() };
+        let report = import_csv(&mut storage, "people", "Active,NAME,score,age\nno,ann,0.5,7\n", &options).unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(values(&storage, "name"), ["ann"]);
+        assert_eq!(values(&storage, "active"), ["false"]);
+
+        let error = import_csv(&mut storage, "people", "name,age,score,active,extra\n", &options).unwrap_err();
+        assert!(error.contains("'extra'"), "{}", error);
+        let error = import_csv(&mut storage, "people", "name,age,score\n", &options).unwrap_err();
+        assert!(error.contains("missing column 'active'"), "{}", error);
+        assert!(import_csv(&mut storage, "missing", "a\n", &options).is_err());
+    }
+
+    #[test]
+    fn exported_csv_imports_back() {
+        let mut storage = people();
+        let options = CsvOptions { header: true, ..CsvOptions::default() };
+        import_csv(&mut storage, "people", "name,age,score,active\n\"Smith, \"\"Jo\"\"\",3,1.25,t\nann,,,\n", &options).unwrap();
+        let (text, count) = export_csv(&storage, "people", &options).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(text, "name,age,score,active\n\"Smith, \"\"Jo\"\"\",3,1.25,true\nann,,,\n");
+
+        let mut copy = people();
+        import_csv(&mut copy, "people", &text, &options).unwrap();
+        assert_eq!(copy.select_all("people").unwrap(), storage.select_all("people").unwrap());
+    }
+
+    #[test]
+    fn json_columns_are_typed_from_their_values() {
+        let mut storage = StorageEngine::new();
+        let input = "{\"id\": 1, \"ratio\": 1, \"ok\": true, \"tag\": \"a\"}\n\n{\"id\": 2, \"ratio\": 0.5, \"ok\": null, \"tag\": 3}\nnot json\n[1]\n";
+        let report = import_json(&mut storage, "t", input).unwrap();
+        assert_eq!((report.imported, report.rejected), (2, 2));
+        assert!(report.errors[0].starts_with("line 4: "), "{:?}", report.errors);
+        assert_eq!(report.errors[1], "line 5: expected a JSON object");
+        assert_eq!(storage.describe_columns("t").unwrap(), [
+            ("id".to_string(), ColumnType::Integer),
+            ("ratio".to_string(), ColumnType::Float),
+            ("ok".to_string(), ColumnType::Boolean),
+            ("tag".to_string(), ColumnType::Text),
+        ]);
+
+        let (text, _) = export_json(&storage, "t", true).unwrap();
+        assert_eq!(text, "{\"id\":1,\"ratio\":1.0,\"ok\":true,\"tag\":\"a\"}\n{\"id\":2,\"ratio\":0.5,\"ok\":null,\"tag\":\"3\"}\n");
+        let report = import_json(&mut storage, "t", "[{\"id\": 3, \"other\": 1}]").unwrap();
+        assert_eq!(report.errors, ["record 1: unknown column 'other'"]);
+    }
+}
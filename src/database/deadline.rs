@@ -0,0 +1,39 @@
+use std::time::{Duration, Instant};
+
+/// Rows a scan visits between deadline checks.
+const CHECK_INTERVAL: usize = 1024;
+
+/// The time by which a running statement must finish. Scans check it as
+/// they go and fail with a timeout error once it has passed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Deadline {
+    limit: Option<(Instant, Duration)>,
+}
+
+impl Deadline {
+    /// A deadline `timeout` from now; a zero timeout means none.
+    pub fn after(timeout: Duration) -> Self {
+        if timeout.is_zero() {
+            return Deadline::default();
+        }
+        Deadline { limit: Some((Instant::now() + timeout, timeout)) }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        match self.limit {
+            Some((at, timeout)) if Instant::now() >= at => {
+                Err(format!("Query timed out after {} ms", timeout.as_millis()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks on every `CHECK_INTERVAL`th row, given how many rows a scan
+    /// has visited so far.
+    pub fn check_row(&self, visited: usize) -> Result<(), String> {
+        if visited.is_multiple_of(CHECK_INTERVAL) {
+            return self.check();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::query::parser::ROW_ID;
+use super::storage::Storage;
+use super::schema::ColumnType;
+
+/// Rows per INSERT statement unless the caller asks otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Renders tables as a SQL script of CREATE TABLE, batched INSERT and
+/// CREATE INDEX statements that `atom restore` or `atom exec` can replay. Tables are
+/// sorted by name and rows kept in ID order so that dumps diff cleanly;
+/// each row names its `rowid`, so restoring keeps every row's ID.
+/// An empty `tables` slice dumps every table.
+pub fn dump_sql(storage: &dyn Storage, tables: &[String], batch_size: usize) -> Result<String, String> {
+    let mut names: Vec<String> = if tables.is_empty() {
+        storage.list_tables().into_iter().cloned().collect()
+    } else {
+        tables.to_vec()
+    };
+    names.sort();
+
+    let mut out = String::from("-- atomDB SQL dump\n-- Restore with: atom restore <file>\n");
+    for name in &names {
+        let columns = storage.describe_columns(name)?;
+        let definitions: Vec<String> = columns.iter()
+            .map(|(column, column_type)| format!("{} {}", column, column_type))
+            .collect();
+        out.push_str(&format!("\nCREATE TABLE {} ({});\n", name, definitions.join(", ")));
+
+        let rows = storage.select_all(name)?;
+        for batch in rows.chunks(batch_size.max(1)) {
+            let tuples: Vec<String> = batch.iter()
+                .map(|(id, row)| {
+                    let pairs: Vec<String> = std::iter::once(format!("{}={}", ROW_ID, id))
+                        .chain(columns.iter().map(|(column, column_type)| {
+                            let value = row.get(column).map(String::as_str).unwrap_or_default();
+                            format!("{}={}", column, sql_literal(value, *column_type))
+                        }))
+                        .collect();
+                    format!("({})", pairs.join(", "))
+                })
+                .collect();
+            out.push_str(&format!("INSERT INTO {} {};\n", name, tuples.join(",\n  ")));
+        }
+        for column in storage.list_indexes(name)? {
+            out.push_str(&format!("CREATE INDEX ON {} ({});\n", name, column));
+        }
+    }
+    Ok(out)
+}
+
+/// Typed values already in canonical form are written bare; everything else
+/// is single-quoted so that case, commas and quotes survive the round trip.
+fn sql_literal(value: &str, column_type: ColumnType) -> String {
+    let canonical = column_type != ColumnType::Text
+        && !value.is_empty()
+        && column_type.coerce(value).is_ok_and(|coerced| coerced == value);
+    if canonical {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
@@ -0,0 +1,235 @@
+use std::borrow::Cow;
+use std::fmt;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use sha2::{Digest, Sha256};
+
+/// Encrypted files start with this magic, a version byte, the cipher, the
+/// ID of the key and the nonce; the ciphertext and its tag follow. The
+/// header is authenticated along with the ciphertext.
+pub const MAGIC: &[u8; 7] = b"ATOMENC";
+const VERSION: u8 = 1;
+const AES_256_GCM: u8 = 1;
+const KEY_ID_START: usize = MAGIC.len() + 2;
+const KEY_ID_SIZE: usize = 8;
+const NONCE_SIZE: usize = 12;
+const HEADER_SIZE: usize = KEY_ID_START + KEY_ID_SIZE + NONCE_SIZE;
+
+/// A 256-bit AES-GCM key, identified by the start of its SHA-256 hash so
+/// that a file can name the key it needs without revealing it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey {
+    id: [u8; KEY_ID_SIZE],
+    key: [u8; 32],
+}
+
+impl EncryptionKey {
+    /// Parses a key written as 64 hex digits, e.g. by `openssl rand -hex 32`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text.len() != 64 || !text.is_ascii() {
+            return Err("An encryption key must be 64 hex digits (32 bytes)".to_string());
+        }
+        let mut key = [0u8; 32];
+        for (index, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16)
+                .map_err(|_| "An encryption key must be 64 hex digits (32 bytes)".to_string())?;
+        }
+        let mut id = [0u8; KEY_ID_SIZE];
+        id.copy_from_slice(&Sha256::digest(key)[..KEY_ID_SIZE]);
+        Ok(EncryptionKey { id, key })
+    }
+
+    pub fn id(&self) -> String {
+        hex(&self.id)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptionKey({})", self.id())
+    }
+}
+
+/// The key new files are encrypted with, plus keys from before a rotation
+/// that are still accepted when reading. Without a current key, files are
+/// written in the clear.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyRing {
+    current: Option<EncryptionKey>,
+    previous: Vec<EncryptionKey>,
+    /// Accept data in the clear although a key is configured, so that files
+    /// written before encryption was enabled can be read and sealed once.
+    migrating: bool,
+}
+
+impl KeyRing {
+    pub fn new(current: Option<EncryptionKey>, previous: Vec<EncryptionKey>) -> Self {
+        KeyRing { current, previous, migrating: false }
+    }
+
+    /// Lets `open` return data in the clear while a key is configured.
+    pub fn migrating(mut self, migrating: bool) -> Self {
+        self.migrating = migrating;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// True when `data` is in the clear or sealed with a key other than the
+    /// current one, so that it should be written again.
+    pub fn is_stale(&self, data: &[u8]) -> bool {
+        self.current.as_ref().is_some_and(|current| key_id(data) != Some(&current.id[..]))
+    }
+
+    /// Encrypts `data` with the current key; returns it unchanged when
+    /// encryption is off.
+    pub fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let Some(key) = &self.current else {
+            return Ok(data);
+        };
+        let mut sealed = MAGIC.to_vec();
+        sealed.extend([VERSION, AES_256_GCM]);
+        sealed.extend(key.id);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        sealed.extend(nonce.as_slice());
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.key));
+        let ciphertext = cipher.encrypt(&nonce, Payload { msg: &data, aad: &sealed })
+            .map_err(|_| "Encryption failed".to_string())?;
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    /// Fails when a key is configured, since anyone able to write the
+    /// files could otherwise replace encrypted data with their own, unless
+    /// a migration from unencrypted files was asked for.
+    pub fn check_plaintext(&self) -> Result<(), String> {
+        if self.current.is_none() || self.migrating {
+            return Ok(());
+        }
+        Err("The data is not encrypted but an encryption key is configured; to encrypt files written before \
+            encryption was enabled, start once with storage.migrate_unencrypted = true (or ATOM_MIGRATE_UNENCRYPTED=true)".to_string())
+    }
+
+    /// Decrypts `data` with whichever key it names. Data in the clear is
+    /// returned as it is if `check_plaintext` allows it.
+    pub fn open<'a>(&self, data: &'a [u8]) -> Result<Cow<'a, [u8]>, String> {
+        if !Self::is_encrypted(data) {
+            self.check_plaintext()?;
+            return Ok(Cow::Borrowed(data));
+        }
+        if data.len() < HEADER_SIZE {
+            return Err("Truncated encryption header".to_string());
+        }
+        let (header, ciphertext) = data.split_at(HEADER_SIZE);
+        let (version, cipher_id) = (header[MAGIC.len()], header[MAGIC.len() + 1]);
+        if version != VERSION || cipher_id != AES_256_GCM {
+            return Err(format!("Unsupported encryption version {} or cipher {}", version, cipher_id));
+        }
+        let key_id = &header[KEY_ID_START..KEY_ID_START + KEY_ID_SIZE];
+        let nonce = &header[HEADER_SIZE - NONCE_SIZE..];
+
+        let key = self.current.iter().chain(&self.previous)
+            .find(|key| key.id[..] == *key_id)
+            .ok_or_else(|| self.missing_key_error(key_id))?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.key));
+        cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+            .map(Cow::Owned)
+            .map_err(|_| "Decryption failed: the data is damaged or was modified".to_string())
+    }
+
+    fn missing_key_error(&self, key_id: &[u8]) -> String {
+        let key_id = hex(key_id);
+        if self.current.is_none() && self.previous.is_empty() {
+            return format!("The data is encrypted (key ID {}) but no encryption key is configured; \
+                set storage.encryption_key_file or ATOM_ENCRYPTION_KEY", key_id);
+        }
+        format!("The data is encrypted with a different key (key ID {}); configure that key, \
+            or set it as the previous key while rotating", key_id)
+    }
+}
+
+/// The ID of the key that sealed `data`, if it is encrypted.
+fn key_id(data: &[u8]) -> Option<&[u8]> {
+    if !KeyRing::is_encrypted(data) {
+        return None;
+    }
+    data.get(KEY_ID_START..KEY_ID_START + KEY_ID_SIZE)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const NEW_KEY: &str = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff000102030405060708090a0b0c0d0e0f";
+
+    fn ring(current: &str, previous: &[&str]) -> KeyRing {
+        let previous = previous.iter().map(|key| EncryptionKey::parse(key).unwrap()).collect();
+        KeyRing::new(Some(EncryptionKey::parse(current).unwrap()), previous)
+    }
+
+    #[test]
+    fn sealed_data_opens_with_its_key() {
+        let keys = ring(OLD_KEY, &[]);
+        let sealed = keys.seal(b"secret rows".to_vec()).unwrap();
+        assert!(KeyRing::is_encrypted(&sealed));
+        assert!(!sealed.windows(6).any(|window| window == b"secret"));
+        assert_eq!(&*keys.open(&sealed).unwrap(), b"secret rows");
+        assert!(!keys.is_stale(&sealed));
+    }
+
+    #[test]
+    fn another_key_is_refused() {
+        let sealed = ring(OLD_KEY, &[]).seal(b"secret rows".to_vec()).unwrap();
+        let error = ring(NEW_KEY, &[]).open(&sealed).unwrap_err();
+        assert!(error.contains("different key"), "{}", error);
+        let error = KeyRing::default().open(&sealed).unwrap_err();
+        assert!(error.contains("no encryption key is configured"), "{}", error);
+    }
+
+    #[test]
+    fn tampering_fails_authentication() {
+        let keys = ring(OLD_KEY, &[]);
+        let sealed = keys.seal(b"secret rows".to_vec()).unwrap();
+        for at in [HEADER_SIZE - 1, HEADER_SIZE, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[at] ^= 1;
+            assert!(keys.open(&tampered).unwrap_err().contains("damaged or was modified"));
+        }
+        assert!(keys.open(&sealed[..HEADER_SIZE - 1]).unwrap_err().contains("Truncated"));
+    }
+
+    #[test]
+    fn rotation_keeps_the_old_key_for_reading() {
+        let sealed = ring(OLD_KEY, &[]).seal(b"secret rows".to_vec()).unwrap();
+        let rotated = ring(NEW_KEY, &[OLD_KEY]);
+        assert_eq!(&*rotated.open(&sealed).unwrap(), b"secret rows");
+        assert!(rotated.is_stale(&sealed));
+
+        let resealed = rotated.seal(rotated.open(&sealed).unwrap().into_owned()).unwrap();
+        assert!(!rotated.is_stale(&resealed));
+        assert_eq!(&*ring(NEW_KEY, &[]).open(&resealed).unwrap(), b"secret rows");
+        assert!(ring(OLD_KEY, &[]).open(&resealed).is_err());
+    }
+
+    #[test]
+    fn plaintext_is_refused_unless_migrating() {
+        assert_eq!(&*KeyRing::default().open(b"rows").unwrap(), b"rows");
+        assert!(ring(OLD_KEY, &[]).open(b"rows").unwrap_err().contains("migrate_unencrypted"));
+        let migrating = ring(OLD_KEY, &[]).migrating(true);
+        assert_eq!(&*migrating.open(b"rows").unwrap(), b"rows");
+        assert!(migrating.is_stale(b"rows"));
+    }
+}
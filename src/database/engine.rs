@@ -1,107 +1,443 @@
 use std::collections::HashMap;
+use std::path::Path;
 use serde::{Deserialize, Serialize};
-use super::schema::{Table, Row};
+use super::compression::Compression;
+use super::deadline::Deadline;
+use super::file_format;
+use super::kv::KvStore;
+use super::schema::{ColumnType, Table, Row, RowIdRange};
+use super::storage::Storage;
+use super::wal::{Change, LogRecord};
 
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// The in-memory backend: every table is held in memory and the database is
+/// saved as a whole to the data file.
+#[derive(Debug, Deserialize, Serialize)]
 pub struct StorageEngine {
     tables: HashMap<String, Table>,
+    #[serde(skip)]
+    limits: StorageLimits,
+    /// Log sequence number of the last change; stored in the file header.
+    #[serde(skip)]
+    lsn: u64,
+    /// Changes not yet written to the write log; `None` when logging is off.
+    #[serde(skip)]
+    journal: Option<Vec<LogRecord>>,
+    #[serde(skip)]
+    savepoint: Option<Savepoint>,
+    #[serde(skip)]
+    deadline: Deadline,
+}
+
+/// State to return to when a transaction rolls back.
+#[derive(Debug)]
+struct Savepoint {
+    tables: HashMap<String, Table>,
+    lsn: u64,
+    journal_len: usize,
+}
+
+/// Size limits enforced on writes; zero means unlimited. Not persisted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageLimits {
+    pub max_tables: usize,
+    pub max_rows_per_table: usize,
 }
 
 impl StorageEngine {
     pub fn new() -> Self {
+        Self::from_tables(HashMap::new())
+    }
+
+    pub fn from_tables(tables: HashMap<String, Table>) -> Self {
         StorageEngine {
-            tables: HashMap::new(),
+            tables,
+            limits: StorageLimits::default(),
+            lsn: 0,
+            journal: None,
+            savepoint: None,
+            deadline: Deadline::default(),
+        }
+    }
+
+    pub fn set_lsn(&mut self, lsn: u64) {
+        self.lsn = lsn;
+    }
+
+    /// Starts collecting changes for the write log.
+    pub fn enable_journal(&mut self) {
+        self.journal.get_or_insert_with(Vec::new);
+    }
+
+    /// Replays a logged change. Limits are not enforced, since the change was
+    /// accepted when it was first made.
+    pub fn apply(&mut self, record: &LogRecord) -> Result<(), String> {
+        let missing = |table: &str| format!("LSN {}: table '{}' not found", record.lsn, table);
+        match &record.change {
+            Change::CreateTable { table, columns, types } => {
+                self.tables.insert(table.clone(), Table::with_types(columns.clone(), types.clone()));
+            },
+            Change::DropTable { table } => {
+                self.tables.remove(table).ok_or_else(|| missing(table))?;
+            },
+            Change::Insert { table, id, row } => {
+                let table = self.tables.get_mut(table).ok_or_else(|| missing(table))?;
+                let values = table.values(row.clone());
+                table.put_row(*id, values);
+            },
+            Change::Delete { table, column, value } => {
+                self.tables.get_mut(table).ok_or_else(|| missing(table))?.delete_where(column, value);
+            },
+            Change::Update { table, column, value, target, new_value } => {
+                self.tables.get_mut(table).ok_or_else(|| missing(table))?.update_where(column, value, target, new_value)
+                    .map_err(|e| format!("LSN {}: {}", record.lsn, e))?;
+            },
+            Change::CreateIndex { table, column } => {
+                self.tables.get_mut(table).ok_or_else(|| missing(table))?.create_index(column)
+                    .map_err(|e| format!("LSN {}: {}", record.lsn, e))?;
+            },
+            Change::DropIndex { table, column } => {
+                self.tables.get_mut(table).ok_or_else(|| missing(table))?.drop_index(column)
+                    .map_err(|e| format!("LSN {}: {}", record.lsn, e))?;
+            },
+            Change::PutTable { table, contents } => {
+                self.tables.insert(table.clone(), contents.clone());
+            },
+            Change::Compact => {
+                for table in self.tables.values_mut() {
+                    table.renumber();
+                }
+            },
+        }
+        self.lsn = record.lsn;
+        Ok(())
+    }
+
+    /// Inserts a row under `id`, or under the next free ID when `None`.
+    fn insert(&mut self, table_name: &str, id: Option<usize>, row: Row) -> Result<usize, String> {
+        match self.tables.get_mut(table_name) {
+            Some(table) => {
+                let max_rows = self.limits.max_rows_per_table;
+                if max_rows > 0 && table.rows.len() >= max_rows {
+                    return Err(format!("Table '{}' has reached its limit of {} rows", table_name, max_rows));
+                }
+                let id = match id {
+                    Some(id) => table.insert_row_at(id, row)?,
+                    None => table.insert_row(row)?,
+                };
+                self.record(|engine| {
+                    let row = engine.tables[table_name].get_row(id).unwrap_or_default();
+                    Change::Insert { table: table_name.to_string(), id, row }
+                });
+                Ok(id)
+            },
+            None => Err(format!("Table '{}' not found", table_name))
         }
     }
 
-    pub fn create_table(&mut self, name: &str, columns: Vec<String>) -> Result<(), String> {
+    /// Assigns the next LSN and, when the journal is on, records the change.
+    /// `change` is only evaluated if it will be kept.
+    fn record<F: FnOnce(&Self) -> Change>(&mut self, change: F) {
+        self.lsn += 1;
+        if self.journal.is_some() {
+            let record = LogRecord::new(self.lsn, change(self));
+            if let Some(journal) = &mut self.journal {
+                journal.push(record);
+            }
+        }
+    }
+
+    pub fn serialize(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn deserialize(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn serialize_binary(&self, compression: Compression) -> Result<Vec<u8>, bincode::Error> {
+        file_format::encode(self, compression)
+    }
+
+    pub fn deserialize_binary(data: &[u8]) -> Result<Self, bincode::Error> {
+        file_format::decode(data)
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, filename: P) -> Result<(), Box<dyn std::error::Error>> {
+        let json = self.serialize()?;
+        std::fs::write(filename, json)?;
+        Ok(())
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(filename: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let json = std::fs::read_to_string(filename)?;
+        let db = Self::deserialize(&json)?;
+        Ok(db)
+    }
+
+    pub fn save_to_binary_file<P: AsRef<Path>>(&self, filename: P) -> Result<(), Box<dyn std::error::Error>> {
+        let binary_data = self.serialize_binary(Compression::None)?;
+        std::fs::write(filename, binary_data)?;
+        Ok(())
+    }
+
+    pub fn load_from_binary_file<P: AsRef<Path>>(filename: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let binary_data = std::fs::read(filename)?;
+        let db = Self::deserialize_binary(&binary_data)?;
+        Ok(db)
+    }
+}
+
+impl Storage for StorageEngine {
+    fn create_typed_table(&mut self, name: &str, columns: Vec<String>, types: Vec<ColumnType>) -> Result<(), String> {
         if self.tables.contains_key(name) {
             return Err(format!("Table '{}' already exists", name));
         }
+        if self.limits.max_tables > 0 && self.tables.len() >= self.limits.max_tables {
+            return Err(format!("Table limit of {} reached", self.limits.max_tables));
+        }
+        if columns.len() != types.len() {
+            return Err("Every column needs exactly one type".to_string());
+        }
         self.tables.insert(
             name.to_string(),
-            Table::new(columns)
+            Table::with_types(columns, types)
         );
+        self.record(|engine| {
+            let table = &engine.tables[name];
+            Change::CreateTable { table: name.to_string(), columns: table.columns.clone(), types: table.types.clone() }
+        });
         Ok(())
     }
 
-    pub fn insert_row(&mut self, table_name: &str, row: Row) -> Result<usize, String> {
-        match self.tables.get_mut(table_name) {
-            Some(table) => table.insert_row(row),
+    fn drop_table(&mut self, name: &str) -> Result<(), String> {
+        match self.tables.remove(name) {
+            Some(_) => {
+                self.record(|_| Change::DropTable { table: name.to_string() });
+                Ok(())
+            },
+            None => Err(format!("Table '{}' not found", name))
+        }
+    }
+
+    fn has_table(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+    }
+
+    fn list_tables(&self) -> Vec<&String> {
+        self.tables.keys().collect()
+    }
+
+    fn describe_table(&self, name: &str) -> Result<&Vec<String>, String> {
+        match self.tables.get(name) {
+            Some(table) => Ok(&table.columns),
+            None => Err(format!("Table '{}' not found", name))
+        }
+    }
+
+    fn describe_columns(&self, name: &str) -> Result<Vec<(String, ColumnType)>, String> {
+        match self.tables.get(name) {
+            Some(table) => Ok(table.columns.iter()
+                .enumerate()
+                .map(|(index, column)| (column.clone(), table.column_type(index)))
+                .collect()),
+            None => Err(format!("Table '{}' not found", name))
+        }
+    }
+
+    fn insert_row(&mut self, table_name: &str, row: Row) -> Result<usize, String> {
+        self.insert(table_name, None, row)
+    }
+
+    fn insert_row_at(&mut self, table_name: &str, id: usize, row: Row) -> Result<usize, String> {
+        self.insert(table_name, Some(id), row)
+    }
+
+    fn select_all(&self, table_name: &str) -> Result<Vec<(usize, Row)>, String> {
+        match self.tables.get(table_name) {
+            Some(table) => table.get_all_rows(&self.deadline),
             None => Err(format!("Table '{}' not found", table_name))
         }
     }
 
-    pub fn select_all(&self, table_name: &str) -> Result<Vec<(usize, &Row)>, String> {
+    fn select_range(&self, table_name: &str, range: RowIdRange) -> Result<Vec<(usize, Row)>, String> {
         match self.tables.get(table_name) {
-            Some(table) => Ok(table.get_all_rows()),
+            Some(table) => table.range(range, &self.deadline),
             None => Err(format!("Table '{}' not found", table_name))
         }
     }
 
-    pub fn select_where(&self, table_name: &str, column: &str, value: &str) -> Result<Vec<(usize, &Row)>, String> {
+    fn select_batch(&self, table_name: &str, range: RowIdRange, filter: Option<(&str, &str)>, descending: bool, limit: usize) -> Result<Vec<(usize, Row)>, String> {
         match self.tables.get(table_name) {
-            Some(table) => Ok(table.select_where(column, value)),
+            Some(table) => table.batch(range, filter, descending, limit, &self.deadline),
             None => Err(format!("Table '{}' not found", table_name))
         }
     }
 
-    pub fn describe_table(&self, name: &str) -> Result<&Vec<String>, String> {
-        match self.tables.get(name) {
-            Some(table) => Ok(&table.columns),
-            None => Err(format!("Table '{}' not found", name))
+    fn select_where(&self, table_name: &str, column: &str, value: &str) -> Result<Vec<(usize, Row)>, String> {
+        match self.tables.get(table_name) {
+            Some(table) => table.select_where(column, value, &self.deadline),
+            None => Err(format!("Table '{}' not found", table_name))
         }
     }
 
-    pub fn list_tables(&self) -> Vec<&String> {
-        self.tables.keys().collect()
+    fn get_row(&self, table_name: &str, id: usize) -> Result<Option<Row>, String> {
+        match self.tables.get(table_name) {
+            Some(table) => Ok(table.get_row(id)),
+            None => Err(format!("Table '{}' not found", table_name))
+        }
     }
 
-    pub fn drop_table(&mut self, name: &str) -> Result<(), String> {
-        match self.tables.remove(name) {
-            Some(_) => Ok(()),
-            None => Err(format!("Table '{}' not found", name))
+    fn create_index(&mut self, table_name: &str, column: &str) -> Result<(), String> {
+        match self.tables.get_mut(table_name) {
+            Some(table) => table.create_index(column)?,
+            None => return Err(format!("Table '{}' not found", table_name))
         }
+        self.record(|_| Change::CreateIndex { table: table_name.to_string(), column: column.to_string() });
+        Ok(())
     }
 
-    pub fn serialize(&self) -> Result<String, serde_json::Error> {
-        serde_json::to_string_pretty(self)
+    fn drop_index(&mut self, table_name: &str, column: &str) -> Result<(), String> {
+        match self.tables.get_mut(table_name) {
+            Some(table) => table.drop_index(column)?,
+            None => return Err(format!("Table '{}' not found", table_name))
+        }
+        self.record(|_| Change::DropIndex { table: table_name.to_string(), column: column.to_string() });
+        Ok(())
     }
 
-    pub fn deserialize(json: &str) -> Result<Self, serde_json::Error> {
-        serde_json::from_str(json)
+    fn list_indexes(&self, table_name: &str) -> Result<Vec<String>, String> {
+        match self.tables.get(table_name) {
+            Some(table) => Ok(table.indexes.clone()),
+            None => Err(format!("Table '{}' not found", table_name))
+        }
     }
 
-    pub fn serialize_binary(&self) -> Result<Vec<u8>, bincode::Error> {
-        bincode::serialize(self)
+    fn index_lookup(&self, table_name: &str, column: &str, value: &str) -> Result<Option<Vec<usize>>, String> {
+        match self.tables.get(table_name) {
+            Some(table) => Ok(table.lookup(column, value)),
+            None => Err(format!("Table '{}' not found", table_name))
+        }
     }
 
-    pub fn deserialize_binary(data: &[u8]) -> Result<Self, bincode::Error> {
-        bincode::deserialize(data)
+    fn update_where(&mut self, table_name: &str, column: &str, value: &str, target: &str, new_value: &str) -> Result<usize, String> {
+        let updated = match self.tables.get_mut(table_name) {
+            Some(table) => table.update_where(column, value, target, new_value)?,
+            None => return Err(format!("Table '{}' not found", table_name))
+        };
+        if updated > 0 {
+            self.record(|_| Change::Update {
+                table: table_name.to_string(),
+                column: column.to_string(),
+                value: value.to_string(),
+                target: target.to_string(),
+                new_value: new_value.to_string(),
+            });
+        }
+        Ok(updated)
     }
 
-    pub fn save_to_file(&self, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let json = self.serialize()?;
-        std::fs::write(filename, json)?;
+    fn delete_where(&mut self, table_name: &str, column: &str, value: &str) -> Result<usize, String> {
+        let deleted = match self.tables.get_mut(table_name) {
+            Some(table) => table.delete_where(column, value),
+            None => return Err(format!("Table '{}' not found", table_name))
+        };
+        if deleted > 0 {
+            self.record(|_| Change::Delete {
+                table: table_name.to_string(),
+                column: column.to_string(),
+                value: value.to_string(),
+            });
+        }
+        Ok(deleted)
+    }
+
+    fn begin(&mut self) -> Result<(), String> {
+        if self.savepoint.is_some() {
+            return Err("A transaction is already open".to_string());
+        }
+        let journal_len = self.journal.as_ref().map_or(0, Vec::len);
+        self.savepoint = Some(Savepoint { tables: self.tables.clone(), lsn: self.lsn, journal_len });
         Ok(())
     }
 
-    pub fn load_from_file(filename: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let json = std::fs::read_to_string(filename)?;
-        let db = Self::deserialize(&json)?;
-        Ok(db)
+    fn commit(&mut self) -> Result<(), String> {
+        self.savepoint.take().ok_or("No transaction is open")?;
+        Ok(())
     }
 
-    pub fn save_to_binary_file(&self, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let binary_data = self.serialize_binary()?;
-        std::fs::write(filename, binary_data)?;
+    /// Also drops the write log entries made since `begin`.
+    fn rollback(&mut self) -> Result<(), String> {
+        let savepoint = self.savepoint.take().ok_or("No transaction is open")?;
+        self.tables = savepoint.tables;
+        self.lsn = savepoint.lsn;
+        if let Some(journal) = &mut self.journal {
+            journal.truncate(savepoint.journal_len);
+        }
         Ok(())
     }
 
-    pub fn load_from_binary_file(filename: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let binary_data = std::fs::read(filename)?;
-        let db = Self::deserialize_binary(&binary_data)?;
-        Ok(db)
+    fn limits(&self) -> StorageLimits {
+        self.limits
+    }
+
+    fn set_limits(&mut self, limits: StorageLimits) {
+        self.limits = limits;
+    }
+
+    fn set_deadline(&mut self, deadline: Deadline) {
+        self.deadline = deadline;
+    }
+
+    fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    fn merge(&mut self, other: &dyn Storage, replace: bool) -> Result<Vec<String>, String> {
+        let mut names: Vec<String> = other.list_tables().into_iter().cloned().collect();
+        names.sort();
+        if let (false, Some(name)) = (replace, names.iter().find(|name| self.tables.contains_key(*name))) {
+            return Err(format!("Table '{}' already exists", name));
+        }
+        for name in &names {
+            let (columns, types) = other.describe_columns(name)?.into_iter().unzip();
+            let mut table = Table::with_types(columns, types);
+            for (id, row) in other.select_all(name)? {
+                let values = table.values(row);
+                table.put_row(id, values);
+            }
+            for column in other.list_indexes(name)? {
+                table.create_index(&column)?;
+            }
+            self.tables.insert(name.clone(), table);
+            self.record(|engine| Change::PutTable { table: name.clone(), contents: engine.tables[name].clone() });
+        }
+        Ok(names)
+    }
+
+    fn check(&self) -> Vec<String> {
+        let mut names: Vec<&String> = self.tables.keys().collect();
+        names.sort();
+        names.into_iter()
+            .flat_map(|name| self.tables[name].check().into_iter().map(move |p| format!("{}: {}", name, p)))
+            .collect()
+    }
+
+    /// Rows are kept in a map by ID, so there are no gaps to close; saving
+    /// afterwards writes the file anew.
+    fn compact(&mut self) -> Result<usize, String> {
+        KvStore::new(self).purge_expired()
+    }
+
+    fn take_journal(&mut self) -> Vec<LogRecord> {
+        self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn to_binary(&self, compression: Compression) -> Result<Vec<u8>, String> {
+        self.serialize_binary(compression).map_err(|e| e.to_string())
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        self.serialize().map_err(|e| e.to_string())
     }
 }
 
@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use serde::Deserialize;
+use super::compression::Compression;
+use super::encryption::KeyRing;
+use super::engine::StorageEngine;
+use super::schema::{Row, StoredTable, Table, UnindexedTable};
+use super::storage::Storage;
+
+/// Binary data files start with this magic followed by a version byte.
+/// Files without it were written before the header existed (version 0).
+/// Version 2 adds the LSN of the last change as a little-endian u64.
+/// Version 3 stores each row as its values in column order instead of
+/// naming the column of every value. Version 4 adds a codec byte before
+/// the LSN; the body after the LSN is compressed with that codec. Version
+/// 5 adds each table's list of indexed columns.
+pub const MAGIC: &[u8; 6] = b"ATOMDB";
+pub const FORMAT_VERSION: u8 = 5;
+
+/// How data files, backups and write log blocks are written: compressed,
+/// then encrypted when a key is configured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileOptions {
+    pub compression: Compression,
+    pub keys: KeyRing,
+}
+
+/// Body of version 1 and 2 files, whose rows name every column.
+#[derive(Deserialize)]
+struct NamedStorage {
+    tables: HashMap<String, StoredTable<Row>>,
+}
+
+/// Body of version 3 and 4 files, written before tables had indexes.
+#[derive(Deserialize)]
+struct UnindexedStorage {
+    tables: HashMap<String, UnindexedTable>,
+}
+
+#[derive(Deserialize)]
+struct LegacyStorage {
+    tables: HashMap<String, LegacyTable>,
+}
+
+#[derive(Deserialize)]
+struct LegacyTable {
+    columns: Vec<String>,
+    rows: BTreeMap<usize, Row>,
+}
+
+pub fn encode(storage: &StorageEngine, compression: Compression) -> Result<Vec<u8>, bincode::Error> {
+    let body = bincode::serialize(storage)?;
+    let mut data = MAGIC.to_vec();
+    data.push(FORMAT_VERSION);
+    data.push(compression.id());
+    data.extend(storage.lsn().to_le_bytes());
+    data.extend(compression.compress(&body).map_err(format_error)?);
+    Ok(data)
+}
+
+pub fn decode(data: &[u8]) -> Result<StorageEngine, bincode::Error> {
+    let Some(rest) = data.strip_prefix(MAGIC) else {
+        return decode_legacy(data);
+    };
+
+    match rest.split_first() {
+        Some((1, body)) => decode_named(body),
+        Some((&version, body)) if (2..=FORMAT_VERSION).contains(&version) => {
+            let (compression, body) = match body.split_first() {
+                Some((codec, body)) if version >= 4 => (Compression::from_id(*codec).map_err(format_error)?, body),
+                _ => (Compression::None, body),
+            };
+            if body.len() < 8 {
+                return Err(format_error("Truncated data file header".to_string()));
+            }
+            let (lsn, body) = body.split_at(8);
+            let body = match compression {
+                Compression::None => Cow::Borrowed(body),
+                _ => Cow::Owned(compression.decompress(body)
+                    .map_err(|e| format_error(format!("Cannot decompress data file: {}", e)))?),
+            };
+            let mut storage = match version {
+                2 => decode_named(&body)?,
+                3 | 4 => decode_unindexed(&body)?,
+                _ => bincode::deserialize(&body)?,
+            };
+            storage.set_lsn(u64::from_le_bytes(lsn.try_into().unwrap_or_default()));
+            Ok(storage)
+        },
+        Some((version, _)) => Err(format_error(format!("Unsupported data file version {}", version))),
+        None => Err(format_error("Truncated data file header".to_string())),
+    }
+}
+
+fn decode_named(body: &[u8]) -> Result<StorageEngine, bincode::Error> {
+    let named: NamedStorage = bincode::deserialize(body)?;
+    let tables = named.tables.into_iter()
+        .map(|(name, table)| (name, table.into_table()))
+        .collect();
+    Ok(StorageEngine::from_tables(tables))
+}
+
+fn decode_unindexed(body: &[u8]) -> Result<StorageEngine, bincode::Error> {
+    let unindexed: UnindexedStorage = bincode::deserialize(body)?;
+    let tables = unindexed.tables.into_iter()
+        .map(|(name, table)| (name, table.into_table()))
+        .collect();
+    Ok(StorageEngine::from_tables(tables))
+}
+
+fn decode_legacy(data: &[u8]) -> Result<StorageEngine, bincode::Error> {
+    let legacy: LegacyStorage = bincode::deserialize(data)?;
+    let tables = legacy.tables.into_iter()
+        .map(|(name, table)| {
+            let mut upgraded = Table::new(table.columns);
+            upgraded.rows = table.rows.into_iter().map(|(id, row)| (id, upgraded.values(row))).collect();
+            (name, upgraded)
+        })
+        .collect();
+    Ok(StorageEngine::from_tables(tables))
+}
+
+fn format_error(message: String) -> bincode::Error {
+    Box::new(bincode::ErrorKind::Custom(message))
+}
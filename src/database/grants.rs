@@ -0,0 +1,381 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use super::kv::KV_TABLE;
+use super::storage::Storage;
+use super::schema::Row;
+use super::users::{check_name, Users, USERS_TABLE};
+
+pub const ROLES_TABLE: &str = "atom_roles";
+pub const GRANTS_TABLE: &str = "atom_grants";
+const GRANTS_COLUMNS: [&str; 3] = ["grantee", "privilege", "object"];
+
+/// Errors for statements a session is not allowed to run start with this,
+/// so that servers can report them as their own kind of error.
+pub const PERMISSION_DENIED: &str = "Permission denied";
+
+/// `privilege` of the rows that make a grantee a member of the role named
+/// in `object`.
+const MEMBER_OF: &str = "role";
+
+/// What a grant allows. Table privileges name a table; `Admin` covers the
+/// whole database: DDL, LOAD, backups, COPY and managing users and grants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Admin,
+}
+
+/// Roles and the privileges granted to users and roles, kept in the
+/// `atom_roles` and `atom_grants` tables. A user holds its own grants plus
+/// those of every role it belongs to, directly or through other roles.
+pub struct Grants<'a> {
+    storage: &'a mut dyn Storage,
+}
+
+impl<'a> Grants<'a> {
+    pub fn new(storage: &'a mut dyn Storage) -> Self {
+        Grants { storage }
+    }
+
+    pub fn is_role(&self, name: &str) -> Result<bool, String> {
+        if !self.storage.has_table(ROLES_TABLE) {
+            return Ok(false);
+        }
+        Ok(!self.storage.select_where(ROLES_TABLE, "name", name)?.is_empty())
+    }
+
+    pub fn create_role(&mut self, name: &str) -> Result<(), String> {
+        check_name(name)?;
+        if self.is_role(name)? || Users::new(self.storage).exists(name)? {
+            return Err(format!("A user or role named '{}' already exists", name));
+        }
+        if !self.storage.has_table(ROLES_TABLE) {
+            self.storage.create_table(ROLES_TABLE, vec!["name".to_string()])?;
+        }
+        let mut row = Row::new();
+        row.insert("name".to_string(), name.to_string());
+        self.storage.insert_row(ROLES_TABLE, row)?;
+        Ok(())
+    }
+
+    /// Drops the role along with its grants and memberships.
+    pub fn drop_role(&mut self, name: &str) -> Result<(), String> {
+        if !self.is_role(name)? {
+            return Err(format!("Role '{}' does not exist", name));
+        }
+        self.storage.delete_where(ROLES_TABLE, "name", name)?;
+        self.forget(name)
+    }
+
+    /// Grants a privilege on `table`, or database-wide `Admin` when `table`
+    /// is `None`.
+    pub fn grant(&mut self, privilege: Privilege, table: Option<&str>, grantee: &str) -> Result<(), String> {
+        let object = self.check_object(privilege, table)?;
+        self.check_grantee(grantee)?;
+        self.add(grantee, &privilege.to_string(), &object)
+    }
+
+    /// Returns whether the grantee held the privilege.
+    pub fn revoke(&mut self, privilege: Privilege, table: Option<&str>, grantee: &str) -> Result<bool, String> {
+        self.remove(grantee, &privilege.to_string(), table.unwrap_or_default())
+    }
+
+    pub fn grant_role(&mut self, role: &str, grantee: &str) -> Result<(), String> {
+        if !self.is_role(role)? {
+            return Err(format!("Role '{}' does not exist", role));
+        }
+        self.check_grantee(grantee)?;
+        if self.roles_of(role)?.contains(grantee) || role == grantee {
+            return Err(format!("Granting '{}' to '{}' would make a cycle of roles", role, grantee));
+        }
+        self.add(grantee, MEMBER_OF, role)
+    }
+
+    /// Returns whether the grantee was a member of the role.
+    pub fn revoke_role(&mut self, role: &str, grantee: &str) -> Result<bool, String> {
+        self.remove(grantee, MEMBER_OF, role)
+    }
+
+    /// Removes every grant to or membership of a user or role that is gone.
+    pub fn forget(&mut self, name: &str) -> Result<(), String> {
+        if !self.storage.has_table(GRANTS_TABLE) {
+            return Ok(());
+        }
+        self.storage.delete_where(GRANTS_TABLE, "grantee", name)?;
+        let members: Vec<Row> = self.storage.select_where(GRANTS_TABLE, "object", name)?
+            .into_iter()
+            .map(|(_, row)| row)
+            .filter(|row| field(row, "privilege") == MEMBER_OF)
+            .collect();
+        for row in members {
+            self.remove(field(&row, "grantee"), MEMBER_OF, name)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the grants on a dropped table, so that a new table with the
+    /// same name starts without them.
+    pub fn forget_table(&mut self, table: &str) -> Result<(), String> {
+        if !self.storage.has_table(GRANTS_TABLE) {
+            return Ok(());
+        }
+        let grants: Vec<Row> = self.storage.select_where(GRANTS_TABLE, "object", table)?
+            .into_iter()
+            .map(|(_, row)| row)
+            .filter(|row| field(row, "privilege") != MEMBER_OF)
+            .collect();
+        for row in grants {
+            self.remove(field(&row, "grantee"), field(&row, "privilege"), table)?;
+        }
+        Ok(())
+    }
+
+    /// Whether `user` holds `privilege` on `table` (or database-wide when
+    /// `table` is `None`). Admins hold every privilege, and only admins may
+    /// touch the system tables.
+    pub fn allows(&self, user: &str, privilege: Privilege, table: Option<&str>) -> Result<bool, String> {
+        if !self.storage.has_table(GRANTS_TABLE) {
+            return Ok(false);
+        }
+        let mut grantees = self.roles_of(user)?;
+        grantees.insert(user.to_string());
+
+        let admin = Privilege::Admin.to_string();
+        let wanted = privilege.to_string();
+        let is_system = table.is_some_and(is_system_table);
+        for grantee in &grantees {
+            for (_, row) in self.storage.select_where(GRANTS_TABLE, "grantee", grantee)? {
+                let held = field(&row, "privilege");
+                if held == admin {
+                    return Ok(true);
+                }
+                if !is_system && held == wanted && table == Some(field(&row, "object")) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Every role `name` belongs to, directly or through other roles.
+    fn roles_of(&self, name: &str) -> Result<BTreeSet<String>, String> {
+        let mut roles = BTreeSet::new();
+        if !self.storage.has_table(GRANTS_TABLE) {
+            return Ok(roles);
+        }
+        let mut pending = vec![name.to_string()];
+        while let Some(grantee) = pending.pop() {
+            for (_, row) in self.storage.select_where(GRANTS_TABLE, "grantee", &grantee)? {
+                if field(&row, "privilege") == MEMBER_OF && roles.insert(field(&row, "object").to_string()) {
+                    pending.push(field(&row, "object").to_string());
+                }
+            }
+        }
+        Ok(roles)
+    }
+
+    fn check_object(&self, privilege: Privilege, table: Option<&str>) -> Result<String, String> {
+        match (privilege, table) {
+            (Privilege::Admin, None) => Ok(String::new()),
+            (Privilege::Admin, Some(_)) => Err("ADMIN is granted on the whole database, not ON a table".to_string()),
+            (_, None) => Err(format!("{} must be granted ON a table", privilege)),
+            (_, Some(table)) if is_system_table(table) => {
+                Err(format!("Table '{}' is a system table; only admins can access it", table))
+            },
+            // The key/value table is created by the first write over RESP.
+            (_, Some(table)) if !self.storage.has_table(table) && table != KV_TABLE => Err(format!("Table '{}' not found", table)),
+            (_, Some(table)) => Ok(table.to_string()),
+        }
+    }
+
+    fn check_grantee(&mut self, grantee: &str) -> Result<(), String> {
+        if self.is_role(grantee)? || Users::new(self.storage).exists(grantee)? {
+            return Ok(());
+        }
+        Err(format!("No user or role named '{}'", grantee))
+    }
+
+    fn add(&mut self, grantee: &str, privilege: &str, object: &str) -> Result<(), String> {
+        self.ensure_table()?;
+        if self.has(grantee, privilege, object)? {
+            return Ok(());
+        }
+        let mut row = Row::new();
+        row.insert("grantee".to_string(), grantee.to_string());
+        row.insert("privilege".to_string(), privilege.to_string());
+        row.insert("object".to_string(), object.to_string());
+        self.storage.insert_row(GRANTS_TABLE, row)?;
+        Ok(())
+    }
+
+    /// Deletes one grant, returning whether it existed. Storage deletes by
+    /// a single column, so the grantee's other grants are inserted again.
+    fn remove(&mut self, grantee: &str, privilege: &str, object: &str) -> Result<bool, String> {
+        if !self.has(grantee, privilege, object)? {
+            return Ok(false);
+        }
+        let rows = self.storage.select_where(GRANTS_TABLE, "grantee", grantee)?;
+        self.storage.delete_where(GRANTS_TABLE, "grantee", grantee)?;
+        for (_, row) in rows {
+            if field(&row, "privilege") != privilege || field(&row, "object") != object {
+                self.storage.insert_row(GRANTS_TABLE, row)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn has(&self, grantee: &str, privilege: &str, object: &str) -> Result<bool, String> {
+        if !self.storage.has_table(GRANTS_TABLE) {
+            return Ok(false);
+        }
+        Ok(self.storage.select_where(GRANTS_TABLE, "grantee", grantee)?
+            .iter()
+            .any(|(_, row)| field(row, "privilege") == privilege && field(row, "object") == object))
+    }
+
+    fn ensure_table(&mut self) -> Result<(), String> {
+        if !self.storage.has_table(GRANTS_TABLE) {
+            let columns = GRANTS_COLUMNS.iter().map(|c| c.to_string()).collect();
+            return self.storage.create_table(GRANTS_TABLE, columns);
+        }
+        let columns = self.storage.describe_table(GRANTS_TABLE)?;
+        if GRANTS_COLUMNS.iter().any(|c| !columns.iter().any(|existing| existing == c)) {
+            return Err(format!("Table '{}' exists but is not a grants table", GRANTS_TABLE));
+        }
+        Ok(())
+    }
+}
+
+/// Tables holding accounts and grants, readable only by admins.
+pub fn is_system_table(table: &str) -> bool {
+    matches!(table, USERS_TABLE | ROLES_TABLE | GRANTS_TABLE)
+}
+
+/// The error for a statement `user` may not run.
+pub fn permission_denied(user: &str, privilege: Privilege, table: Option<&str>) -> String {
+    format!("{}: user '{}' lacks the {} privilege{}", PERMISSION_DENIED, user, privilege, on_table(table))
+}
+
+pub fn is_permission_denied(error: &str) -> bool {
+    error.starts_with(PERMISSION_DENIED)
+}
+
+fn on_table(table: Option<&str>) -> String {
+    table.map(|table| format!(" on table '{}'", table)).unwrap_or_default()
+}
+
+fn field<'r>(row: &'r Row, column: &str) -> &'r str {
+    row.get(column).map(String::as_str).unwrap_or_default()
+}
+
+impl FromStr for Privilege {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "select" => Ok(Privilege::Select),
+            "insert" => Ok(Privilege::Insert),
+            "admin" => Ok(Privilege::Admin),
+            _ => Err(format!("Unknown privilege '{}' (expected SELECT, INSERT, ALL or ADMIN)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Privilege::Select => write!(f, "SELECT"),
+            Privilege::Insert => write!(f, "INSERT"),
+            Privilege::Admin => write!(f, "ADMIN"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::StorageEngine;
+    use super::*;
+
+    /// A database with tables `users` and `orders`, user `alice` and role `analyst`.
+    fn database() -> StorageEngine {
+        let mut storage = StorageEngine::new();
+        storage.create_table("users", vec!["name".to_string()]).unwrap();
+        storage.create_table("orders", vec!["id".to_string()]).unwrap();
+        Users::new(&mut storage).create("alice", "s3cret").unwrap();
+        Grants::new(&mut storage).create_role("analyst").unwrap();
+        storage
+    }
+
+    #[test]
+    fn grants_allow_one_privilege_on_one_table_until_revoked() {
+        let mut storage = database();
+        let mut grants = Grants::new(&mut storage);
+        assert_eq!(grants.allows("alice", Privilege::Select, Some("users")), Ok(false));
+        grants.grant(Privilege::Select, Some("users"), "alice").unwrap();
+        assert_eq!(grants.allows("alice", Privilege::Select, Some("users")), Ok(true));
+        assert_eq!(grants.allows("alice", Privilege::Insert, Some("users")), Ok(false));
+        assert_eq!(grants.allows("alice", Privilege::Select, Some("orders")), Ok(false));
+        assert_eq!(grants.allows("alice", Privilege::Admin, None), Ok(false));
+
+        assert_eq!(grants.revoke(Privilege::Select, Some("users"), "alice"), Ok(true));
+        assert_eq!(grants.revoke(Privilege::Select, Some("users"), "alice"), Ok(false));
+        assert_eq!(grants.allows("alice", Privilege::Select, Some("users")), Ok(false));
+
+        assert!(grants.grant(Privilege::Select, Some("missing"), "alice").is_err());
+        assert!(grants.grant(Privilege::Select, None, "alice").is_err());
+        assert!(grants.grant(Privilege::Admin, Some("users"), "alice").is_err());
+        assert!(grants.grant(Privilege::Select, Some("users"), "nobody").is_err());
+    }
+
+    #[test]
+    fn users_hold_the_grants_of_their_roles_and_the_roles_above() {
+        let mut storage = database();
+        let mut grants = Grants::new(&mut storage);
+        grants.create_role("reader").unwrap();
+        grants.grant(Privilege::Select, Some("users"), "reader").unwrap();
+        grants.grant_role("reader", "analyst").unwrap();
+        grants.grant_role("analyst", "alice").unwrap();
+        assert_eq!(grants.allows("alice", Privilege::Select, Some("users")), Ok(true));
+        assert!(grants.grant_role("analyst", "reader").is_err());
+        assert!(grants.grant_role("analyst", "analyst").is_err());
+
+        grants.revoke_role("reader", "analyst").unwrap();
+        assert_eq!(grants.allows("alice", Privilege::Select, Some("users")), Ok(false));
+
+        grants.grant(Privilege::Admin, None, "analyst").unwrap();
+        assert_eq!(grants.allows("alice", Privilege::Insert, Some("orders")), Ok(true));
+        grants.drop_role("analyst").unwrap();
+        assert_eq!(grants.allows("alice", Privilege::Admin, None), Ok(false));
+    }
+
+    #[test]
+    fn only_admins_reach_the_system_tables() {
+        let mut storage = database();
+        let mut grants = Grants::new(&mut storage);
+        for table in [USERS_TABLE, ROLES_TABLE, GRANTS_TABLE] {
+            assert!(is_system_table(table));
+            assert!(grants.grant(Privilege::Select, Some(table), "alice").is_err());
+        }
+        grants.grant(Privilege::Admin, None, "alice").unwrap();
+        assert_eq!(grants.allows("alice", Privilege::Select, Some(USERS_TABLE)), Ok(true));
+        assert!(!is_system_table("users"));
+    }
+
+    #[test]
+    fn dropped_grantees_and_tables_take_their_grants_along() {
+        let mut storage = database();
+        let mut grants = Grants::new(&mut storage);
+        grants.grant(Privilege::Select, Some("users"), "analyst").unwrap();
+        grants.grant(Privilege::Insert, Some("orders"), "alice").unwrap();
+        grants.grant_role("analyst", "alice").unwrap();
+        grants.forget_table("users").unwrap();
+        assert_eq!(grants.allows("alice", Privilege::Select, Some("users")), Ok(false));
+        assert_eq!(grants.allows("alice", Privilege::Insert, Some("orders")), Ok(true));
+
+        Users::new(&mut storage).drop("alice").unwrap();
+        Users::new(&mut storage).create("alice", "again").unwrap();
+        assert_eq!(Grants::new(&mut storage).allows("alice", Privilege::Insert, Some("orders")), Ok(false));
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::BTreeSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::storage::Storage;
+use super::schema::Row;
+
+pub const KV_TABLE: &str = "kv";
+const KV_COLUMNS: [&str; 5] = ["key", "type", "field", "value", "expires_at"];
+
+const TYPE_STRING: &str = "string";
+const TYPE_HASH: &str = "hash";
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Key/value view over the `kv` table: one row per string key or hash field.
+/// Values are bytes, kept hex-encoded in the `value` column; keys and hash
+/// fields are text.
+pub struct KvStore<'a> {
+    storage: &'a mut dyn Storage,
+}
+
+impl<'a> KvStore<'a> {
+    pub fn new(storage: &'a mut dyn Storage) -> Self {
+        KvStore { storage }
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let rows = self.entries(key)?;
+        match rows.first() {
+            None => Ok(None),
+            Some(row) if row_type(row) == TYPE_STRING => row_value(row).map(Some),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8], ttl_ms: Option<u64>) -> Result<(), String> {
+        self.ensure_table()?;
+        self.storage.delete_where(KV_TABLE, "key", key)?;
+        let expires_at = ttl_ms.map(|ttl| (now_millis() + ttl as u128).to_string()).unwrap_or_default();
+        self.insert_entry(key, TYPE_STRING, "", &encode_value(value), &expires_at)
+    }
+
+    pub fn del(&mut self, key: &str) -> Result<bool, String> {
+        if self.entries(key)?.is_empty() {
+            return Ok(false);
+        }
+        self.storage.delete_where(KV_TABLE, "key", key)?;
+        Ok(true)
+    }
+
+    pub fn exists(&mut self, key: &str) -> Result<bool, String> {
+        Ok(!self.entries(key)?.is_empty())
+    }
+
+    pub fn keys(&mut self, pattern: &str) -> Result<Vec<String>, String> {
+        if !self.storage.has_table(KV_TABLE) {
+            return Ok(Vec::new());
+        }
+        let now = now_millis();
+        let keys: BTreeSet<String> = self.storage.select_all(KV_TABLE)?
+            .into_iter()
+            .filter(|(_, row)| !is_expired(row, now))
+            .filter_map(|(_, row)| row.get("key").cloned())
+            .filter(|key| glob_match(pattern, key))
+            .collect();
+        Ok(keys.into_iter().collect())
+    }
+
+    /// Sets a hash field, returning `true` if the field did not exist before.
+    pub fn hset(&mut self, key: &str, field: &str, value: &[u8]) -> Result<bool, String> {
+        let rows = self.entries(key)?;
+        if rows.iter().any(|row| row_type(row) != TYPE_HASH) {
+            return Err(WRONG_TYPE.to_string());
+        }
+
+        self.ensure_table()?;
+        let expires_at = rows.first().and_then(|row| row.get("expires_at").cloned()).unwrap_or_default();
+        let mut fields: Vec<(String, String)> = rows.iter()
+            .filter_map(|row| Some((row.get("field")?.clone(), row.get("value")?.clone())))
+            .collect();
+
+        let value = encode_value(value);
+        let is_new = match fields.iter_mut().find(|(name, _)| name == field) {
+            Some(existing) => {
+                existing.1 = value;
+                false
+            },
+            None => {
+                fields.push((field.to_string(), value));
+                true
+            }
+        };
+
+        self.storage.delete_where(KV_TABLE, "key", key)?;
+        for (name, value) in fields {
+            self.insert_entry(key, TYPE_HASH, &name, &value, &expires_at)?;
+        }
+        Ok(is_new)
+    }
+
+    pub fn hgetall(&mut self, key: &str) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let rows = self.entries(key)?;
+        if rows.iter().any(|row| row_type(row) != TYPE_HASH) {
+            return Err(WRONG_TYPE.to_string());
+        }
+        let mut fields = rows.iter()
+            .map(|row| Ok((row.get("field").cloned().unwrap_or_default(), row_value(row)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        fields.sort();
+        Ok(fields)
+    }
+
+    /// Sets a time-to-live on `key`. A non-positive TTL deletes the key, as in Redis.
+    pub fn expire(&mut self, key: &str, seconds: i64) -> Result<bool, String> {
+        if self.entries(key)?.is_empty() {
+            return Ok(false);
+        }
+        if seconds <= 0 {
+            self.storage.delete_where(KV_TABLE, "key", key)?;
+            return Ok(true);
+        }
+        let expires_at = (now_millis() + seconds as u128 * 1000).to_string();
+        self.storage.update_where(KV_TABLE, "key", key, "expires_at", &expires_at)?;
+        Ok(true)
+    }
+
+    /// Removes every expired entry, returning the number of rows dropped.
+    pub fn purge_expired(&mut self) -> Result<usize, String> {
+        if !self.storage.has_table(KV_TABLE) {
+            return Ok(0);
+        }
+        let now = now_millis();
+        let expired: BTreeSet<String> = self.storage.select_all(KV_TABLE)?
+            .into_iter()
+            .filter(|(_, row)| is_expired(row, now))
+            .filter_map(|(_, row)| row.get("key").cloned())
+            .collect();
+
+        let mut removed = 0;
+        for key in expired {
+            removed += self.storage.delete_where(KV_TABLE, "key", &key)?;
+        }
+        Ok(removed)
+    }
+
+    fn ensure_table(&mut self) -> Result<(), String> {
+        if !self.storage.has_table(KV_TABLE) {
+            let columns = KV_COLUMNS.iter().map(|c| c.to_string()).collect();
+            return self.storage.create_table(KV_TABLE, columns);
+        }
+        let columns = self.storage.describe_table(KV_TABLE)?;
+        if KV_COLUMNS.iter().any(|c| !columns.iter().any(|existing| existing == c)) {
+            return Err(format!("Table '{}' exists but is not a key/value table", KV_TABLE));
+        }
+        Ok(())
+    }
+
+    /// Returns the live rows stored under `key`, dropping them first if the key has expired.
+    fn entries(&mut self, key: &str) -> Result<Vec<Row>, String> {
+        if !self.storage.has_table(KV_TABLE) {
+            return Ok(Vec::new());
+        }
+        let rows: Vec<Row> = self.storage.select_where(KV_TABLE, "key", key)?
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect();
+
+        let now = now_millis();
+        if rows.iter().any(|row| is_expired(row, now)) {
+            self.storage.delete_where(KV_TABLE, "key", key)?;
+            return Ok(Vec::new());
+        }
+        Ok(rows)
+    }
+
+    fn insert_entry(&mut self, key: &str, kind: &str, field: &str, value: &str, expires_at: &str) -> Result<(), String> {
+        let mut row = Row::new();
+        row.insert("key".to_string(), key.to_string());
+        row.insert("type".to_string(), kind.to_string());
+        row.insert("field".to_string(), field.to_string());
+        row.insert("value".to_string(), value.to_string());
+        row.insert("expires_at".to_string(), expires_at.to_string());
+        self.storage.insert_row(KV_TABLE, row)?;
+        Ok(())
+    }
+}
+
+fn row_type(row: &Row) -> &str {
+    row.get("type").map(String::as_str).unwrap_or(TYPE_STRING)
+}
+
+fn row_value(row: &Row) -> Result<Vec<u8>, String> {
+    decode_value(row.get("value").map(String::as_str).unwrap_or(""))
+}
+
+fn encode_value(value: &[u8]) -> String {
+    value.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_value(hex: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("Corrupt value in table '{}': expected hex, got '{}'", KV_TABLE, hex);
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..hex.len()).step_by(2)
+        .map(|at| hex.get(at..at + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()).ok_or_else(invalid))
+        .collect()
+}
+
+fn is_expired(row: &Row, now: u128) -> bool {
+    row.get("expires_at")
+        .and_then(|ts| ts.parse::<u128>().ok())
+        .is_some_and(|ts| ts <= now)
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// Redis-style glob matching supporting `*` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::StorageEngine;
+
+    #[test]
+    fn values_are_binary_safe() {
+        let mut storage = StorageEngine::new();
+        let mut kv = KvStore::new(&mut storage);
+        let value = [0, 0xff, b'\r', b'\n', 0xc3];
+        kv.set("blob", &value, None).unwrap();
+        assert_eq!(kv.get("blob").unwrap(), Some(value.to_vec()));
+
+        kv.hset("h", "f", &value[1..]).unwrap();
+        assert_eq!(kv.hgetall("h").unwrap(), [("f".to_string(), value[1..].to_vec())]);
+        assert!(kv.get("h").is_err());
+        assert_eq!(storage.select_where(KV_TABLE, "key", "blob").unwrap()[0].1.get("value").unwrap(), "00ff0d0ac3");
+    }
+
+    #[test]
+    fn corrupt_values_are_reported() {
+        assert_eq!(decode_value("0aFF").unwrap(), [0x0a, 0xff]);
+        assert!(decode_value("abc").is_err());
+        assert!(decode_value("zz").is_err());
+    }
+}
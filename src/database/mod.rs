@@ -1,5 +1,25 @@
+pub mod storage;
 pub mod engine;
 pub mod schema;
+pub mod kv;
+pub mod users;
+pub mod grants;
+pub mod file_format;
+pub mod compression;
+pub mod encryption;
+pub mod copy;
+pub mod dump;
+pub mod wal;
+pub mod deadline;
+pub mod paged;
 
-pub use engine::StorageEngine;
-pub use schema::{Table,Row};
\ No newline at end of file
+pub use storage::{lock_storage, SharedStorage, Storage};
+pub use engine::{StorageEngine, StorageLimits};
+pub use schema::{ColumnType, Table, Row, RowIdRange};
+pub use kv::KvStore;
+pub use users::Users;
+pub use grants::{Grants, Privilege};
+pub use compression::Compression;
+pub use encryption::KeyRing;
+pub use file_format::FileOptions;
+pub use deadline::Deadline;
@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use super::page::PAGE_SIZE;
+
+/// A page is identified by the heap file it belongs to and its number in it.
+pub type PageId = (u32, u64);
+
+/// An undo log entry: the file ID, the page number and the page.
+pub const UNDO_ENTRY_SIZE: usize = 4 + 8 + PAGE_SIZE;
+
+#[derive(Debug)]
+struct Frame {
+    data: Box<[u8; PAGE_SIZE]>,
+    dirty: bool,
+    last_used: u64,
+}
+
+/// Where a transaction keeps the pages it overwrites on disk.
+#[derive(Debug)]
+struct Undo {
+    log: File,
+    /// Length of each file when the transaction began; pages past it are
+    /// new and need no saving, and files not listed were created since.
+    lengths: HashMap<u32, u64>,
+    saved: HashSet<PageId>,
+}
+
+/// Caches up to `capacity` pages across every open heap file. When full, the
+/// least recently used page is evicted, and written back first if dirty.
+/// During a transaction, a page's contents on disk are appended to the undo
+/// log before it is first overwritten.
+#[derive(Debug)]
+pub struct BufferPool {
+    capacity: usize,
+    frames: HashMap<PageId, Frame>,
+    files: HashMap<u32, File>,
+    clock: u64,
+    undo: Option<Undo>,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        BufferPool {
+            capacity: capacity.max(1),
+            frames: HashMap::new(),
+            files: HashMap::new(),
+            clock: 0,
+            undo: None,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn open_file(&mut self, file_id: u32, path: &Path) -> Result<(), String> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+            .map_err(|e| format!("Cannot open '{}': {}", path.display(), e))?;
+        self.files.insert(file_id, file);
+        Ok(())
+    }
+
+    /// Forgets a file's cached pages without writing them back.
+    pub fn close_file(&mut self, file_id: u32) {
+        self.frames.retain(|(file, _), _| *file != file_id);
+        self.files.remove(&file_id);
+    }
+
+    /// Truncates a file to zero pages, discarding its cached pages.
+    pub fn truncate_file(&mut self, file_id: u32) -> Result<(), String> {
+        self.frames.retain(|(file, _), _| *file != file_id);
+        let saved_pages = self.undo.as_ref().and_then(|undo| undo.lengths.get(&file_id)).map_or(0, |len| len.div_ceil(PAGE_SIZE as u64));
+        for page_no in 0..saved_pages {
+            self.save_original((file_id, page_no))?;
+        }
+        self.file(file_id)?.set_len(0).map_err(|e| e.to_string())
+    }
+
+    /// Number of pages in the file, counting a partly written last one.
+    pub fn file_pages(&mut self, file_id: u32) -> Result<u64, String> {
+        let len = self.file(file_id)?.metadata().map_err(|e| e.to_string())?.len();
+        Ok(len.div_ceil(PAGE_SIZE as u64))
+    }
+
+    /// The length of every open file, as a transaction starting now needs.
+    pub fn file_lengths(&mut self) -> Result<HashMap<u32, u64>, String> {
+        self.files.iter()
+            .map(|(file_id, file)| Ok((*file_id, file.metadata().map_err(|e| e.to_string())?.len())))
+            .collect()
+    }
+
+    /// Starts saving pages to `log`, for a transaction that began when the
+    /// files had `lengths`. Nothing may be dirty yet.
+    pub fn begin_undo(&mut self, log: File, lengths: HashMap<u32, u64>) {
+        self.undo = Some(Undo { log, lengths, saved: HashSet::new() });
+    }
+
+    pub fn end_undo(&mut self) {
+        self.undo = None;
+    }
+
+    pub fn read<R>(&mut self, id: PageId, f: impl FnOnce(&[u8]) -> R) -> Result<R, String> {
+        let frame = self.fetch(id)?;
+        Ok(f(&frame.data[..]))
+    }
+
+    pub fn write<R>(&mut self, id: PageId, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, String> {
+        let frame = self.fetch(id)?;
+        frame.dirty = true;
+        Ok(f(&mut frame.data[..]))
+    }
+
+    /// Writes every dirty page back and syncs the files.
+    pub fn flush(&mut self) -> Result<(), String> {
+        let mut dirty: Vec<PageId> = self.frames.iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(id, _)| *id)
+            .collect();
+        dirty.sort();
+        for id in dirty {
+            let data = self.frames[&id].data.clone();
+            self.write_page(id, &data[..])?;
+            if let Some(frame) = self.frames.get_mut(&id) {
+                frame.dirty = false;
+            }
+        }
+        for file in self.files.values() {
+            file.sync_data().map_err(|e| format!("Cannot sync page file: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn fetch(&mut self, id: PageId) -> Result<&mut Frame, String> {
+        self.clock += 1;
+        if !self.frames.contains_key(&id) {
+            if self.frames.len() >= self.capacity {
+                self.evict()?;
+            }
+            let data = self.read_page(id)?;
+            self.frames.insert(id, Frame { data, dirty: false, last_used: 0 });
+        }
+        let frame = self.frames.get_mut(&id).ok_or("Page missing from the buffer pool")?;
+        frame.last_used = self.clock;
+        Ok(frame)
+    }
+
+    fn evict(&mut self) -> Result<(), String> {
+        let Some(victim) = self.frames.iter().min_by_key(|(_, frame)| frame.last_used).map(|(id, _)| *id) else {
+            return Ok(());
+        };
+        if let Some(frame) = self.frames.remove(&victim).filter(|frame| frame.dirty) {
+            self.write_page(victim, &frame.data[..])?;
+        }
+        Ok(())
+    }
+
+    /// Pages past the end of the file read as zeros, an empty page.
+    fn read_page(&mut self, (file_id, page_no): PageId) -> Result<Box<[u8; PAGE_SIZE]>, String> {
+        let mut data = Box::new([0u8; PAGE_SIZE]);
+        let file = self.file(file_id)?;
+        let len = file.metadata().map_err(|e| e.to_string())?.len();
+        let offset = page_no * PAGE_SIZE as u64;
+        if offset < len {
+            file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+            let available = ((len - offset) as usize).min(PAGE_SIZE);
+            file.read_exact(&mut data[..available]).map_err(|e| format!("Cannot read page {}: {}", page_no, e))?;
+        }
+        Ok(data)
+    }
+
+    fn write_page(&mut self, (file_id, page_no): PageId, data: &[u8]) -> Result<(), String> {
+        self.save_original((file_id, page_no))?;
+        let file = self.file(file_id)?;
+        file.seek(SeekFrom::Start(page_no * PAGE_SIZE as u64)).map_err(|e| e.to_string())?;
+        file.write_all(data).map_err(|e| format!("Cannot write page {}: {}", page_no, e))
+    }
+
+    /// Appends the page as it is on disk to the undo log, and syncs the log,
+    /// unless no transaction is open, the page is new in it, or it was saved
+    /// already.
+    fn save_original(&mut self, id: PageId) -> Result<(), String> {
+        let Some(mut undo) = self.undo.take() else {
+            return Ok(());
+        };
+        let result = match undo.lengths.get(&id.0) {
+            Some(len) if id.1 * (PAGE_SIZE as u64) < *len && !undo.saved.contains(&id) => {
+                self.read_page(id).and_then(|data| {
+                    let mut entry = Vec::with_capacity(UNDO_ENTRY_SIZE);
+                    entry.extend(id.0.to_le_bytes());
+                    entry.extend(id.1.to_le_bytes());
+                    entry.extend(&data[..]);
+                    undo.log.write_all(&entry).and_then(|_| undo.log.sync_data())
+                        .map_err(|e| format!("Cannot write the undo log: {}", e))?;
+                    undo.saved.insert(id);
+                    Ok(())
+                })
+            },
+            _ => Ok(()),
+        };
+        self.undo = Some(undo);
+        result
+    }
+
+    fn file(&mut self, file_id: u32) -> Result<&mut File, String> {
+        self.files.get_mut(&file_id).ok_or_else(|| format!("Page file {} is not open", file_id))
+    }
+}
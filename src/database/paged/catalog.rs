@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::database::schema::Table;
+
+pub const CATALOG_FILE: &str = "catalog.json";
+
+/// Layout of the row records in the heap files. Version 1 holds a row's
+/// values in column order; version 0, read from catalogs without the
+/// field, named the column of every value.
+pub const RECORD_FORMAT: u32 = 1;
+
+/// Table definitions and heap bookkeeping for a paged database, written at
+/// every checkpoint.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Catalog {
+    #[serde(default)]
+    pub record_format: u32,
+    pub lsn: u64,
+    pub next_file_id: u32,
+    pub tables: BTreeMap<String, TableEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TableEntry {
+    pub file_id: u32,
+    /// Column names and types; `rows` is always empty.
+    pub schema: Table,
+    pub next_id: usize,
+    pub rows: usize,
+    pub pages: u64,
+}
+
+impl Catalog {
+    /// Returns `None` when the directory holds no catalog yet.
+    pub fn load(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(CATALOG_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|e| format!("Corrupt catalog '{}': {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Cannot read '{}': {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let path = dir.join(CATALOG_FILE);
+        let temp_path = path.with_extension("tmp");
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&temp_path, json).map_err(|e| format!("Cannot write '{}': {}", temp_path.display(), e))?;
+        std::fs::rename(&temp_path, &path).map_err(|e| format!("Cannot write '{}': {}", path.display(), e))
+    }
+}
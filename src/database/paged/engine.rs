@@ -0,0 +1,941 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use crate::database::compression::Compression;
+use crate::database::deadline::Deadline;
+use crate::database::engine::{StorageEngine, StorageLimits};
+use crate::database::kv::KvStore;
+use crate::database::schema::{ColumnType, Row, RowIdRange, Table};
+use crate::database::storage::Storage;
+use super::buffer_pool::{BufferPool, UNDO_ENTRY_SIZE};
+use super::catalog::{Catalog, TableEntry, CATALOG_FILE, RECORD_FORMAT};
+use super::heap::{HeapFile, NO_IDS};
+use super::page::{self, MAX_RECORD_SIZE, PAGE_SIZE};
+
+/// What `begin` saves for `rollback`: the catalog, the free-space maps, the
+/// length of every heap file and the undo log of overwritten pages. It is
+/// removed on commit, so finding it when opening means a transaction was
+/// cut short by a crash.
+const ROLLBACK_DIR: &str = "rollback";
+/// Where `begin` prepares the rollback directory before renaming it into place.
+const NEW_ROLLBACK_DIR: &str = "rollback.new";
+const UNDO_LOG: &str = "undo";
+const FILE_LENGTHS: &str = "lengths.json";
+
+/// For each value of an indexed column, the rows holding it and the page
+/// each of them is in.
+type PageIndex = HashMap<String, BTreeMap<usize, u64>>;
+
+/// The indexes of one table, by column.
+type TableIndexes = BTreeMap<String, PageIndex>;
+
+/// Keeps each table in a heap file of fixed-size pages inside one directory,
+/// reading pages through a shared buffer pool so that only the pages in use
+/// need to be in memory. Changes reach the files when pages are evicted and
+/// at each checkpoint, which also writes the catalog.
+#[derive(Debug)]
+pub struct PagedEngine {
+    dir: PathBuf,
+    catalog: Catalog,
+    heaps: HashMap<String, HeapFile>,
+    /// Reads update the pool, so it is borrowed mutably even by `&self` methods.
+    pool: RefCell<BufferPool>,
+    limits: StorageLimits,
+    lsn: u64,
+    /// LSN to return to on rollback while a transaction is open.
+    savepoint: Option<u64>,
+    /// Heap files of tables dropped in the open transaction, removed on commit.
+    dropped: Vec<u32>,
+    deadline: Deadline,
+    /// Index contents, kept in memory only: the catalog lists the indexed
+    /// columns, and a table's indexes are built by reading it the first
+    /// time one of them is used.
+    indexes: RefCell<HashMap<String, TableIndexes>>,
+}
+
+impl PagedEngine {
+    /// Opens the database in `dir`, creating the directory if needed, with a
+    /// buffer pool of `pool_pages` pages. Also returns whether the database is new.
+    /// A transaction left open by a crash is rolled back first.
+    pub fn open(dir: &Path, pool_pages: usize) -> Result<(Self, bool), String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Cannot create '{}': {}", dir.display(), e))?;
+        remove_dir(&dir.join(NEW_ROLLBACK_DIR))?;
+        if restore_savepoint(dir)? {
+            eprintln!("Rolled back a transaction left open in {}", dir.display());
+        }
+        let (catalog, created) = match Catalog::load(dir)? {
+            Some(catalog) => (catalog, false),
+            None => (Catalog { record_format: RECORD_FORMAT, ..Catalog::default() }, true),
+        };
+
+        let mut engine = PagedEngine {
+            dir: dir.to_path_buf(),
+            lsn: catalog.lsn,
+            catalog,
+            heaps: HashMap::new(),
+            pool: RefCell::new(BufferPool::new(pool_pages)),
+            limits: StorageLimits::default(),
+            savepoint: None,
+            dropped: Vec::new(),
+            deadline: Deadline::default(),
+            indexes: RefCell::new(HashMap::new()),
+        };
+        let pool = engine.pool.get_mut();
+        for (name, entry) in &engine.catalog.tables {
+            pool.open_file(entry.file_id, &engine.dir.join(heap_file(entry.file_id)))?;
+            // Pages evicted since the last checkpoint may have grown the file.
+            let pages = entry.pages.max(pool.file_pages(entry.file_id)?);
+            let heap = HeapFile::load(entry.file_id, pages, &engine.dir.join(fsm_file(entry.file_id)), pool)?;
+            engine.heaps.insert(name.clone(), heap);
+        }
+        if engine.catalog.record_format < RECORD_FORMAT {
+            engine.upgrade_records()?;
+        } else {
+            engine.recount()?;
+        }
+        Ok((engine, created))
+    }
+
+    /// Counts the rows of every table from its heap. The catalog is written
+    /// only at checkpoints while pages reach the heap files whenever they are
+    /// evicted, so after a crash a heap may hold rows the catalog does not
+    /// know of; going by the heap keeps their IDs from being given out again.
+    fn recount(&mut self) -> Result<(), String> {
+        let names: Vec<String> = self.catalog.tables.keys().cloned().collect();
+        for name in names {
+            let (rows, next_id) = self.read_id_bounds(&name)?;
+            let pages = self.heap(&name)?.page_count();
+            let Some(entry) = self.catalog.tables.get_mut(&name) else { continue };
+            if (rows, pages) != (entry.rows, entry.pages) || next_id > entry.next_id {
+                eprintln!("Table '{}' changed after the last checkpoint; recounted {} rows from its heap file", name, rows);
+            }
+            entry.rows = rows;
+            entry.pages = pages;
+            entry.next_id = entry.next_id.max(next_id);
+        }
+        Ok(())
+    }
+
+    /// Rewrites every table from records that name each column into
+    /// positional ones. Each table goes to a new heap file and the old files
+    /// are removed only after the catalog points at the new ones, so a crash
+    /// part way leaves the old database as it was.
+    fn upgrade_records(&mut self) -> Result<(), String> {
+        let names: Vec<String> = self.catalog.tables.keys().cloned().collect();
+        let mut old_files = Vec::new();
+        for name in &names {
+            let heap = self.heaps.get(name).ok_or_else(|| not_found(name))?;
+            let mut rows = Vec::new();
+            for page_no in 0..heap.page_count() {
+                for record in heap.read_page(self.pool.get_mut(), page_no)? {
+                    rows.push(decode_named(&record)?);
+                }
+            }
+
+            let file_id = self.catalog.next_file_id;
+            self.catalog.next_file_id += 1;
+            let pool = self.pool.get_mut();
+            pool.open_file(file_id, &self.dir.join(heap_file(file_id)))?;
+            pool.truncate_file(file_id)?;
+            if let Some(entry) = self.catalog.tables.get_mut(name) {
+                old_files.push(entry.file_id);
+                entry.file_id = file_id;
+                entry.rows = 0;
+            }
+            self.heaps.insert(name.clone(), HeapFile::new(file_id));
+            for (id, row) in rows {
+                self.store(name, id, row)?;
+            }
+        }
+        self.catalog.record_format = RECORD_FORMAT;
+        self.write_checkpoint()?;
+        for file_id in old_files {
+            self.pool.get_mut().close_file(file_id);
+            remove_files(&self.dir, file_id)?;
+        }
+        println!("Rewrote {} table(s) in {} with positional row records", names.len(), self.dir.display());
+        Ok(())
+    }
+
+    pub fn schema(&self, name: &str) -> Result<&Table, String> {
+        self.entry(name).map(|entry| &entry.schema)
+    }
+
+    /// Calls `f` with every row of the table, in page order.
+    pub fn scan(&self, name: &str, mut f: impl FnMut(usize, Row)) -> Result<(), String> {
+        let heap = self.heap(name)?;
+        let schema = self.schema(name)?;
+        let mut pool = self.pool.borrow_mut();
+        for page_no in 0..heap.page_count() {
+            self.deadline.check()?;
+            for record in heap.read_page(&mut pool, page_no)? {
+                let (id, values) = decode(&record)?;
+                f(id, schema.row(&values));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads every table into an in-memory copy of the database at the same LSN.
+    pub fn to_memory(&self) -> Result<StorageEngine, String> {
+        let mut tables = HashMap::new();
+        for (name, entry) in &self.catalog.tables {
+            let mut table = entry.schema.clone();
+            self.scan(name, |id, row| {
+                let values = table.values(row);
+                table.put_row(id, values);
+            })?;
+            tables.insert(name.clone(), table);
+        }
+        let mut copy = StorageEngine::from_tables(tables);
+        copy.set_lsn(self.lsn);
+        Ok(copy)
+    }
+
+    /// Writes dirty pages, free-space maps and the catalog, after which the
+    /// directory holds a consistent copy of the database.
+    fn write_checkpoint(&mut self) -> Result<(), String> {
+        self.pool.get_mut().flush()?;
+        for (name, heap) in &self.heaps {
+            heap.save_fsm(&self.dir.join(fsm_file(heap.file_id)))?;
+            if let Some(entry) = self.catalog.tables.get_mut(name) {
+                entry.pages = heap.page_count();
+            }
+        }
+        self.catalog.lsn = self.lsn;
+        self.catalog.save(&self.dir)
+    }
+
+    fn store(&mut self, name: &str, id: usize, row: Row) -> Result<(), String> {
+        let entry = self.catalog.tables.get_mut(name).ok_or_else(|| not_found(name))?;
+        let values = entry.schema.values(row);
+        let record = encode(id, &values)?;
+        let heap = self.heaps.get_mut(name).ok_or_else(|| not_found(name))?;
+        let page_no = heap.insert(self.pool.get_mut(), id, &record)?;
+        if let Some(indexes) = self.indexes.get_mut().get_mut(name) {
+            add_to_indexes(indexes, &entry.schema, id, &values, page_no);
+        }
+        entry.next_id = entry.next_id.max(id + 1);
+        entry.rows += 1;
+        Ok(())
+    }
+
+    /// Passes each row matching `column = value` through `change`, which
+    /// returns the new row or `None` to delete it, and rewrites the pages
+    /// touched. Rows that grow out of their page move to another one. With
+    /// an index on `column`, only the pages it lists are read.
+    fn rewrite_matching(&mut self, name: &str, column: &str, value: &str, change: impl Fn(Row) -> Option<Row>) -> Result<usize, String> {
+        let pages: Vec<u64> = match self.indexed(name, column, value)? {
+            Some(rows) => rows.into_values().collect::<BTreeSet<u64>>().into_iter().collect(),
+            None => (0..self.heap(name)?.page_count()).collect(),
+        };
+        let schema = &self.catalog.tables.get(name).ok_or_else(|| not_found(name))?.schema;
+        let Some(position) = schema.column_index(column) else {
+            return Ok(0);
+        };
+        let heap = self.heaps.get_mut(name).ok_or_else(|| not_found(name))?;
+        let pool = self.pool.get_mut();
+        let mut indexes = self.indexes.get_mut().get_mut(name);
+        let mut matched = 0;
+        let mut moved = Vec::new();
+
+        for page_no in pages {
+            let records = heap.read_page(pool, page_no)?;
+            let mut kept = Vec::with_capacity(records.len());
+            let mut changed = false;
+            for record in records {
+                let (id, values) = decode(&record)?;
+                if values.get(position).is_none_or(|v| v != value) {
+                    kept.push(record);
+                    continue;
+                }
+                matched += 1;
+                changed = true;
+                if let Some(indexes) = indexes.as_deref_mut() {
+                    remove_from_indexes(indexes, schema, id, &values);
+                }
+                if let Some(row) = change(schema.row(&values)) {
+                    let values = schema.values(row);
+                    if let Some(indexes) = indexes.as_deref_mut() {
+                        add_to_indexes(indexes, schema, id, &values, page_no);
+                    }
+                    kept.push(encode(id, &values)?);
+                }
+            }
+            if changed {
+                moved.extend(kept.split_off(page::fitting(&kept)));
+                heap.rewrite_page(pool, page_no, &kept)?;
+            }
+        }
+        for record in moved {
+            let (id, values) = decode(&record)?;
+            let page_no = heap.insert(pool, id, &record)?;
+            if let Some(indexes) = indexes.as_deref_mut() {
+                add_to_indexes(indexes, schema, id, &values, page_no);
+            }
+        }
+        Ok(matched)
+    }
+
+    /// The rows whose `column` holds `value` and their pages, or `None` when
+    /// the column has no index. Builds the table's indexes if needed.
+    fn indexed(&self, name: &str, column: &str, value: &str) -> Result<Option<BTreeMap<usize, u64>>, String> {
+        if !self.schema(name)?.indexes.iter().any(|c| c == column) {
+            return Ok(None);
+        }
+        if !self.indexes.borrow().contains_key(name) {
+            self.build_indexes(name)?;
+        }
+        let indexes = self.indexes.borrow();
+        Ok(Some(indexes.get(name)
+            .and_then(|indexes| indexes.get(column))
+            .and_then(|index| index.get(value))
+            .cloned()
+            .unwrap_or_default()))
+    }
+
+    /// Reads every page of the table to build the indexes the catalog lists.
+    fn build_indexes(&self, name: &str) -> Result<(), String> {
+        let heap = self.heap(name)?;
+        let schema = self.schema(name)?;
+        let mut built: TableIndexes = schema.indexes.iter()
+            .map(|column| (column.clone(), PageIndex::new()))
+            .collect();
+        let mut pool = self.pool.borrow_mut();
+        for page_no in 0..heap.page_count() {
+            self.deadline.check()?;
+            for record in heap.read_page(&mut pool, page_no)? {
+                let (id, values) = decode(&record)?;
+                add_to_indexes(&mut built, schema, id, &values, page_no);
+            }
+        }
+        self.indexes.borrow_mut().insert(name.to_string(), built);
+        Ok(())
+    }
+
+    /// Reads every page of the table to learn the row IDs each one holds.
+    /// Returns the number of rows and the ID after the highest.
+    fn read_id_bounds(&self, name: &str) -> Result<(usize, usize), String> {
+        let heap = self.heap(name)?;
+        let mut pool = self.pool.borrow_mut();
+        let mut ids = Vec::new();
+        let (mut rows, mut next_id) = (0, 0);
+        for page_no in 0..heap.page_count() {
+            self.deadline.check()?;
+            let mut bounds = NO_IDS;
+            for record in heap.read_page(&mut pool, page_no)? {
+                let (id, _) = decode(&record)?;
+                bounds = (bounds.0.min(id), bounds.1.max(id));
+                rows += 1;
+                next_id = next_id.max(id + 1);
+            }
+            ids.push(bounds);
+        }
+        heap.set_id_bounds(ids);
+        Ok((rows, next_id))
+    }
+
+    fn entry(&self, name: &str) -> Result<&TableEntry, String> {
+        self.catalog.tables.get(name).ok_or_else(|| not_found(name))
+    }
+
+    fn heap(&self, name: &str) -> Result<&HeapFile, String> {
+        self.heaps.get(name).ok_or_else(|| not_found(name))
+    }
+}
+
+impl Storage for PagedEngine {
+    fn create_typed_table(&mut self, name: &str, columns: Vec<String>, types: Vec<ColumnType>) -> Result<(), String> {
+        if self.has_table(name) {
+            return Err(format!("Table '{}' already exists", name));
+        }
+        if self.limits.max_tables > 0 && self.catalog.tables.len() >= self.limits.max_tables {
+            return Err(format!("Table limit of {} reached", self.limits.max_tables));
+        }
+        if columns.len() != types.len() {
+            return Err("Every column needs exactly one type".to_string());
+        }
+
+        let file_id = self.catalog.next_file_id;
+        let pool = self.pool.get_mut();
+        pool.open_file(file_id, &self.dir.join(heap_file(file_id)))?;
+        // A crash may have left a file behind under an ID that was never checkpointed.
+        pool.truncate_file(file_id)?;
+
+        self.catalog.next_file_id += 1;
+        self.catalog.tables.insert(name.to_string(), TableEntry {
+            file_id,
+            schema: Table::with_types(columns, types),
+            next_id: 0,
+            rows: 0,
+            pages: 0,
+        });
+        self.heaps.insert(name.to_string(), HeapFile::new(file_id));
+        self.lsn += 1;
+        Ok(())
+    }
+
+    /// In a transaction, the table's files stay until commit in case of rollback.
+    fn drop_table(&mut self, name: &str) -> Result<(), String> {
+        let entry = self.catalog.tables.remove(name).ok_or_else(|| not_found(name))?;
+        self.heaps.remove(name);
+        self.indexes.get_mut().remove(name);
+        self.pool.get_mut().close_file(entry.file_id);
+        if self.savepoint.is_some() {
+            self.dropped.push(entry.file_id);
+        } else {
+            remove_files(&self.dir, entry.file_id)?;
+        }
+        self.lsn += 1;
+        Ok(())
+    }
+
+    fn has_table(&self, name: &str) -> bool {
+        self.catalog.tables.contains_key(name)
+    }
+
+    fn list_tables(&self) -> Vec<&String> {
+        self.catalog.tables.keys().collect()
+    }
+
+    fn describe_table(&self, name: &str) -> Result<&Vec<String>, String> {
+        self.schema(name).map(|table| &table.columns)
+    }
+
+    fn describe_columns(&self, name: &str) -> Result<Vec<(String, ColumnType)>, String> {
+        let table = self.schema(name)?;
+        Ok(table.columns.iter()
+            .enumerate()
+            .map(|(index, column)| (column.clone(), table.column_type(index)))
+            .collect())
+    }
+
+    fn insert_row(&mut self, name: &str, row: Row) -> Result<usize, String> {
+        let entry = self.entry(name)?;
+        let max_rows = self.limits.max_rows_per_table;
+        if max_rows > 0 && entry.rows >= max_rows {
+            return Err(format!("Table '{}' has reached its limit of {} rows", name, max_rows));
+        }
+        let row = entry.schema.coerce_row(row)?;
+        let id = entry.next_id;
+        self.store(name, id, row)?;
+        self.lsn += 1;
+        Ok(id)
+    }
+
+    /// IDs below the next one may be taken, and the heap has no index by
+    /// ID, so those cost a scan of the table.
+    fn insert_row_at(&mut self, name: &str, id: usize, row: Row) -> Result<usize, String> {
+        let entry = self.entry(name)?;
+        let max_rows = self.limits.max_rows_per_table;
+        if max_rows > 0 && entry.rows >= max_rows {
+            return Err(format!("Table '{}' has reached its limit of {} rows", name, max_rows));
+        }
+        let row = entry.schema.coerce_row(row)?;
+        if id < entry.next_id {
+            let mut taken = false;
+            self.scan(name, |existing, _| taken |= existing == id)?;
+            if taken {
+                return Err(format!("Row ID {} already exists", id));
+            }
+        }
+        self.store(name, id, row)?;
+        self.lsn += 1;
+        Ok(id)
+    }
+
+    /// Heap files are unordered, so scans are sorted by row ID here.
+    fn select_all(&self, name: &str) -> Result<Vec<(usize, Row)>, String> {
+        let mut rows = Vec::new();
+        self.scan(name, |id, row| rows.push((id, row)))?;
+        rows.sort_unstable_by_key(|(id, _)| *id);
+        Ok(rows)
+    }
+
+    /// Pages are not kept in ID order, so the batch reads the pages whose
+    /// row IDs reach into `range`, nearest its start first, until no page
+    /// left can hold a row nearer than the `limit` found.
+    fn select_batch(&self, name: &str, range: RowIdRange, filter: Option<(&str, &str)>, descending: bool, limit: usize) -> Result<Vec<(usize, Row)>, String> {
+        let heap = self.heap(name)?;
+        let pages = match heap.pages_in(range, descending) {
+            Some(pages) => pages,
+            None => {
+                self.read_id_bounds(name)?;
+                heap.pages_in(range, descending).unwrap_or_default()
+            }
+        };
+
+        let schema = self.schema(name)?;
+        let filter = match filter {
+            Some((column, value)) => match schema.column_index(column) {
+                Some(position) => Some((position, value)),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+        let mut nearest = BTreeMap::new();
+        let mut pool = self.pool.borrow_mut();
+        for (page_no, (low, high)) in pages {
+            let beyond = if descending {
+                nearest.first_key_value().is_some_and(|(id, _)| high < *id)
+            } else {
+                nearest.last_key_value().is_some_and(|(id, _)| low > *id)
+            };
+            if nearest.len() >= limit && beyond {
+                break;
+            }
+            self.deadline.check()?;
+            for record in heap.read_page(&mut pool, page_no)? {
+                let (id, values) = decode(&record)?;
+                let matches = filter.is_none_or(|(position, value)| values.get(position).is_some_and(|v| v == value));
+                if !matches || !range.contains(&id) {
+                    continue;
+                }
+                nearest.insert(id, values);
+                if nearest.len() > limit {
+                    if descending {
+                        nearest.pop_first();
+                    } else {
+                        nearest.pop_last();
+                    }
+                }
+            }
+        }
+        let rows = nearest.into_iter().map(|(id, values)| (id, schema.row(&values)));
+        Ok(if descending { rows.rev().collect() } else { rows.collect() })
+    }
+
+    /// With an index on `column`, reads only the pages holding matches.
+    fn select_where(&self, name: &str, column: &str, value: &str) -> Result<Vec<(usize, Row)>, String> {
+        if let Some(matches) = self.indexed(name, column, value)? {
+            let mut by_page: BTreeMap<u64, BTreeSet<usize>> = BTreeMap::new();
+            for (id, page_no) in matches {
+                by_page.entry(page_no).or_default().insert(id);
+            }
+            let heap = self.heap(name)?;
+            let schema = self.schema(name)?;
+            let mut pool = self.pool.borrow_mut();
+            let mut rows = Vec::new();
+            for (page_no, ids) in by_page {
+                self.deadline.check()?;
+                for record in heap.read_page(&mut pool, page_no)? {
+                    let (id, values) = decode(&record)?;
+                    if ids.contains(&id) {
+                        rows.push((id, schema.row(&values)));
+                    }
+                }
+            }
+            rows.sort_unstable_by_key(|(id, _)| *id);
+            return Ok(rows);
+        }
+        let mut rows = Vec::new();
+        self.scan(name, |id, row| {
+            if row.get(column).is_some_and(|v| v == value) {
+                rows.push((id, row));
+            }
+        })?;
+        rows.sort_unstable_by_key(|(id, _)| *id);
+        Ok(rows)
+    }
+
+    fn create_index(&mut self, name: &str, column: &str) -> Result<(), String> {
+        let entry = self.catalog.tables.get_mut(name).ok_or_else(|| not_found(name))?;
+        entry.schema.create_index(column)?;
+        self.indexes.get_mut().remove(name);
+        self.lsn += 1;
+        Ok(())
+    }
+
+    fn drop_index(&mut self, name: &str, column: &str) -> Result<(), String> {
+        let entry = self.catalog.tables.get_mut(name).ok_or_else(|| not_found(name))?;
+        entry.schema.drop_index(column)?;
+        if let Some(indexes) = self.indexes.get_mut().get_mut(name) {
+            indexes.remove(column);
+        }
+        self.lsn += 1;
+        Ok(())
+    }
+
+    fn list_indexes(&self, name: &str) -> Result<Vec<String>, String> {
+        Ok(self.schema(name)?.indexes.clone())
+    }
+
+    fn index_lookup(&self, name: &str, column: &str, value: &str) -> Result<Option<Vec<usize>>, String> {
+        Ok(self.indexed(name, column, value)?.map(|rows| rows.into_keys().collect()))
+    }
+
+    fn update_where(&mut self, name: &str, column: &str, value: &str, target: &str, new_value: &str) -> Result<usize, String> {
+        if self.schema(name)?.column_index(target).is_none() {
+            return Err(format!("Column '{}' not found", target));
+        }
+        let updated = self.rewrite_matching(name, column, value, |mut row| {
+            row.insert(target.to_string(), new_value.to_string());
+            Some(row)
+        })?;
+        if updated > 0 {
+            self.lsn += 1;
+        }
+        Ok(updated)
+    }
+
+    fn delete_where(&mut self, name: &str, column: &str, value: &str) -> Result<usize, String> {
+        let deleted = self.rewrite_matching(name, column, value, |_| None)?;
+        if let Some(entry) = self.catalog.tables.get_mut(name) {
+            entry.rows -= deleted;
+        }
+        if deleted > 0 {
+            self.lsn += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Checkpoints and saves the catalog and free-space maps for `rollback`.
+    /// Pages go to the undo log only when first overwritten in the heap
+    /// files, so the transaction costs in proportion to what it changes
+    /// rather than to the size of the database.
+    fn begin(&mut self) -> Result<(), String> {
+        if self.savepoint.is_some() {
+            return Err("A transaction is already open".to_string());
+        }
+        self.write_checkpoint()?;
+        let new = self.dir.join(NEW_ROLLBACK_DIR);
+        remove_dir(&new)?;
+        std::fs::create_dir(&new).map_err(|e| format!("Cannot create '{}': {}", new.display(), e))?;
+        self.catalog.save(&new)?;
+        for heap in self.heaps.values() {
+            heap.save_fsm(&new.join(fsm_file(heap.file_id)))?;
+        }
+        let pool = self.pool.get_mut();
+        let lengths = pool.file_lengths()?;
+        let json = serde_json::to_string(&lengths).map_err(|e| e.to_string())?;
+        std::fs::write(new.join(FILE_LENGTHS), json).map_err(|e| format!("Cannot write '{}': {}", new.display(), e))?;
+        let log = File::create(new.join(UNDO_LOG)).map_err(|e| format!("Cannot create the undo log: {}", e))?;
+        let rollback = self.dir.join(ROLLBACK_DIR);
+        std::fs::rename(&new, &rollback).map_err(|e| format!("Cannot create '{}': {}", rollback.display(), e))?;
+        pool.begin_undo(log, lengths);
+        self.savepoint = Some(self.lsn);
+        Ok(())
+    }
+
+    /// Checkpoints, so that the transaction survives a crash from here on.
+    fn commit(&mut self) -> Result<(), String> {
+        if self.savepoint.is_none() {
+            return Err("No transaction is open".to_string());
+        }
+        self.write_checkpoint()?;
+        self.savepoint = None;
+        self.pool.get_mut().end_undo();
+        remove_dir(&self.dir.join(ROLLBACK_DIR))?;
+        for file_id in std::mem::take(&mut self.dropped) {
+            remove_files(&self.dir, file_id)?;
+        }
+        Ok(())
+    }
+
+    /// Discards everything since `begin`, including pages not yet written.
+    fn rollback(&mut self) -> Result<(), String> {
+        self.savepoint.take().ok_or("No transaction is open")?;
+        let capacity = self.pool.get_mut().capacity();
+        self.pool = RefCell::new(BufferPool::new(capacity));
+        restore_savepoint(&self.dir)?;
+
+        let limits = self.limits;
+        let dir = self.dir.clone();
+        *self = Self::open(&dir, capacity)?.0;
+        self.limits = limits;
+        Ok(())
+    }
+
+    fn limits(&self) -> StorageLimits {
+        self.limits
+    }
+
+    fn set_limits(&mut self, limits: StorageLimits) {
+        self.limits = limits;
+    }
+
+    fn set_deadline(&mut self, deadline: Deadline) {
+        self.deadline = deadline;
+    }
+
+    fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    fn merge(&mut self, other: &dyn Storage, replace: bool) -> Result<Vec<String>, String> {
+        let mut names: Vec<String> = other.list_tables().into_iter().cloned().collect();
+        names.sort();
+        if let (false, Some(name)) = (replace, names.iter().find(|name| self.has_table(name))) {
+            return Err(format!("Table '{}' already exists", name));
+        }
+        for name in &names {
+            if self.has_table(name) {
+                self.drop_table(name)?;
+            }
+            let (columns, types) = other.describe_columns(name)?.into_iter().unzip();
+            self.create_typed_table(name, columns, types)?;
+            for (id, row) in other.select_all(name)? {
+                self.store(name, id, row)?;
+            }
+            for column in other.list_indexes(name)? {
+                self.create_index(name, &column)?;
+            }
+        }
+        Ok(names)
+    }
+
+    fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, entry) in &self.catalog.tables {
+            let rows = match self.select_all(name) {
+                Ok(rows) => rows,
+                Err(e) => {
+                    problems.push(format!("{}: {}", name, e));
+                    continue;
+                }
+            };
+            if rows.len() != entry.rows {
+                problems.push(format!("{}: catalog lists {} rows, heap holds {}", name, entry.rows, rows.len()));
+            }
+            let mut seen = HashSet::new();
+            for (id, row) in &rows {
+                if !seen.insert(*id) {
+                    problems.push(format!("{}: row {} is stored twice", name, id));
+                }
+                problems.extend(entry.schema.check_row(*id, row).into_iter().map(|p| format!("{}: {}", name, p)));
+            }
+        }
+        problems
+    }
+
+    /// Also rewrites every heap without free space. Each table is read into
+    /// memory in turn to keep its rows in ID order; IDs stay as they were,
+    /// and so does the next one to be given out.
+    fn compact(&mut self) -> Result<usize, String> {
+        let purged = KvStore::new(self).purge_expired()?;
+        let names: Vec<String> = self.catalog.tables.keys().cloned().collect();
+        for name in names {
+            let rows = self.select_all(&name)?;
+
+            if let Some(heap) = self.heaps.get_mut(&name) {
+                heap.clear(self.pool.get_mut())?;
+            }
+            self.indexes.get_mut().remove(&name);
+            if let Some(entry) = self.catalog.tables.get_mut(&name) {
+                entry.rows = 0;
+            }
+            for (id, row) in rows {
+                self.store(&name, id, row)?;
+            }
+        }
+        self.lsn += 1;
+        Ok(purged)
+    }
+
+    fn checkpoint(&mut self) -> Result<bool, String> {
+        self.write_checkpoint()?;
+        Ok(true)
+    }
+
+    fn to_binary(&self, compression: Compression) -> Result<Vec<u8>, String> {
+        self.to_memory()?.to_binary(compression)
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        self.to_memory()?.to_json()
+    }
+}
+
+fn heap_file(file_id: u32) -> String {
+    format!("{}.heap", file_id)
+}
+
+fn fsm_file(file_id: u32) -> String {
+    format!("{}.fsm", file_id)
+}
+
+/// Removes a table's heap file and free-space map.
+fn remove_files(dir: &Path, file_id: u32) -> Result<(), String> {
+    for file in [heap_file(file_id), fsm_file(file_id)] {
+        let path = dir.join(file);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(format!("Cannot remove '{}': {}", path.display(), e));
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+fn not_found(name: &str) -> String {
+    format!("Table '{}' not found", name)
+}
+
+fn add_to_indexes(indexes: &mut TableIndexes, schema: &Table, id: usize, values: &[String], page_no: u64) {
+    for (column, index) in indexes {
+        if let Some(value) = schema.column_index(column).and_then(|position| values.get(position)) {
+            index.entry(value.clone()).or_default().insert(id, page_no);
+        }
+    }
+}
+
+fn remove_from_indexes(indexes: &mut TableIndexes, schema: &Table, id: usize, values: &[String]) {
+    for (column, index) in indexes {
+        let Some(value) = schema.column_index(column).and_then(|position| values.get(position)) else { continue };
+        if let Some(rows) = index.get_mut(value) {
+            rows.remove(&id);
+            if rows.is_empty() {
+                index.remove(value);
+            }
+        }
+    }
+}
+
+/// A record is the row ID followed by the row's values in column order.
+fn encode(id: usize, values: &[String]) -> Result<Vec<u8>, String> {
+    let record = bincode::serialize(&(id as u64, values)).map_err(|e| e.to_string())?;
+    if record.len() > MAX_RECORD_SIZE {
+        return Err(format!("Row {} takes {} bytes; the paged engine stores rows of up to {} bytes", id, record.len(), MAX_RECORD_SIZE));
+    }
+    Ok(record)
+}
+
+fn decode(record: &[u8]) -> Result<(usize, Vec<String>), String> {
+    let (id, values): (u64, Vec<String>) = bincode::deserialize(record).map_err(|e| format!("Corrupt row record: {}", e))?;
+    Ok((id as usize, values))
+}
+
+/// Records written before they were positional name the column of every value.
+fn decode_named(record: &[u8]) -> Result<(usize, Row), String> {
+    let (id, row): (u64, Row) = bincode::deserialize(record).map_err(|e| format!("Corrupt row record: {}", e))?;
+    Ok((id as usize, row))
+}
+
+/// Puts the files back as they were at `begin`: writes back the pages in
+/// the undo log, cuts each heap file to its length then, removes the files
+/// of tables created since, and restores the catalog and free-space maps.
+/// Returns whether a transaction was open.
+fn restore_savepoint(dir: &Path) -> Result<bool, String> {
+    let rollback = dir.join(ROLLBACK_DIR);
+    if !rollback.exists() {
+        return Ok(false);
+    }
+    let lengths = std::fs::read_to_string(rollback.join(FILE_LENGTHS)).map_err(|e| format!("Cannot read '{}': {}", rollback.display(), e))?;
+    let lengths: HashMap<u32, u64> = serde_json::from_str(&lengths).map_err(|e| format!("Corrupt rollback lengths: {}", e))?;
+    let undo = std::fs::read(rollback.join(UNDO_LOG)).map_err(|e| format!("Cannot read the undo log: {}", e))?;
+
+    let mut files: HashMap<u32, File> = HashMap::new();
+    for file_id in lengths.keys() {
+        let path = dir.join(heap_file(*file_id));
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(&path)
+            .map_err(|e| format!("Cannot open '{}': {}", path.display(), e))?;
+        files.insert(*file_id, file);
+    }
+    // An entry cut short by a crash was never synced, so its page was not
+    // overwritten and needs nothing back.
+    for entry in undo.chunks_exact(UNDO_ENTRY_SIZE) {
+        let file_id = u32::from_le_bytes(entry[..4].try_into().unwrap_or_default());
+        let page_no = u64::from_le_bytes(entry[4..12].try_into().unwrap_or_default());
+        let file = files.get_mut(&file_id).ok_or_else(|| format!("Undo log names unknown heap file {}", file_id))?;
+        file.seek(SeekFrom::Start(page_no * PAGE_SIZE as u64))
+            .and_then(|_| file.write_all(&entry[12..]))
+            .map_err(|e| format!("Cannot restore page {} of heap file {}: {}", page_no, file_id, e))?;
+    }
+    for (file_id, file) in &files {
+        file.set_len(lengths[file_id]).and_then(|_| file.sync_all())
+            .map_err(|e| format!("Cannot restore heap file {}: {}", file_id, e))?;
+    }
+
+    for path in files_in(dir)? {
+        let created = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u32>().ok())
+            .is_some_and(|file_id| !lengths.contains_key(&file_id));
+        if created {
+            std::fs::remove_file(&path).map_err(|e| format!("Cannot remove '{}': {}", path.display(), e))?;
+        }
+    }
+    for path in files_in(&rollback)? {
+        if let Some(file_name) = path.file_name().filter(|name| *name == CATALOG_FILE || path.extension().is_some_and(|ext| ext == "fsm")) {
+            std::fs::copy(&path, dir.join(file_name)).map_err(|e| format!("Cannot copy '{}': {}", path.display(), e))?;
+        }
+    }
+    remove_dir(&rollback)?;
+    Ok(true)
+}
+
+fn files_in(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Cannot read '{}': {}", dir.display(), e))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn remove_dir(dir: &Path) -> Result<(), String> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Cannot remove '{}': {}", dir.display(), e)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, email: &str) -> Row {
+        let mut row = Row::new();
+        row.insert("name".to_string(), name.to_string());
+        row.insert("email".to_string(), email.to_string());
+        row
+    }
+
+    #[test]
+    fn records_hold_values_without_column_names() {
+        let values = vec!["alice".to_string(), "alice@example.com".to_string()];
+        let record = encode(7, &values).unwrap();
+        assert!(!record.windows(5).any(|window| window == b"email"));
+        assert!(record.len() < bincode::serialize(&(7u64, row("alice", "alice@example.com"))).unwrap().len());
+        assert_eq!(decode(&record).unwrap(), (7, values));
+    }
+
+    #[test]
+    fn named_records_are_rewritten_on_open() {
+        let dir = std::env::temp_dir().join(format!("atom-paged-upgrade-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (mut engine, _) = PagedEngine::open(&dir, 4).unwrap();
+        engine.create_table("t", vec!["name".to_string(), "email".to_string()]).unwrap();
+        // Writes records the way the engine did before they were positional.
+        for id in [3, 5, 9] {
+            let record = bincode::serialize(&(id as u64, row(&format!("user{}", id), "x@example.com"))).unwrap();
+            engine.heaps.get_mut("t").unwrap().insert(engine.pool.get_mut(), id, &record).unwrap();
+        }
+        let entry = engine.catalog.tables.get_mut("t").unwrap();
+        entry.rows = 3;
+        entry.next_id = 10;
+        let old_file = entry.file_id;
+        engine.catalog.record_format = 0;
+        engine.write_checkpoint().unwrap();
+        drop(engine);
+
+        let (engine, _) = PagedEngine::open(&dir, 4).unwrap();
+        assert_eq!(engine.catalog.record_format, RECORD_FORMAT);
+        assert!(!dir.join(heap_file(old_file)).exists());
+        let rows = engine.select_all("t").unwrap();
+        assert_eq!(rows.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [3, 5, 9]);
+        assert_eq!(rows[1].1, row("user5", "x@example.com"));
+        assert_eq!(engine.entry("t").unwrap().next_id, 10);
+        assert!(engine.check().is_empty());
+        drop(engine);
+
+        let (engine, _) = PagedEngine::open(&dir, 4).unwrap();
+        assert_eq!(engine.select_all("t").unwrap(), rows);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::path::Path;
+use crate::database::schema::RowIdRange;
+use super::buffer_pool::BufferPool;
+use super::page::{self, EMPTY_PAGE_SPACE, SLOT_SIZE};
+
+/// Lowest and highest row ID a page may hold; the low bound is above the
+/// high one for a page that has held none.
+pub type IdBounds = (usize, usize);
+
+pub const NO_IDS: IdBounds = (usize::MAX, 0);
+
+/// An unordered file of pages holding one table's records, together with
+/// its free-space map: the bytes still available in each page.
+#[derive(Debug)]
+pub struct HeapFile {
+    pub file_id: u32,
+    free: Vec<u16>,
+    /// Row IDs in each page, kept in memory only and so unknown for a heap
+    /// loaded from disk until `set_id_bounds`. Deletes leave the bounds as
+    /// they were, so they may be wider than what the page holds now.
+    ids: RefCell<Option<Vec<IdBounds>>>,
+}
+
+impl HeapFile {
+    pub fn new(file_id: u32) -> Self {
+        HeapFile { file_id, free: Vec::new(), ids: RefCell::new(Some(Vec::new())) }
+    }
+
+    /// Loads the free-space map saved with the heap, or rebuilds it from the
+    /// page headers when it is missing or does not match the page count.
+    pub fn load(file_id: u32, pages: u64, fsm_path: &Path, pool: &mut BufferPool) -> Result<Self, String> {
+        let saved = std::fs::read(fsm_path).unwrap_or_default();
+        let free: Vec<u16> = if saved.len() as u64 == pages * 2 {
+            saved.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect()
+        } else {
+            (0..pages)
+                .map(|page_no| pool.read((file_id, page_no), |data| page::free_space(data) as u16))
+                .collect::<Result<_, _>>()?
+        };
+        Ok(HeapFile { file_id, free, ids: RefCell::new(None) })
+    }
+
+    pub fn save_fsm(&self, path: &Path) -> Result<(), String> {
+        let bytes: Vec<u8> = self.free.iter().flat_map(|free| free.to_le_bytes()).collect();
+        std::fs::write(path, bytes).map_err(|e| format!("Cannot write '{}': {}", path.display(), e))
+    }
+
+    pub fn page_count(&self) -> u64 {
+        self.free.len() as u64
+    }
+
+    /// Stores the record of row `id` in the last page if it fits, else in
+    /// the first page with room for it, else in a new page. Returns the
+    /// number of the page it went to.
+    pub fn insert(&mut self, pool: &mut BufferPool, id: usize, record: &[u8]) -> Result<u64, String> {
+        let needed = record.len() + SLOT_SIZE;
+        let last = self.free.len().checked_sub(1).filter(|last| self.free[*last] as usize >= needed);
+        let page_no = match last.or_else(|| self.free.iter().position(|free| *free as usize >= needed)) {
+            Some(page_no) => page_no,
+            None => {
+                self.free.push(EMPTY_PAGE_SPACE as u16);
+                self.free.len() - 1
+            }
+        };
+        let (stored, free) = pool.write((self.file_id, page_no as u64), |data| {
+            (page::insert(data, record), page::free_space(data))
+        })?;
+        self.free[page_no] = free as u16;
+        if !stored {
+            return Err(format!("Page {} has less free space than its free-space map records", page_no));
+        }
+        if let Some(ids) = self.ids.get_mut() {
+            ids.resize(self.free.len(), NO_IDS);
+            let (low, high) = ids[page_no];
+            ids[page_no] = (low.min(id), high.max(id));
+        }
+        Ok(page_no as u64)
+    }
+
+    pub fn read_page(&self, pool: &mut BufferPool, page_no: u64) -> Result<Vec<Vec<u8>>, String> {
+        pool.read((self.file_id, page_no), |data| {
+            page::records(data).map(|records| records.into_iter().map(<[u8]>::to_vec).collect())
+        })?
+    }
+
+    /// Replaces a page's records; they must fit in one page.
+    pub fn rewrite_page(&mut self, pool: &mut BufferPool, page_no: u64, records: &[Vec<u8>]) -> Result<(), String> {
+        let free = pool.write((self.file_id, page_no), |data| {
+            page::rewrite(data, records);
+            page::free_space(data)
+        })?;
+        self.free[page_no as usize] = free as u16;
+        Ok(())
+    }
+
+    /// Records the row IDs in each page, read by a scan of every page.
+    pub fn set_id_bounds(&self, ids: Vec<IdBounds>) {
+        *self.ids.borrow_mut() = Some(ids);
+    }
+
+    /// Pages that may hold IDs in `range`, nearest its start first: by low
+    /// bound, or by high bound when `descending`. `None` until the bounds
+    /// are known.
+    pub fn pages_in(&self, range: RowIdRange, descending: bool) -> Option<Vec<(u64, IdBounds)>> {
+        let ids = self.ids.borrow();
+        let mut pages: Vec<(u64, IdBounds)> = ids.as_ref()?.iter()
+            .enumerate()
+            .filter(|(_, (low, high))| low <= high && overlaps(range, *low, *high))
+            .map(|(page_no, bounds)| (page_no as u64, *bounds))
+            .collect();
+        if descending {
+            pages.sort_unstable_by_key(|(_, (_, high))| std::cmp::Reverse(*high));
+        } else {
+            pages.sort_unstable_by_key(|(_, (low, _))| *low);
+        }
+        Some(pages)
+    }
+
+    /// Removes every page.
+    pub fn clear(&mut self, pool: &mut BufferPool) -> Result<(), String> {
+        self.free.clear();
+        self.set_id_bounds(Vec::new());
+        pool.truncate_file(self.file_id)
+    }
+}
+
+fn overlaps(range: RowIdRange, low: usize, high: usize) -> bool {
+    let above_start = match range.0 {
+        Bound::Included(start) => high >= start,
+        Bound::Excluded(start) => high > start,
+        Bound::Unbounded => true,
+    };
+    let below_end = match range.1 {
+        Bound::Included(end) => low <= end,
+        Bound::Excluded(end) => low < end,
+        Bound::Unbounded => true,
+    };
+    above_start && below_end
+}
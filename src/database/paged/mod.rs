@@ -0,0 +1,7 @@
+pub mod page;
+pub mod buffer_pool;
+pub mod heap;
+pub mod catalog;
+pub mod engine;
+
+pub use engine::PagedEngine;
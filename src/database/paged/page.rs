@@ -0,0 +1,90 @@
+// Slotted page layout. A page starts with a 4-byte header (slot count and
+// the offset where record data begins), followed by one 4-byte slot
+// (offset, length) per record. Records are packed from the end of the page
+// towards the slots, so the free space is always the gap between the two.
+// An all-zero page is a valid empty page.
+
+pub const PAGE_SIZE: usize = 4096;
+const HEADER_SIZE: usize = 4;
+pub const SLOT_SIZE: usize = 4;
+/// Free space in a new page.
+pub const EMPTY_PAGE_SPACE: usize = PAGE_SIZE - HEADER_SIZE;
+/// Largest record that fits in an empty page.
+pub const MAX_RECORD_SIZE: usize = EMPTY_PAGE_SPACE - SLOT_SIZE;
+
+fn read_u16(page: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([page[at], page[at + 1]]) as usize
+}
+
+fn write_u16(page: &mut [u8], at: usize, value: usize) {
+    page[at..at + 2].copy_from_slice(&(value as u16).to_le_bytes());
+}
+
+fn slot_count(page: &[u8]) -> usize {
+    read_u16(page, 0)
+}
+
+fn data_start(page: &[u8]) -> usize {
+    match read_u16(page, 2) {
+        0 => PAGE_SIZE,
+        start => start,
+    }
+}
+
+/// Bytes available for one more record, slot included.
+pub fn free_space(page: &[u8]) -> usize {
+    data_start(page).saturating_sub(HEADER_SIZE + SLOT_SIZE * slot_count(page))
+}
+
+/// How many of `records`, taken in order, fit together in one page.
+pub fn fitting(records: &[Vec<u8>]) -> usize {
+    let mut space = EMPTY_PAGE_SPACE;
+    records.iter()
+        .take_while(|record| match space.checked_sub(record.len() + SLOT_SIZE) {
+            Some(left) => {
+                space = left;
+                true
+            },
+            None => false,
+        })
+        .count()
+}
+
+pub fn records(page: &[u8]) -> Result<Vec<&[u8]>, String> {
+    let count = slot_count(page);
+    if HEADER_SIZE + SLOT_SIZE * count > data_start(page) {
+        return Err("Corrupt page: slot directory overlaps record data".to_string());
+    }
+    (0..count)
+        .map(|slot| {
+            let at = HEADER_SIZE + SLOT_SIZE * slot;
+            let (offset, len) = (read_u16(page, at), read_u16(page, at + 2));
+            page.get(offset..offset + len).ok_or_else(|| format!("Corrupt page: slot {} points outside the page", slot))
+        })
+        .collect()
+}
+
+/// Adds a record if it fits; returns whether it did.
+pub fn insert(page: &mut [u8], record: &[u8]) -> bool {
+    if record.len() + SLOT_SIZE > free_space(page) {
+        return false;
+    }
+    let count = slot_count(page);
+    let offset = data_start(page) - record.len();
+    page[offset..offset + record.len()].copy_from_slice(record);
+
+    let slot = HEADER_SIZE + SLOT_SIZE * count;
+    write_u16(page, slot, offset);
+    write_u16(page, slot + 2, record.len());
+    write_u16(page, 0, count + 1);
+    write_u16(page, 2, offset);
+    true
+}
+
+/// Replaces the page contents with `records`, which must fit (see `fitting`).
+pub fn rewrite(page: &mut [u8], records: &[Vec<u8>]) {
+    page.fill(0);
+    for record in records {
+        insert(page, record);
+    }
+}
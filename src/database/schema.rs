@@ -1,5 +1,75 @@
-use std::collections::HashMap;
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize};
+use super::deadline::Deadline;
+
+/// Bounds on row IDs, as taken by `BTreeMap::range`.
+pub type RowIdRange = (Bound<usize>, Bound<usize>);
+
+/// The IDs of the rows holding each value of an indexed column.
+pub type ColumnIndex = HashMap<String, BTreeSet<usize>>;
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Copy, Eq, Default)]
+pub enum ColumnType {
+    #[default]
+    Text,
+    Integer,
+    Float,
+    Boolean,
+}
+
+impl ColumnType {
+    /// Checks `value` against the type and returns its canonical spelling.
+    /// Empty values are accepted for every type.
+    pub fn coerce(&self, value: &str) -> Result<String, String> {
+        let trimmed = value.trim();
+        if trimmed.is_empty() || *self == ColumnType::Text {
+            return Ok(if trimmed.is_empty() { String::new() } else { value.to_string() });
+        }
+
+        let invalid = || format!("'{}' is not a valid {}", value, self);
+        match self {
+            ColumnType::Integer => trimmed.parse::<i64>().map(|v| v.to_string()).map_err(|_| invalid()),
+            ColumnType::Float => match trimmed.parse::<f64>() {
+                Ok(v) if v.is_finite() => Ok(v.to_string()),
+                _ => Err(invalid()),
+            },
+            ColumnType::Boolean => match trimmed.to_lowercase().as_str() {
+                "true" | "t" | "yes" | "y" | "1" => Ok("true".to_string()),
+                "false" | "f" | "no" | "n" | "0" => Ok("false".to_string()),
+                _ => Err(invalid()),
+            },
+            ColumnType::Text => Ok(value.to_string()),
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "string" | "varchar" => Ok(ColumnType::Text),
+            "int" | "integer" | "bigint" => Ok(ColumnType::Integer),
+            "float" | "real" | "double" => Ok(ColumnType::Float),
+            "bool" | "boolean" => Ok(ColumnType::Boolean),
+            _ => Err(format!("Unknown column type '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnType::Text => write!(f, "TEXT"),
+            ColumnType::Integer => write!(f, "INTEGER"),
+            ColumnType::Float => write!(f, "FLOAT"),
+            ColumnType::Boolean => write!(f, "BOOLEAN"),
+        }
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Eq)]
 pub struct Row {
@@ -26,45 +96,411 @@ impl Row {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Eq)]
+impl Default for Row {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone, Eq)]
 pub struct Table {
     pub columns: Vec<String>,
-    pub rows: HashMap<usize, Row>,
+    /// One entry per column; empty for tables created before column types existed.
+    #[serde(default)]
+    pub types: Vec<ColumnType>,
+    /// Rows clustered by row ID, so scans come back in ID order. Each row
+    /// holds its values in column order; column names are stored once, above.
+    pub rows: BTreeMap<usize, Vec<String>>,
+    /// Indexed columns, in the order the indexes were created.
+    #[serde(default)]
+    pub indexes: Vec<String>,
+    /// Contents of each index, rebuilt from the rows when loaded.
+    #[serde(skip)]
+    lookups: BTreeMap<String, ColumnIndex>,
+}
+
+/// A table as written to disk, generic over how each row is stored.
+#[derive(Deserialize)]
+pub struct StoredTable<R> {
+    columns: Vec<String>,
+    #[serde(default)]
+    types: Vec<ColumnType>,
+    rows: BTreeMap<usize, R>,
+    #[serde(default)]
+    indexes: Vec<String>,
+}
+
+/// A table in data files older than version 5, which have no index list.
+#[derive(Deserialize)]
+pub struct UnindexedTable {
+    columns: Vec<String>,
+    types: Vec<ColumnType>,
+    rows: BTreeMap<usize, Vec<String>>,
+}
+
+/// JSON written before rows were positional names the column of every value.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonRow {
+    Values(Vec<String>),
+    Named(Row),
+}
+
+impl StoredTable<Vec<String>> {
+    fn into_table(self) -> Table {
+        let mut table = Table::with_types(self.columns, self.types);
+        table.rows = self.rows;
+        table.set_indexes(self.indexes);
+        table
+    }
+}
+
+impl StoredTable<Row> {
+    /// Converts a table whose rows still name every column.
+    pub fn into_table(self) -> Table {
+        let mut table = Table::with_types(self.columns, self.types);
+        table.rows = self.rows.into_iter().map(|(id, row)| (id, table.values(row))).collect();
+        table
+    }
+}
+
+impl UnindexedTable {
+    pub fn into_table(self) -> Table {
+        let mut table = Table::with_types(self.columns, self.types);
+        table.rows = self.rows;
+        table
+    }
+}
+
+impl StoredTable<JsonRow> {
+    fn into_table(self) -> Table {
+        let mut table = Table::with_types(self.columns, self.types);
+        table.rows = self.rows.into_iter()
+            .map(|(id, row)| match row {
+                JsonRow::Values(values) => (id, values),
+                JsonRow::Named(row) => (id, table.values(row)),
+            })
+            .collect();
+        table.set_indexes(self.indexes);
+        table
+    }
+}
+
+impl<'de> Deserialize<'de> for Table {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            Ok(StoredTable::<JsonRow>::deserialize(deserializer)?.into_table())
+        } else {
+            Ok(StoredTable::<Vec<String>>::deserialize(deserializer)?.into_table())
+        }
+    }
 }
 
 impl Table {
     pub fn new(columns: Vec<String>) -> Self {
+        let types = vec![ColumnType::Text; columns.len()];
+        Self::with_types(columns, types)
+    }
+
+    pub fn with_types(columns: Vec<String>, types: Vec<ColumnType>) -> Self {
         Table {
             columns,
-            rows: HashMap::new(),
+            types,
+            rows: BTreeMap::new(),
+            indexes: Vec::new(),
+            lookups: BTreeMap::new(),
         }
     }
 
+    pub fn column_type(&self, index: usize) -> ColumnType {
+        self.types.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn column_index(&self, column: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == column)
+    }
+
+    /// Takes a row's values in column order; missing columns become empty.
+    pub fn values(&self, mut row: Row) -> Vec<String> {
+        self.columns.iter().map(|column| row.data.remove(column).unwrap_or_default()).collect()
+    }
+
+    /// Names each of a stored row's values by its column.
+    pub fn row(&self, values: &[String]) -> Row {
+        Row { data: self.columns.iter().cloned().zip(values.iter().cloned()).collect() }
+    }
+
     pub fn insert_row(&mut self, row: Row) -> Result<usize, String> {
+        let values = self.values(self.coerce_row(row)?);
+        let mut row_id = self.rows.len();
+        while self.rows.contains_key(&row_id) {
+            row_id += 1;
+        }
+        self.put_row(row_id, values);
+        Ok(row_id)
+    }
+
+    /// Inserts under a given row ID, as restoring a dump does.
+    pub fn insert_row_at(&mut self, id: usize, row: Row) -> Result<usize, String> {
+        if self.rows.contains_key(&id) {
+            return Err(format!("Row ID {} already exists", id));
+        }
+        let values = self.values(self.coerce_row(row)?);
+        self.put_row(id, values);
+        Ok(id)
+    }
+
+    /// Stores values already in column order under `id`, replacing any row
+    /// there, and keeps the indexes up to date.
+    pub fn put_row(&mut self, id: usize, values: Vec<String>) {
+        if let Some(old) = self.rows.remove(&id) {
+            self.unindex(id, &old);
+        }
+        self.index(id, &values);
+        self.rows.insert(id, values);
+    }
+
+    /// Checks that `row` has every column and no others, and converts typed
+    /// values to their canonical form.
+    pub fn coerce_row(&self, mut row: Row) -> Result<Row, String> {
         if !row.validate_columns(&self.columns) {
             return Err("Row missing required columns".to_string());
         }
+        if let Some(unknown) = row.data.keys().find(|column| !self.columns.contains(column)) {
+            return Err(format!("Column '{}' not found", unknown));
+        }
 
-        let row_id = self.rows.len();
-        self.rows.insert(row_id, row);
-        Ok(row_id)
+        for (index, column) in self.columns.iter().enumerate() {
+            let column_type = self.column_type(index);
+            if column_type == ColumnType::Text {
+                continue;
+            }
+            if let Some(value) = row.data.get_mut(column) {
+                *value = column_type.coerce(value).map_err(|e| format!("Column '{}': {}", column, e))?;
+            }
+        }
+        Ok(row)
     }
 
-    pub fn get_row(&self, id: usize) -> Option<&Row> {
-        self.rows.get(&id)
+    pub fn get_row(&self, id: usize) -> Option<Row> {
+        self.rows.get(&id).map(|values| self.row(values))
     }
 
-    pub fn get_all_rows(&self) -> Vec<(usize, &Row)> {
-        self.rows.iter().map(|(id, row)| (*id, row)).collect()
+    pub fn get_all_rows(&self, deadline: &Deadline) -> Result<Vec<(usize, Row)>, String> {
+        self.collect(self.rows.iter(), deadline)
     }
 
-    pub fn select_where(&self, column: &str, value: &str) -> Vec<(usize, &Row)> {
-        self.rows
-            .iter()
-            .filter(|(_, row)| {
-                row.get(column).map_or(false, |v| v == value)
-            })
-            .map(|(id, row)| (*id, row))
+    /// Rows whose ID falls in `range`, without visiting the others.
+    pub fn range(&self, range: RowIdRange, deadline: &Deadline) -> Result<Vec<(usize, Row)>, String> {
+        if is_empty_range(&range) {
+            return Ok(Vec::new());
+        }
+        self.collect(self.rows.range(range), deadline)
+    }
+
+    /// Answered from the column's index when it has one.
+    pub fn select_where(&self, column: &str, value: &str, deadline: &Deadline) -> Result<Vec<(usize, Row)>, String> {
+        if let Some(ids) = self.lookup(column, value) {
+            return Ok(ids.into_iter().filter_map(|id| self.get_row(id).map(|row| (id, row))).collect());
+        }
+        let Some(index) = self.column_index(column) else {
+            return Ok(Vec::new());
+        };
+        let mut rows = Vec::new();
+        for (visited, (id, values)) in self.rows.iter().enumerate() {
+            deadline.check_row(visited)?;
+            if values.get(index).is_some_and(|v| v == value) {
+                rows.push((*id, self.row(values)));
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Up to `limit` rows in `range` whose `filter` column, if given, holds
+    /// the value, in ID order or its reverse.
+    pub fn batch(&self, range: RowIdRange, filter: Option<(&str, &str)>, descending: bool, limit: usize, deadline: &Deadline) -> Result<Vec<(usize, Row)>, String> {
+        if is_empty_range(&range) {
+            return Ok(Vec::new());
+        }
+        let filter = match filter {
+            Some((column, value)) => match self.column_index(column) {
+                Some(index) => Some((index, value)),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+        let rows: Box<dyn Iterator<Item = (&usize, &Vec<String>)>> = if descending {
+            Box::new(self.rows.range(range).rev())
+        } else {
+            Box::new(self.rows.range(range))
+        };
+        let mut batch = Vec::new();
+        for (visited, (id, values)) in rows.enumerate() {
+            if batch.len() >= limit {
+                break;
+            }
+            deadline.check_row(visited)?;
+            if filter.is_none_or(|(index, value)| values.get(index).is_some_and(|v| v == value)) {
+                batch.push((*id, self.row(values)));
+            }
+        }
+        Ok(batch)
+    }
+
+    fn collect<'r>(&self, rows: impl Iterator<Item = (&'r usize, &'r Vec<String>)>, deadline: &Deadline) -> Result<Vec<(usize, Row)>, String> {
+        let mut collected = Vec::new();
+        for (visited, (id, values)) in rows.enumerate() {
+            deadline.check_row(visited)?;
+            collected.push((*id, self.row(values)));
+        }
+        Ok(collected)
+    }
+
+    pub fn delete_where(&mut self, column: &str, value: &str) -> usize {
+        let ids = self.matching_ids(column, value);
+        for id in &ids {
+            if let Some(values) = self.rows.remove(id) {
+                self.unindex(*id, &values);
+            }
+        }
+        ids.len()
+    }
+
+    pub fn update_where(&mut self, column: &str, value: &str, target: &str, new_value: &str) -> Result<usize, String> {
+        let target = self.column_index(target).ok_or_else(|| format!("Column '{}' not found", target))?;
+        let ids = self.matching_ids(column, value);
+        for id in &ids {
+            if let Some(mut values) = self.rows.get(id).cloned() {
+                if let Some(slot) = values.get_mut(target) {
+                    *slot = new_value.to_string();
+                }
+                self.put_row(*id, values);
+            }
+        }
+        Ok(ids.len())
+    }
+
+    /// IDs of the rows whose `column` holds `value`, from the index when
+    /// there is one.
+    fn matching_ids(&self, column: &str, value: &str) -> Vec<usize> {
+        if let Some(ids) = self.lookup(column, value) {
+            return ids;
+        }
+        let Some(index) = self.column_index(column) else {
+            return Vec::new();
+        };
+        self.rows.iter()
+            .filter(|(_, values)| values.get(index).is_some_and(|v| v == value))
+            .map(|(id, _)| *id)
             .collect()
     }
+
+    /// Indexes `column`, which must not be indexed already.
+    pub fn create_index(&mut self, column: &str) -> Result<(), String> {
+        if self.column_index(column).is_none() {
+            return Err(format!("Column '{}' not found", column));
+        }
+        if self.indexes.iter().any(|c| c == column) {
+            return Err(format!("Column '{}' is already indexed", column));
+        }
+        self.indexes.push(column.to_string());
+        self.rebuild_indexes();
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, column: &str) -> Result<(), String> {
+        let position = self.indexes.iter().position(|c| c == column)
+            .ok_or_else(|| format!("Column '{}' has no index", column))?;
+        self.indexes.remove(position);
+        self.lookups.remove(column);
+        Ok(())
+    }
+
+    /// IDs of the rows whose `column` holds `value`, in ID order, or `None`
+    /// when the column has no index.
+    pub fn lookup(&self, column: &str, value: &str) -> Option<Vec<usize>> {
+        let index = self.lookups.get(column)?;
+        Some(index.get(value).map(|ids| ids.iter().copied().collect()).unwrap_or_default())
+    }
+
+    /// Replaces the index list and builds every index from the rows.
+    fn set_indexes(&mut self, indexes: Vec<String>) {
+        self.indexes = indexes;
+        self.rebuild_indexes();
+    }
+
+    fn rebuild_indexes(&mut self) {
+        self.lookups = self.indexes.iter()
+            .filter(|column| self.column_index(column).is_some())
+            .map(|column| (column.clone(), ColumnIndex::new()))
+            .collect();
+        let rows = std::mem::take(&mut self.rows);
+        for (id, values) in &rows {
+            self.index(*id, values);
+        }
+        self.rows = rows;
+    }
+
+    fn index(&mut self, id: usize, values: &[String]) {
+        for (column, index) in &mut self.lookups {
+            if let Some(value) = self.columns.iter().position(|c| c == column).and_then(|i| values.get(i)) {
+                index.entry(value.clone()).or_default().insert(id);
+            }
+        }
+    }
+
+    fn unindex(&mut self, id: usize, values: &[String]) {
+        for (column, index) in &mut self.lookups {
+            let Some(value) = self.columns.iter().position(|c| c == column).and_then(|i| values.get(i)) else {
+                continue;
+            };
+            if let Some(ids) = index.get_mut(value) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    index.remove(value);
+                }
+            }
+        }
+    }
+
+    /// Describes rows that do not match the table's columns.
+    pub fn check(&self) -> Vec<String> {
+        self.rows.iter()
+            .filter(|(_, values)| values.len() != self.columns.len())
+            .map(|(id, values)| format!("row {} has {} values for {} columns", id, values.len(), self.columns.len()))
+            .collect()
+    }
+
+    pub fn check_row(&self, id: usize, row: &Row) -> Vec<String> {
+        let mut problems = Vec::new();
+        for column in &self.columns {
+            if row.get(column).is_none() {
+                problems.push(format!("row {} is missing column '{}'", id, column));
+            }
+        }
+        for column in row.data.keys() {
+            if !self.columns.contains(column) {
+                problems.push(format!("row {} has unknown column '{}'", id, column));
+            }
+        }
+        problems
+    }
+
+    /// Renumbers rows to contiguous IDs, keeping their relative order. Only
+    /// replayed from write logs that recorded a compaction which renumbered.
+    pub fn renumber(&mut self) {
+        let rows = std::mem::take(&mut self.rows);
+        self.rows = rows.into_values().enumerate().collect();
+        self.rebuild_indexes();
+    }
+}
+
+/// True for ranges with the start past the end, which `BTreeMap::range`
+/// rejects.
+fn is_empty_range(range: &RowIdRange) -> bool {
+    match *range {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
 }
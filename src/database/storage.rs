@@ -0,0 +1,163 @@
+use std::ops::RangeBounds;
+use std::sync::{Arc, Mutex, MutexGuard};
+use super::compression::Compression;
+use super::deadline::Deadline;
+use super::engine::StorageLimits;
+use super::schema::{ColumnType, Row, RowIdRange};
+use super::wal::LogRecord;
+
+/// A database shared between the shell and the servers.
+pub type SharedStorage = Arc<Mutex<Box<dyn Storage>>>;
+
+/// Locks a shared database. A session that panics while holding the lock
+/// poisons it; the lock is taken back rather than failing every session
+/// after it, since the statement that panicked stops there.
+pub fn lock_storage(storage: &SharedStorage) -> MutexGuard<'_, Box<dyn Storage>> {
+    storage.lock().unwrap_or_else(|poisoned| {
+        eprintln!("Recovered the database lock after a panic in another session");
+        storage.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// What the query engine, servers and CLI need from a database backend.
+/// `StorageEngine` keeps tables in memory; `PagedEngine` keeps them in page
+/// files. Errors are messages for the user.
+pub trait Storage: Send {
+    fn create_typed_table(&mut self, name: &str, columns: Vec<String>, types: Vec<ColumnType>) -> Result<(), String>;
+
+    fn create_table(&mut self, name: &str, columns: Vec<String>) -> Result<(), String> {
+        let types = vec![ColumnType::Text; columns.len()];
+        self.create_typed_table(name, columns, types)
+    }
+
+    fn drop_table(&mut self, name: &str) -> Result<(), String>;
+
+    fn has_table(&self, name: &str) -> bool;
+
+    fn list_tables(&self) -> Vec<&String>;
+
+    fn describe_table(&self, name: &str) -> Result<&Vec<String>, String>;
+
+    fn describe_columns(&self, name: &str) -> Result<Vec<(String, ColumnType)>, String>;
+
+    /// Stores a row after checking it against the table's columns and types,
+    /// returning its ID.
+    fn insert_row(&mut self, table: &str, row: Row) -> Result<usize, String>;
+
+    /// Stores a row under the given ID, failing if a row already has it.
+    fn insert_row_at(&mut self, table: &str, id: usize, row: Row) -> Result<usize, String>;
+
+    /// Every row of the table, in row ID order.
+    fn select_all(&self, table: &str) -> Result<Vec<(usize, Row)>, String>;
+
+    /// Rows whose ID falls in `range`, in ID order. Backends that keep rows
+    /// ordered by ID should seek to the start instead of scanning.
+    fn select_range(&self, table: &str, range: RowIdRange) -> Result<Vec<(usize, Row)>, String> {
+        Ok(self.select_all(table)?
+            .into_iter()
+            .filter(|(id, _)| range.contains(id))
+            .collect())
+    }
+
+    /// Up to `limit` rows with IDs in `range` whose `filter` column, if
+    /// given, holds the value, in ID order or its reverse. Cursors read a
+    /// table this way a batch at a time.
+    fn select_batch(&self, table: &str, range: RowIdRange, filter: Option<(&str, &str)>, descending: bool, limit: usize) -> Result<Vec<(usize, Row)>, String> {
+        let mut rows = self.select_range(table, range)?;
+        rows.retain(|(_, row)| filter.is_none_or(|(column, value)| row.get(column).is_some_and(|v| v == value)));
+        if descending {
+            rows.reverse();
+        }
+        rows.truncate(limit);
+        Ok(rows)
+    }
+
+    /// Rows whose `column` equals `value`, in ID order. Backends with an
+    /// index on the column should answer from it instead of scanning.
+    fn select_where(&self, table: &str, column: &str, value: &str) -> Result<Vec<(usize, Row)>, String> {
+        if let Some(ids) = self.index_lookup(table, column, value)? {
+            let mut rows = Vec::with_capacity(ids.len());
+            for id in ids {
+                if let Some(row) = self.get_row(table, id)? {
+                    rows.push((id, row));
+                }
+            }
+            return Ok(rows);
+        }
+        Ok(self.select_all(table)?
+            .into_iter()
+            .filter(|(_, row)| row.get(column).is_some_and(|v| v == value))
+            .collect())
+    }
+
+    /// The row with the given ID, if any.
+    fn get_row(&self, table: &str, id: usize) -> Result<Option<Row>, String> {
+        Ok(self.select_all(table)?.into_iter().find(|(row_id, _)| *row_id == id).map(|(_, row)| row))
+    }
+
+    /// Indexes `column`. The index is kept up to date by every later write
+    /// and persisted with the table.
+    fn create_index(&mut self, table: &str, column: &str) -> Result<(), String>;
+
+    fn drop_index(&mut self, table: &str, column: &str) -> Result<(), String>;
+
+    /// The table's indexed columns, in the order their indexes were created.
+    fn list_indexes(&self, table: &str) -> Result<Vec<String>, String>;
+
+    /// IDs of the rows whose `column` equals `value`, in ID order, read from
+    /// the column's index; `None` when the column has none.
+    fn index_lookup(&self, table: &str, column: &str, value: &str) -> Result<Option<Vec<usize>>, String>;
+
+    fn update_where(&mut self, table: &str, column: &str, value: &str, target: &str, new_value: &str) -> Result<usize, String>;
+
+    fn delete_where(&mut self, table: &str, column: &str, value: &str) -> Result<usize, String>;
+
+    /// Starts a transaction. Only one can be open at a time.
+    fn begin(&mut self) -> Result<(), String>;
+
+    fn commit(&mut self) -> Result<(), String>;
+
+    /// Undoes every change since `begin`.
+    fn rollback(&mut self) -> Result<(), String>;
+
+    fn limits(&self) -> StorageLimits;
+
+    fn set_limits(&mut self, limits: StorageLimits);
+
+    /// Makes scans fail once `deadline` passes, until it is replaced.
+    fn set_deadline(&mut self, deadline: Deadline);
+
+    /// Log sequence number of the last change.
+    fn lsn(&self) -> u64;
+
+    /// Copies every table of `other`, with its row IDs and indexes, into this
+    /// database. Existing tables are only overwritten when `replace` is set.
+    /// Returns the imported table names.
+    fn merge(&mut self, other: &dyn Storage, replace: bool) -> Result<Vec<String>, String>;
+
+    /// Returns one message per integrity problem found, prefixed with the table name.
+    fn check(&self) -> Vec<String>;
+
+    /// Drops expired key/value entries and reclaims free space, keeping
+    /// every row's ID. Returns the number of expired entries removed.
+    fn compact(&mut self) -> Result<usize, String>;
+
+    /// Changes made since the last call, for the write log.
+    fn take_journal(&mut self) -> Vec<LogRecord> {
+        Vec::new()
+    }
+
+    /// Makes all changes durable in the backend's own files. Returns false
+    /// for backends that have none and are saved as a snapshot instead.
+    fn checkpoint(&mut self) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    /// The whole database in the binary data file format, with the body
+    /// compressed by `compression`.
+    fn to_binary(&self, compression: Compression) -> Result<Vec<u8>, String>;
+
+    /// The whole database as JSON, as written by `atom export`.
+    fn to_json(&self) -> Result<String, String>;
+}
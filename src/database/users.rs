@@ -0,0 +1,178 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use super::grants::Grants;
+use super::storage::Storage;
+use super::schema::Row;
+
+pub const USERS_TABLE: &str = "atom_users";
+const USERS_COLUMNS: [&str; 2] = ["name", "password_hash"];
+
+/// Accounts the servers accept logins from, one row per user in the
+/// `atom_users` table. Passwords are stored as salted Argon2id hashes in
+/// PHC string form. Logins are required once at least one user exists.
+pub struct Users<'a> {
+    storage: &'a mut dyn Storage,
+}
+
+impl<'a> Users<'a> {
+    pub fn new(storage: &'a mut dyn Storage) -> Self {
+        Users { storage }
+    }
+
+    /// True when at least one account exists, so sessions must log in.
+    pub fn is_enabled(&self) -> Result<bool, String> {
+        if !self.storage.has_table(USERS_TABLE) {
+            return Ok(false);
+        }
+        Ok(!self.storage.select_all(USERS_TABLE)?.is_empty())
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool, String> {
+        Ok(self.find(name)?.is_some())
+    }
+
+    pub fn create(&mut self, name: &str, password: &str) -> Result<(), String> {
+        check_name(name)?;
+        self.ensure_table()?;
+        if self.exists(name)? || Grants::new(self.storage).is_role(name)? {
+            return Err(format!("A user or role named '{}' already exists", name));
+        }
+        let mut row = Row::new();
+        row.insert("name".to_string(), name.to_string());
+        row.insert("password_hash".to_string(), hash_password(password)?);
+        self.storage.insert_row(USERS_TABLE, row)?;
+        Ok(())
+    }
+
+    /// Drops the user along with its grants.
+    pub fn drop(&mut self, name: &str) -> Result<(), String> {
+        if !self.exists(name)? {
+            return Err(format!("User '{}' does not exist", name));
+        }
+        self.storage.delete_where(USERS_TABLE, "name", name)?;
+        Grants::new(self.storage).forget(name)
+    }
+
+    pub fn set_password(&mut self, name: &str, password: &str) -> Result<(), String> {
+        if !self.exists(name)? {
+            return Err(format!("User '{}' does not exist", name));
+        }
+        let hash = hash_password(password)?;
+        self.storage.update_where(USERS_TABLE, "name", name, "password_hash", &hash)?;
+        Ok(())
+    }
+
+    /// True when a session logged in as `user`, or not logged in at all,
+    /// may run commands: either no accounts exist or the user still does.
+    pub fn allows(&self, user: Option<&str>) -> Result<bool, String> {
+        if !self.is_enabled()? {
+            return Ok(true);
+        }
+        match user {
+            Some(name) => self.exists(name),
+            None => Ok(false),
+        }
+    }
+
+    /// The stored hash to check a login against. Servers verify it with
+    /// `verify_password` after releasing the storage lock, since hashing is
+    /// deliberately slow.
+    pub fn password_hash(&self, name: &str) -> Result<Option<String>, String> {
+        Ok(self.find(name)?.and_then(|row| row.get("password_hash").cloned()))
+    }
+
+    fn find(&self, name: &str) -> Result<Option<Row>, String> {
+        if !self.storage.has_table(USERS_TABLE) {
+            return Ok(None);
+        }
+        let rows = self.storage.select_where(USERS_TABLE, "name", name)?;
+        Ok(rows.into_iter().next().map(|(_, row)| row))
+    }
+
+    fn ensure_table(&mut self) -> Result<(), String> {
+        if !self.storage.has_table(USERS_TABLE) {
+            let columns = USERS_COLUMNS.iter().map(|c| c.to_string()).collect();
+            return self.storage.create_table(USERS_TABLE, columns);
+        }
+        let columns = self.storage.describe_table(USERS_TABLE)?;
+        if USERS_COLUMNS.iter().any(|c| !columns.iter().any(|existing| existing == c)) {
+            return Err(format!("Table '{}' exists but is not a users table", USERS_TABLE));
+        }
+        Ok(())
+    }
+}
+
+/// Checks `password` against a hash from `Users::password_hash`.
+pub fn verify_password(hash: &str, password: &str) -> Result<bool, String> {
+    let hash = PasswordHash::new(hash).map_err(|e| format!("Stored password hash is invalid: {}", e))?;
+    Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// User and role names are identifiers.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("Invalid user name '{}': use letters, digits and underscores", name));
+    }
+    Ok(())
+}
+
+fn hash_password(password: &str) -> Result<String, String> {
+    if password.is_empty() {
+        return Err("Password must not be empty".to_string());
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Cannot hash password: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::StorageEngine;
+    use super::*;
+
+    #[test]
+    fn passwords_are_stored_as_salted_argon2_hashes() {
+        let mut storage = StorageEngine::new();
+        let mut users = Users::new(&mut storage);
+        users.create("alice", "s3cret").unwrap();
+        users.create("bob", "s3cret").unwrap();
+
+        let alice = users.password_hash("alice").unwrap().unwrap();
+        let bob = users.password_hash("bob").unwrap().unwrap();
+        assert!(alice.starts_with("$argon2id$"), "{}", alice);
+        assert!(!alice.contains("s3cret"));
+        assert_ne!(alice, bob);
+        assert_eq!(verify_password(&alice, "s3cret"), Ok(true));
+        assert_eq!(verify_password(&alice, "wrong"), Ok(false));
+        assert!(verify_password("not a hash", "s3cret").is_err());
+
+        users.set_password("alice", "n3w").unwrap();
+        let alice = users.password_hash("alice").unwrap().unwrap();
+        assert_eq!(verify_password(&alice, "s3cret"), Ok(false));
+        assert_eq!(verify_password(&alice, "n3w"), Ok(true));
+    }
+
+    #[test]
+    fn logins_are_required_once_a_user_exists() {
+        let mut storage = StorageEngine::new();
+        let mut users = Users::new(&mut storage);
+        assert_eq!(users.allows(None), Ok(true));
+        users.create("alice", "s3cret").unwrap();
+        assert_eq!(users.allows(None), Ok(false));
+        assert_eq!(users.allows(Some("alice")), Ok(true));
+        assert_eq!(users.allows(Some("bob")), Ok(false));
+
+        assert!(users.create("alice", "again").is_err());
+        assert!(users.create("bad-name", "x").is_err());
+        assert!(users.create("carol", "").is_err());
+        assert!(users.set_password("bob", "x").is_err());
+
+        users.drop("alice").unwrap();
+        assert_eq!(users.allows(Some("alice")), Ok(true));
+        assert!(users.drop("alice").is_err());
+    }
+}
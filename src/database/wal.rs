@@ -0,0 +1,450 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use super::compression::Compression;
+use super::encryption::KeyRing;
+use super::file_format::FileOptions;
+use super::engine::StorageEngine;
+use super::schema::{ColumnType, Row, Table};
+use super::storage::Storage;
+
+/// One committed change to the database, as recorded in the write log.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    CreateTable { table: String, columns: Vec<String>, types: Vec<ColumnType> },
+    DropTable { table: String },
+    /// The row as stored, after type coercion, under the ID it was given.
+    Insert { table: String, id: usize, row: Row },
+    Delete { table: String, column: String, value: String },
+    Update { table: String, column: String, value: String, target: String, new_value: String },
+    CreateIndex { table: String, column: String },
+    DropIndex { table: String, column: String },
+    /// A whole table written by an import or restore.
+    PutTable { table: String, contents: Table },
+    /// Written by `compact` when it still renumbered rows; kept so that
+    /// older logs replay the same way.
+    Compact,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LogRecord {
+    pub lsn: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub change: Change,
+}
+
+impl LogRecord {
+    pub fn new(lsn: u64, change: Change) -> Self {
+        LogRecord {
+            lsn,
+            timestamp: now_millis(),
+            change,
+        }
+    }
+}
+
+/// The write log lives next to the data file: `database.bin` logs to `database.wal`.
+pub fn log_path(data_path: &Path) -> PathBuf {
+    data_path.with_extension("wal")
+}
+
+/// Applies the records past the storage's LSN in order, stopping before the
+/// first one past `until_lsn` or made after `until_time` (in milliseconds).
+/// Returns the time of the last change applied.
+pub fn replay(storage: &mut StorageEngine, records: &[LogRecord], until_lsn: Option<u64>, until_time: Option<u64>) -> Result<Option<u64>, String> {
+    let base = storage.lsn();
+    let mut last_time = None;
+    for record in records.iter().filter(|record| record.lsn > base) {
+        let past_lsn = until_lsn.is_some_and(|lsn| record.lsn > lsn);
+        let past_time = until_time.is_some_and(|time| record.timestamp > time);
+        if past_lsn || past_time {
+            break;
+        }
+        if record.lsn != storage.lsn() + 1 {
+            return Err(format!("The write log has no changes between LSN {} and {}; cannot recover past LSN {}",
+                storage.lsn(), record.lsn, storage.lsn()));
+        }
+        storage.apply(record)?;
+        last_time = Some(record.timestamp);
+    }
+    Ok(last_time)
+}
+
+/// Size at which a save that has persisted the whole log moves it aside as
+/// an archived segment, so that startup only reads the changes since.
+pub const SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Starts a compressed or encrypted block in the log; JSON lines never
+/// start with it.
+const BLOCK_MARKER: u8 = 0;
+/// Marker, codec and the length of the block as a little-endian u32.
+const BLOCK_HEADER_SIZE: usize = 6;
+
+/// Appends records as JSON lines and syncs the file before returning. With
+/// compression or encryption the lines are written as one block instead,
+/// compressed and then sealed.
+pub fn append(path: &Path, records: &[LogRecord], options: &FileOptions) -> Result<(), String> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let data = encode(records, options)?;
+    let error = |e: std::io::Error| format!("Cannot write the write log '{}': {}", path.display(), e);
+    let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(error)?;
+    file.write_all(&data).map_err(error)?;
+    file.sync_data().map_err(error)
+}
+
+/// Reads every record in LSN order, from JSON lines and blocks alike. A
+/// missing file is an empty log, and an incomplete last line or block (a
+/// write cut short by a crash) is ignored.
+pub fn read_log(path: &Path, keys: &KeyRing) -> Result<Vec<LogRecord>, String> {
+    Ok(parse_log(path, keys)?.0)
+}
+
+/// Reads the log like `read_log`, then rewrites it as one block under the
+/// current key if any of it is in the clear or sealed with a previous key.
+pub fn read_and_reseal(path: &Path, options: &FileOptions) -> Result<Vec<LogRecord>, String> {
+    let (records, stale) = parse_log(path, &options.keys)?;
+    if stale {
+        let temp_path = path.with_extension("wal.tmp");
+        let error = |e: std::io::Error| format!("Cannot write the write log '{}': {}", temp_path.display(), e);
+        let mut file = File::create(&temp_path).map_err(error)?;
+        file.write_all(&encode(&records, options)?).map_err(error)?;
+        file.sync_data().map_err(error)?;
+        std::fs::rename(&temp_path, path).map_err(|e| format!("Cannot write the write log '{}': {}", path.display(), e))?;
+    }
+    Ok(records)
+}
+
+/// Moves the log aside as `<log>.<first LSN>-<last LSN>` when it is at least
+/// `min_size` bytes and holds nothing past `lsn`, the LSN the data file was
+/// just saved at. Returns the segment's path if the log was moved.
+pub fn rotate(path: &Path, lsn: u64, min_size: u64, keys: &KeyRing) -> Result<Option<PathBuf>, String> {
+    if std::fs::metadata(path).map(|m| m.len()).unwrap_or(0) < min_size {
+        return Ok(None);
+    }
+    let records = read_log(path, keys)?;
+    let (Some(first), Some(last)) = (records.first(), records.last()) else {
+        return Ok(None);
+    };
+    if last.lsn > lsn {
+        return Ok(None);
+    }
+    let segment = with_suffix(path, &format!("{}-{}", first.lsn, last.lsn));
+    std::fs::rename(path, &segment).map_err(|e| format!("Cannot move the write log '{}': {}", path.display(), e))?;
+    Ok(Some(segment))
+}
+
+/// The archived segments of the log, as `(first LSN, last LSN, path)` in LSN order.
+pub fn segments(path: &Path) -> Result<Vec<(u64, u64, PathBuf)>, String> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let prefix = format!("{}.", path.file_name().unwrap_or_default().to_string_lossy());
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Cannot read '{}': {}", dir.display(), e)),
+    };
+    let mut segments = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let range = name.strip_prefix(&prefix).and_then(|range| range.split_once('-'));
+        if let Some((Ok(first), Ok(last))) = range.map(|(first, last)| (first.parse(), last.parse())) {
+            segments.push((first, last, entry.path()));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Reads the archived segments and then the log itself: every change kept,
+/// for recovery from a backup older than the last rotation.
+pub fn read_history(path: &Path, keys: &KeyRing) -> Result<Vec<LogRecord>, String> {
+    let mut records = Vec::new();
+    for (_, _, segment) in segments(path)? {
+        records.extend(read_log(&segment, keys)?);
+    }
+    records.extend(read_log(path, keys)?);
+    Ok(records)
+}
+
+/// `path` with `.suffix` appended to its file name.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", suffix));
+    PathBuf::from(name)
+}
+
+fn encode(records: &[LogRecord], options: &FileOptions) -> Result<Vec<u8>, String> {
+    let mut lines = String::new();
+    for record in records {
+        let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        lines.push_str(&line);
+        lines.push('\n');
+    }
+    if options.compression == Compression::None && !options.keys.is_enabled() {
+        return Ok(lines.into_bytes());
+    }
+    let payload = options.keys.seal(options.compression.compress(lines.as_bytes())?)?;
+    let mut block = vec![BLOCK_MARKER, options.compression.id()];
+    block.extend((payload.len() as u32).to_le_bytes());
+    block.extend(payload);
+    Ok(block)
+}
+
+/// Returns the records and whether any of them should be sealed again.
+fn parse_log(path: &Path, keys: &KeyRing) -> Result<(Vec<LogRecord>, bool), String> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), false)),
+        Err(e) => return Err(format!("Cannot read the write log '{}': {}", path.display(), e)),
+    };
+
+    let mut records = Vec::new();
+    let mut stale = false;
+    let corrupt = |record: usize, e: String| format!("Corrupt write log '{}' at record {}: {}", path.display(), record, e);
+    let mut rest = &data[..];
+    while let Some(first) = rest.first() {
+        if *first == BLOCK_MARKER {
+            let Some((header, body)) = rest.split_at_checked(BLOCK_HEADER_SIZE) else {
+                break;
+            };
+            let length = u32::from_le_bytes([header[2], header[3], header[4], header[5]]) as usize;
+            let Some((block, next)) = body.split_at_checked(length) else {
+                break;
+            };
+            stale |= keys.is_stale(block);
+            let lines = Compression::from_id(header[1])
+                .and_then(|compression| compression.decompress(&keys.open(block)?))
+                .and_then(|lines| String::from_utf8(lines).map_err(|e| e.to_string()))
+                .map_err(|e| corrupt(records.len() + 1, e))?;
+            for line in lines.lines().filter(|line| !line.trim().is_empty()) {
+                records.push(serde_json::from_str(line).map_err(|e| corrupt(records.len() + 1, e.to_string()))?);
+            }
+            rest = next;
+        } else {
+            let end = rest.iter().position(|b| *b == b'\n');
+            let (line, next) = match end {
+                Some(end) => (&rest[..end], &rest[end + 1..]),
+                None => (rest, &rest[rest.len()..]),
+            };
+            let line = String::from_utf8_lossy(line);
+            if !line.trim().is_empty() {
+                match serde_json::from_str::<LogRecord>(&line) {
+                    Ok(record) => records.push(record),
+                    Err(_) if end.is_none() => break,
+                    Err(e) => return Err(corrupt(records.len() + 1, e.to_string())),
+                }
+                keys.check_plaintext().map_err(|e| format!("Cannot read the write log '{}': {}", path.display(), e))?;
+                stale |= keys.is_enabled();
+            }
+            rest = next;
+        }
+    }
+    Ok((records, stale))
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Parses a UTC time given as `YYYY-MM-DD[THH:MM[:SS]][Z]` (a space may
+/// replace the `T`) or as Unix seconds, returning milliseconds since the epoch.
+pub fn parse_timestamp(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(seconds * 1000);
+    }
+
+    let invalid = || format!("Invalid time '{}' (expected YYYY-MM-DDTHH:MM:SSZ or Unix seconds)", value);
+    let value = value.trim_end_matches('Z');
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, "00:00:00"));
+
+    let date: Vec<i64> = date.split('-').map(|p| p.parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+    let time: Vec<i64> = time.split(':').map(|p| p.parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+    let ([year, month, day], [hour, minute, rest @ ..]) = (date.as_slice(), time.as_slice()) else {
+        return Err(invalid());
+    };
+    let second = rest.first().copied().unwrap_or(0);
+    if !(1..=12).contains(month) || !(1..=31).contains(day) || *hour > 23 || *minute > 59 || second > 59 || rest.len() > 1 {
+        return Err(invalid());
+    }
+
+    let seconds = days_from_civil(*year, *month, *day) * 86_400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(seconds * 1000).map_err(|_| invalid())
+}
+
+/// Formats milliseconds since the epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn format_timestamp(millis: u64) -> String {
+    let seconds = (millis / 1000) as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+// Howard Hinnant's algorithms for converting between days since 1970-01-01
+// and proleptic Gregorian dates.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("atom-wal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn row(k: &str) -> Row {
+        let mut row = Row::new();
+        row.insert("k".to_string(), k.to_string());
+        row
+    }
+
+    /// A journaling engine after creating a table and inserting `rows` rows,
+    /// and the records it wrote, made one second apart from 1,000 s.
+    fn changes(rows: usize) -> (StorageEngine, Vec<LogRecord>) {
+        let mut storage = StorageEngine::new();
+        storage.enable_journal();
+        storage.create_table("t", vec!["k".to_string()]).unwrap();
+        for n in 0..rows {
+            storage.insert_row("t", row(&n.to_string())).unwrap();
+        }
+        let mut records = storage.take_journal();
+        for record in &mut records {
+            record.timestamp = (1000 + record.lsn) * 1000;
+        }
+        (storage, records)
+    }
+
+    #[test]
+    fn records_round_trip_as_lines_and_blocks() {
+        let dir = temp_dir("round-trip");
+        let path = dir.join("database.wal");
+        let (_, records) = changes(5);
+        append(&path, &records[..3], &FileOptions::default()).unwrap();
+        append(&path, &records[3..], &FileOptions { compression: Compression::Zstd, ..FileOptions::default() }).unwrap();
+        assert_eq!(read_log(&path, &KeyRing::default()).unwrap(), records);
+        assert!(read_log(&dir.join("missing.wal"), &KeyRing::default()).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_torn_last_record_is_ignored_but_a_corrupt_one_is_not() {
+        let dir = temp_dir("torn");
+        let path = dir.join("database.wal");
+        let (_, records) = changes(3);
+        append(&path, &records[..2], &FileOptions::default()).unwrap();
+        append(&path, &records[2..], &FileOptions { compression: Compression::Lz4, ..FileOptions::default() }).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 3]).unwrap();
+        assert_eq!(read_log(&path, &KeyRing::default()).unwrap(), records[..2]);
+
+        let line = serde_json::to_string(&records[0]).unwrap();
+        std::fs::write(&path, format!("{}\n{}", line, &line[..10])).unwrap();
+        assert_eq!(read_log(&path, &KeyRing::default()).unwrap(), records[..1]);
+
+        std::fs::write(&path, format!("{}\n{{\"lsn\":\n{}\n", line, line)).unwrap();
+        let error = read_log(&path, &KeyRing::default()).unwrap_err();
+        assert!(error.contains("at record 2"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_applies_only_changes_past_the_snapshot() {
+        let (mut storage, mut records) = changes(3);
+        let snapshot = storage.serialize_binary(Compression::None).unwrap();
+        storage.insert_row("t", row("late")).unwrap();
+        records.extend(storage.take_journal());
+
+        let mut loaded = StorageEngine::deserialize_binary(&snapshot).unwrap();
+        assert_eq!(loaded.lsn(), 4);
+        replay(&mut loaded, &records, None, None).unwrap();
+        assert_eq!(loaded.lsn(), 5);
+        assert_eq!(loaded.select_all("t").unwrap(), storage.select_all("t").unwrap());
+    }
+
+    #[test]
+    fn replay_stops_at_an_lsn_or_a_time() {
+        let (_, records) = changes(5);
+        let mut storage = StorageEngine::new();
+        assert_eq!(replay(&mut storage, &records, Some(3), None).unwrap(), Some(1003 * 1000));
+        assert_eq!(storage.lsn(), 3);
+        assert_eq!(storage.select_all("t").unwrap().len(), 2);
+
+        let mut storage = StorageEngine::new();
+        assert_eq!(replay(&mut storage, &records, None, Some(1004 * 1000 + 999)).unwrap(), Some(1004 * 1000));
+        assert_eq!(storage.lsn(), 4);
+
+        let mut storage = StorageEngine::new();
+        let gap = [records[0].clone(), records[2].clone()];
+        let error = replay(&mut storage, &gap, None, None).unwrap_err();
+        assert!(error.contains("between LSN 1 and 3"), "{}", error);
+    }
+
+    #[test]
+    fn rotation_archives_a_persisted_log_that_history_still_reads() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("database.wal");
+        let keys = KeyRing::default();
+        let (_, records) = changes(4);
+        append(&path, &records[..3], &FileOptions::default()).unwrap();
+
+        // Not while the log holds changes past the save, or is below the size.
+        assert_eq!(rotate(&path, 2, 0, &keys).unwrap(), None);
+        assert_eq!(rotate(&path, 3, SEGMENT_SIZE, &keys).unwrap(), None);
+        let segment = rotate(&path, 3, 0, &keys).unwrap().unwrap();
+        assert_eq!(segment, dir.join("database.wal.1-3"));
+        assert!(!path.exists());
+        assert_eq!(rotate(&path, 3, 0, &keys).unwrap(), None);
+
+        append(&path, &records[3..], &FileOptions::default()).unwrap();
+        std::fs::write(dir.join("database.wal.4-9.1760000000"), "set aside").unwrap();
+        std::fs::write(dir.join("database.wal.1760000000"), "set aside").unwrap();
+        assert_eq!(segments(&path).unwrap(), [(1, 3, segment)]);
+        assert_eq!(read_log(&path, &keys).unwrap(), records[3..]);
+        assert_eq!(read_history(&path, &keys).unwrap(), records);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn timestamps_parse_and_format_in_utc() {
+        assert_eq!(parse_timestamp("1970-01-02").unwrap(), 86_400_000);
+        assert_eq!(parse_timestamp("1760884200").unwrap(), 1_760_884_200_000);
+        let millis = parse_timestamp("2026-10-19T14:30:05Z").unwrap();
+        assert_eq!(parse_timestamp("2026-10-19 14:30:05").unwrap(), millis);
+        assert_eq!(format_timestamp(millis), "2026-10-19T14:30:05Z");
+        assert_eq!(format_timestamp(parse_timestamp("2024-02-29T23:59").unwrap()), "2024-02-29T23:59:00Z");
+        for invalid in ["2026-13-01", "2026-10-19T24:00", "yesterday", "2026-10"] {
+            assert!(parse_timestamp(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -1,11 +1,14 @@
+pub mod config;
 pub mod database;
 pub mod cli;
 pub mod server;
 pub mod utils;
 pub mod query;
 
-pub use database::{StorageEngine, Row};
+pub use config::Config;
+pub use database::{Row, Storage, StorageEngine};
 pub use query::QueryEngine;
 pub use cli::cli_interface::run_cli;
 pub use server::tcp_server::start_tcp_server;
+pub use server::resp_server::start_resp_server;
 pub use utils::demo_data::setup_demo_data;
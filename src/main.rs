@@ -1,68 +1,41 @@
-use std::env;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
+use std::sync::Arc;
+use clap::Parser;
 
-use atom::{StorageEngine, run_cli, start_tcp_server, setup_demo_data};
+use atom::Config;
+use atom::cli::Cli;
+use atom::cli::args::{Command, ServeArgs};
+use atom::cli::commands;
 
-fn show_usage() {
-    println!("atomDB - A Rust Database with Immediate Autosave");
-    println!("Usage:");
-    println!("  cargo run                    - Start CLI mode");
-    println!("  cargo run -- --server        - Start TCP server mode");
-    println!("  cargo run -- --both          - Start both CLI and TCP server");
-    println!("  cargo run -- --help          - Show this help");
-    println!();
-    println!("Features:");
-    println!("  - Autosaves immediately after every write operation");
-    println!("  - Final autosave on exit");
-}
+fn main() {
+    let cli = Cli::parse();
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    
-    let initial_storage = match StorageEngine::load_from_binary_file("database.bin") {
-        Ok(storage) => {
-            println!("Loaded existing database from binary file");
-            storage
-        },
-        Err(_) => {
-            println!("Creating new database with demo data");
-            let mut storage = StorageEngine::new();
-            setup_demo_data(&mut storage).expect("Failed to setup demo data");
-            storage
+    let config = match Config::load(&cli.overrides()) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     };
-    
-    let storage = Arc::new(Mutex::new(initial_storage));
-    
-    if args.len() > 1 {
-        match args[1].as_str() {
-            "--help" | "-h" => {
-                show_usage();
-                return Ok(());
-            },
-            "--server" | "-s" => {
-                println!("Starting TCP server mode only...");
-                start_tcp_server(storage);
-            },
-            "--both" | "-b" => {
-                println!("Starting both CLI and TCP server...");
-                let storage_for_server = Arc::clone(&storage);
-                thread::spawn(move || {
-                    start_tcp_server(storage_for_server);
-                });
-                thread::sleep(Duration::from_millis(100));
-                run_cli(storage);
-            },
-            _ => {
-                println!("Unknown argument: {}", args[1]);
-                show_usage();
-            }
-        }
-    } else {
-        run_cli(storage);
+
+    let legacy_serve = ServeArgs { shell: cli.both, ..ServeArgs::default() };
+    let result = match &cli.command {
+        Some(Command::Serve(args)) => commands::serve(config, args),
+        Some(Command::Shell) => commands::shell(config),
+        Some(Command::Exec(args)) => commands::exec(config, args),
+        Some(Command::Import(args)) => commands::import(config, args),
+        Some(Command::Export(args)) => commands::export(config, args),
+        Some(Command::Dump(args)) => commands::dump(config, args),
+        Some(Command::Restore(args)) => commands::restore(config, args),
+        Some(Command::Recover(args)) => commands::recover(config, args),
+        Some(Command::Migrate(args)) => commands::migrate(config, args),
+        Some(Command::Check) => commands::check(config),
+        Some(Command::Compact) => commands::compact(config),
+        None if cli.server || cli.both => commands::serve(config, &legacy_serve),
+        None => commands::shell(config),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
-    
-    Ok(())
 }
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use crate::database::{RowIdRange, Storage};
+use super::result::QueryResult;
+
+/// Cursors one session may have open at once.
+pub const MAX_CURSORS: usize = 256;
+
+/// A SELECT read a batch at a time. A cursor holds its position rather than
+/// its rows, so the database is only locked while a batch is read: each
+/// batch sees the table as it is then, and no row comes back twice.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    table: String,
+    /// Row IDs not read yet.
+    range: RowIdRange,
+    filter: Option<(String, String)>,
+    descending: bool,
+}
+
+impl Cursor {
+    pub fn new(table: &str, range: RowIdRange, filter: Option<(String, String)>, descending: bool) -> Self {
+        Cursor { table: table.to_string(), range, filter, descending }
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// The next `count` rows; fewer, or none, once the end is reached.
+    pub fn fetch(&mut self, storage: &dyn Storage, count: usize) -> Result<QueryResult, String> {
+        let filter = self.filter.as_ref().map(|(column, value)| (column.as_str(), value.as_str()));
+        let rows = storage.select_batch(&self.table, self.range, filter, self.descending, count)?;
+        if let Some((last, _)) = rows.last() {
+            if self.descending {
+                self.range.1 = Bound::Excluded(*last);
+            } else {
+                self.range.0 = Bound::Excluded(*last);
+            }
+        }
+        Ok(QueryResult::Rows {
+            table: self.table.clone(),
+            columns: storage.describe_table(&self.table)?.clone(),
+            rows,
+        })
+    }
+}
+
+/// The cursors a session has declared, by name.
+#[derive(Debug, Default)]
+pub struct Cursors {
+    open: BTreeMap<String, Cursor>,
+}
+
+impl Cursors {
+    pub fn declare(&mut self, name: &str, cursor: Cursor) -> Result<(), String> {
+        if self.open.contains_key(name) {
+            return Err(format!("Cursor '{}' already exists", name));
+        }
+        if self.open.len() >= MAX_CURSORS {
+            return Err(format!("Too many open cursors (at most {})", MAX_CURSORS));
+        }
+        self.open.insert(name.to_string(), cursor);
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Result<&mut Cursor, String> {
+        self.open.get_mut(name).ok_or_else(|| format!("Cursor '{}' does not exist", name))
+    }
+
+    pub fn close(&mut self, name: &str) -> Result<(), String> {
+        self.open.remove(name).map(|_| ()).ok_or_else(|| format!("Cursor '{}' does not exist", name))
+    }
+
+    /// Closes every cursor, returning how many were open.
+    pub fn close_all(&mut self) -> usize {
+        let closed = self.open.len();
+        self.open.clear();
+        closed
+    }
+}
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use crate::database::{ColumnType, Row, Storage};
+use crate::database::wal::{format_timestamp, now_millis};
+use super::parser::QueryEngine;
+use super::script::split_statements;
+
+pub const MIGRATIONS_TABLE: &str = "schema_migrations";
+
+/// A numbered migration: `0003_add_orders.up.sql` with an optional
+/// `0003_add_orders.down.sql`. A plain `0003_add_orders.sql` is up-only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    pub version: u64,
+    pub name: String,
+    pub up: PathBuf,
+    pub down: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMigration {
+    pub version: u64,
+    pub name: String,
+    pub applied_at: String,
+}
+
+/// Migrations completed before `error`, if any, stopped the run.
+#[derive(Debug, Default)]
+pub struct MigrationRun {
+    pub done: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl Migration {
+    pub fn label(&self) -> String {
+        format!("{:04}_{}", self.version, self.name)
+    }
+}
+
+/// Lists the migrations in `dir` in version order. Files that do not start
+/// with a number followed by `_` are ignored.
+pub fn discover(dir: &Path) -> Result<Vec<Migration>, String> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read migrations directory '{}': {}", dir.display(), e))?;
+
+    let mut migrations: Vec<Migration> = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let Some(file_name) = path.file_name().and_then(|f| f.to_str()) else { continue };
+        let Some(stem) = file_name.strip_suffix(".sql") else { continue };
+        let (stem, is_down) = match (stem.strip_suffix(".up"), stem.strip_suffix(".down")) {
+            (Some(stem), _) => (stem, false),
+            (_, Some(stem)) => (stem, true),
+            _ => (stem, false),
+        };
+        let Some((number, name)) = stem.split_once('_') else { continue };
+        let Ok(version) = number.parse::<u64>() else { continue };
+
+        let index = match migrations.iter().position(|m| m.version == version) {
+            Some(index) if migrations[index].name != name => {
+                return Err(format!("Migrations '{}' and '{}' share version {}", migrations[index].label(), stem, version));
+            },
+            Some(index) => index,
+            None => {
+                migrations.push(Migration { version, name: name.to_string(), up: PathBuf::new(), down: None });
+                migrations.len() - 1
+            }
+        };
+        let migration = &mut migrations[index];
+
+        let slot_taken = if is_down { migration.down.is_some() } else { !migration.up.as_os_str().is_empty() };
+        if slot_taken {
+            return Err(format!("Migration {} has more than one {} script", migration.label(), if is_down { "down" } else { "up" }));
+        }
+        if is_down {
+            migration.down = Some(path);
+        } else {
+            migration.up = path;
+        }
+    }
+
+    if let Some(orphan) = migrations.iter().find(|m| m.up.as_os_str().is_empty()) {
+        return Err(format!("Migration {} has a down script but no up script", orphan.label()));
+    }
+    migrations.sort_by_key(|m| m.version);
+    Ok(migrations)
+}
+
+/// Reads the bookkeeping table, oldest first. No table means nothing applied.
+pub fn applied(storage: &dyn Storage) -> Result<Vec<AppliedMigration>, String> {
+    if !storage.has_table(MIGRATIONS_TABLE) {
+        return Ok(Vec::new());
+    }
+    let mut applied: Vec<AppliedMigration> = storage.select_all(MIGRATIONS_TABLE)?
+        .into_iter()
+        .filter_map(|(_, row)| Some(AppliedMigration {
+            version: row.get("version")?.parse().ok()?,
+            name: row.get("name").cloned().unwrap_or_default(),
+            applied_at: row.get("applied_at").cloned().unwrap_or_default(),
+        }))
+        .collect();
+    applied.sort_by_key(|m| m.version);
+    Ok(applied)
+}
+
+/// Applies pending migrations in version order, up to and including
+/// `target` if given. Each migration runs in a transaction that is rolled
+/// back unless every statement succeeds, so a failing migration leaves no
+/// partial changes behind; the run stops there.
+pub fn migrate_up(storage: &mut dyn Storage, migrations: &[Migration], target: Option<u64>) -> MigrationRun {
+    let mut run = MigrationRun::default();
+    let applied: Vec<u64> = match applied(storage) {
+        Ok(applied) => applied.iter().map(|m| m.version).collect(),
+        Err(e) => return MigrationRun { error: Some(e), ..run },
+    };
+
+    for migration in migrations {
+        if applied.contains(&migration.version) || target.is_some_and(|target| migration.version > target) {
+            continue;
+        }
+        if let Err(e) = in_transaction(storage, |storage| apply_up(storage, migration)) {
+            run.error = Some(e);
+            break;
+        }
+        run.done.push(migration.label());
+    }
+    run
+}
+
+/// Reverts the most recent `steps` applied migrations, newest first, using
+/// their down scripts. Stops with an error at a migration that has none.
+pub fn migrate_down(storage: &mut dyn Storage, migrations: &[Migration], steps: usize) -> MigrationRun {
+    let mut run = MigrationRun::default();
+    let applied = match applied(storage) {
+        Ok(applied) => applied,
+        Err(e) => return MigrationRun { error: Some(e), ..run },
+    };
+
+    for applied in applied.into_iter().rev().take(steps) {
+        let reverted = migrations.iter()
+            .find(|m| m.version == applied.version)
+            .ok_or_else(|| format!("Applied migration {:04}_{} has no file in the migrations directory", applied.version, applied.name))
+            .and_then(|migration| in_transaction(storage, |storage| apply_down(storage, migration)).map(|()| migration.label()));
+        match reverted {
+            Ok(label) => {
+                run.done.push(label);
+            },
+            Err(e) => {
+                run.error = Some(e);
+                break;
+            }
+        }
+    }
+    run
+}
+
+/// Runs `f` in a transaction, committing only if it succeeds.
+fn in_transaction<F: FnOnce(&mut dyn Storage) -> Result<(), String>>(storage: &mut dyn Storage, f: F) -> Result<(), String> {
+    storage.begin()?;
+    match f(storage) {
+        Ok(()) => storage.commit(),
+        Err(e) => {
+            storage.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+fn apply_up(storage: &mut dyn Storage, migration: &Migration) -> Result<(), String> {
+    run_script(storage, &migration.up, &migration.label())?;
+    ensure_table(storage)?;
+
+    let mut row = Row::new();
+    row.insert("version".to_string(), migration.version.to_string());
+    row.insert("name".to_string(), migration.name.clone());
+    row.insert("applied_at".to_string(), format_timestamp(now_millis()));
+    storage.insert_row(MIGRATIONS_TABLE, row)?;
+    Ok(())
+}
+
+fn apply_down(storage: &mut dyn Storage, migration: &Migration) -> Result<(), String> {
+    let down = migration.down.as_ref()
+        .ok_or_else(|| format!("Migration {} has no down script", migration.label()))?;
+    run_script(storage, down, &migration.label())?;
+    storage.delete_where(MIGRATIONS_TABLE, "version", &migration.version.to_string())?;
+    Ok(())
+}
+
+fn run_script(storage: &mut dyn Storage, path: &Path, label: &str) -> Result<(), String> {
+    let script = std::fs::read_to_string(path).map_err(|e| format!("Cannot read '{}': {}", path.display(), e))?;
+    for (index, statement) in split_statements(&script).iter().enumerate() {
+        QueryEngine::new(storage).execute_query(&statement.text)
+            .map_err(|e| format!("Migration {} failed at statement {} (line {}): {}", label, index + 1, statement.line, e))?;
+    }
+    Ok(())
+}
+
+fn ensure_table(storage: &mut dyn Storage) -> Result<(), String> {
+    if storage.has_table(MIGRATIONS_TABLE) {
+        return Ok(());
+    }
+    let columns = vec!["version".to_string(), "name".to_string(), "applied_at".to_string()];
+    storage.create_typed_table(MIGRATIONS_TABLE, columns, vec![ColumnType::Integer, ColumnType::Text, ColumnType::Text])
+}
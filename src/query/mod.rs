@@ -1,5 +1,14 @@
 //src/mod.rs
 
+pub mod cursor;
+pub mod migrations;
 pub mod parser;
+pub mod prepared;
+pub mod result;
+pub mod script;
 
-pub use parser::QueryEngine;
\ No newline at end of file
+pub use cursor::{Cursor, Cursors};
+pub use parser::QueryEngine;
+pub use prepared::{Plan, PreparedStatements};
+pub use result::QueryResult;
+pub use script::{is_complete, split_statements, Statement};
@@ -1,104 +1,396 @@
-use crate::database::{StorageEngine, Row};
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::path::Path;
+use std::str::Chars;
+use crate::database::{ColumnType, Deadline, FileOptions, Grants, Privilege, Row, RowIdRange, Storage, Users};
+use crate::database::grants::permission_denied;
+use crate::database::copy::{export_csv, export_json, import_csv, import_json};
+use crate::utils::csv::CsvOptions;
+use crate::utils::persistence::write_snapshot;
+use super::cursor::{Cursor, Cursors};
+use super::prepared::{Filter, Operand, Plan, PlannedRow, PreparedStatements};
+use super::result::QueryResult;
+
+/// Refers to a row's ID in WHERE and ORDER BY. Tables are stored in row ID
+/// order, so conditions and ordering on it need no scan or sort.
+pub const ROW_ID: &str = "rowid";
 
 pub struct QueryEngine<'a> {
-    storage: &'a mut StorageEngine,
+    storage: &'a mut dyn Storage,
+    /// Compression and encryption for files written by BACKUP.
+    file_options: FileOptions,
+    /// The logged-in user whose privileges statements are checked against;
+    /// `None` runs everything, as the local shell and `atom exec` do.
+    user: Option<String>,
+    /// When a running statement's scans give up; none by default.
+    deadline: Deadline,
+    /// The session's cursors, for DECLARE, FETCH and CLOSE.
+    cursors: Option<&'a mut Cursors>,
+    /// The session's prepared statements, for PREPARE, EXECUTE and DEALLOCATE.
+    prepared: Option<&'a mut PreparedStatements>,
 }
 
 impl<'a> QueryEngine<'a> {
-    pub fn new(storage: &'a mut StorageEngine) -> Self {
-        QueryEngine { storage }
+    pub fn new(storage: &'a mut dyn Storage) -> Self {
+        Self::with_file_options(storage, FileOptions::default())
+    }
+
+    pub fn with_file_options(storage: &'a mut dyn Storage, file_options: FileOptions) -> Self {
+        QueryEngine { storage, file_options, user: None, deadline: Deadline::default(), cursors: None, prepared: None }
+    }
+
+    pub fn as_user(mut self, user: Option<String>) -> Self {
+        self.user = user;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn with_cursors(mut self, cursors: &'a mut Cursors) -> Self {
+        self.cursors = Some(cursors);
+        self
+    }
+
+    pub fn with_prepared(mut self, prepared: &'a mut PreparedStatements) -> Self {
+        self.prepared = Some(prepared);
+        self
+    }
+
+    pub fn is_write_query(query: &str) -> bool {
+        let words: Vec<String> = query.split_whitespace().take(3).map(|w| w.to_lowercase()).collect();
+        match words.first().map(String::as_str) {
+            Some("create" | "insert" | "drop" | "alter" | "grant" | "revoke") => true,
+            Some("copy") => words.get(2).is_some_and(|w| w == "from"),
+            _ => false,
+        }
     }
 
     pub fn execute(&mut self, query: &str) -> Result<String, String> {
-        let query = query.trim().to_lowercase();
+        self.execute_query(query).map(|result| result.to_text())
+    }
+
+    pub fn execute_query(&mut self, query: &str) -> Result<QueryResult, String> {
+        self.storage.set_deadline(self.deadline);
+        let result = self.dispatch(query);
+        self.storage.set_deadline(Deadline::default());
+        result
+    }
+
+    /// Plans a SELECT as a cursor instead of running it, so that its rows
+    /// can be read a batch at a time with `fetch`.
+    pub fn open_cursor(&mut self, query: &str) -> Result<Cursor, String> {
+        let words = split_words(query.trim());
+        if !words.first().is_some_and(|w| w.eq_ignore_ascii_case("select")) {
+            return Err("Only SELECT statements can be read with a cursor".to_string());
+        }
+        let (table_name, condition, descending) = parse_select(&words[1..])?;
+        let table_name = table_name.as_str();
+        self.require(Privilege::Select, Some(table_name))?;
+        let condition = condition.map(|condition| plan_condition(&condition, false)).transpose()?;
+        let (range, filter) = match condition.map(|filter| bind_filter(&filter, &[])).transpose()? {
+            None => ((Bound::Unbounded, Bound::Unbounded), None),
+            Some(Condition::RowIds(range)) => (range, None),
+            Some(Condition::Equals(column, value)) => ((Bound::Unbounded, Bound::Unbounded), Some((column, value))),
+        };
+        if !self.storage.has_table(table_name) {
+            return Err(format!("Table '{}' not found", table_name));
+        }
+        Ok(Cursor::new(table_name, range, filter, descending))
+    }
+
+    /// The next `count` rows of `cursor`. The SELECT privilege on its table
+    /// is checked again for every batch.
+    pub fn fetch(&mut self, cursor: &mut Cursor, count: usize) -> Result<QueryResult, String> {
+        self.storage.set_deadline(self.deadline);
+        let result = self.require(Privilege::Select, Some(cursor.table()))
+            .and_then(|_| cursor.fetch(&*self.storage, count));
+        self.storage.set_deadline(Deadline::default());
+        result
+    }
+
+    /// Parses a SELECT or INSERT into a plan whose `$1`, `$2`, ... are bound
+    /// each time it runs with `execute_plan`.
+    pub fn prepare(&mut self, query: &str) -> Result<Plan, String> {
+        let query = query.trim();
+        let plan = match query.split_whitespace().next().map(str::to_lowercase).as_deref() {
+            Some("select") => plan_select(&split_words(query)[1..], true)?,
+            Some("insert") => {
+                let (table, rows) = parse_insert(query, true)?;
+                Plan::Insert { table, rows }
+            },
+            _ => return Err("Only SELECT and INSERT statements can be prepared".to_string()),
+        };
+        let privilege = if plan.is_write() { Privilege::Insert } else { Privilege::Select };
+        self.require(privilege, Some(plan.table()))?;
+        if !self.storage.has_table(plan.table()) {
+            return Err(format!("Table '{}' not found", plan.table()));
+        }
+        Ok(plan)
+    }
+
+    /// Runs a prepared plan with `values` bound to its parameters. The values
+    /// are never parsed as SQL, so they need no quoting or escaping.
+    pub fn execute_plan(&mut self, plan: &Plan, values: &[&str]) -> Result<QueryResult, String> {
+        if values.len() != plan.parameters() {
+            return Err(format!("Statement takes {} parameter(s), got {}", plan.parameters(), values.len()));
+        }
+        self.storage.set_deadline(self.deadline);
+        let result = self.run_plan(plan, values);
+        self.storage.set_deadline(Deadline::default());
+        result
+    }
+
+    /// Prepares and runs `query` once, with `values` bound to its parameters.
+    pub fn execute_with(&mut self, query: &str, values: &[&str]) -> Result<QueryResult, String> {
+        let plan = self.prepare(query)?;
+        self.execute_plan(&plan, values)
+    }
+
+    fn dispatch(&mut self, query: &str) -> Result<QueryResult, String> {
+        let original = query.trim();
+        let query = original.to_lowercase();
         let parts: Vec<&str> = query.split_whitespace().collect();
         
-        match parts.get(0) {
-            Some(&"create") => self.handle_create(&parts[1..]),
-            Some(&"insert") => self.handle_insert(&parts[1..]),
-            Some(&"select") => self.handle_select(&parts[1..]),
-            Some(&"describe") => self.handle_describe(&parts[1..]),
-            Some(&"show") => self.handle_show(&parts[1..]),
-            Some(&"drop") => self.handle_drop(&parts[1..]),
+        match parts.first() {
+            Some(&"create" | &"drop" | &"alter") if parts.get(1) == Some(&"user") => {
+                self.handle_user(original).map(QueryResult::Message)
+            },
+            Some(&"create" | &"drop") if parts.get(1) == Some(&"role") => {
+                self.handle_role(&parts).map(QueryResult::Message)
+            },
+            Some(&"grant" | &"revoke") => self.handle_grant(&parts).map(QueryResult::Message),
+            Some(&"create") => self.handle_create(&parts[1..]).map(QueryResult::Message),
+            Some(&"insert") => {
+                let (table, rows) = parse_insert(original, false)?;
+                self.run_plan(&Plan::Insert { table, rows }, &[])
+            },
+            Some(&"select") => self.run_plan(&plan_select(&split_words(original)[1..], false)?, &[]),
+            Some(&"describe") => self.handle_describe(&parts[1..]).map(QueryResult::Message),
+            Some(&"show") => self.handle_show(&parts[1..]).map(QueryResult::Message),
+            Some(&"drop") => self.handle_drop(&parts[1..]).map(QueryResult::Message),
+            Some(&"copy") => self.handle_copy(original).map(QueryResult::Message),
+            Some(&"backup") => self.handle_backup(original).map(QueryResult::Message),
+            Some(&"declare") => self.handle_declare(&parts[1..], original).map(QueryResult::Message),
+            Some(&"fetch") => self.handle_fetch(&parts[1..]),
+            Some(&"close") => self.handle_close(&parts[1..]).map(QueryResult::Message),
+            Some(&"prepare") => self.handle_prepare(original).map(QueryResult::Message),
+            Some(&"execute") => self.handle_execute(original),
+            Some(&"deallocate") => self.handle_deallocate(&parts[1..]).map(QueryResult::Message),
             _ => Err("Unknown command".to_string()),
         }
     }
 
     fn handle_create(&mut self, parts: &[&str]) -> Result<String, String> {
-        if parts.len() < 4 || parts[0] != "table" {
+        if parts.first() == Some(&"index") {
+            let (table_name, column) = parse_index(parts)?;
+            self.require(Privilege::Admin, None)?;
+            self.storage.create_index(table_name, &column)?;
+            return Ok(format!("Index on '{}.{}' created successfully", table_name, column));
+        }
+        if parts.len() < 3 || parts[0] != "table" {
             return Err("Invalid CREATE TABLE syntax".to_string());
         }
 
         let table_name = parts[1];
         let columns_str = parts[2..].join(" ");
+        self.require(Privilege::Admin, None)?;
 
         if !columns_str.starts_with('(') || !columns_str.ends_with(')') {
             return Err("Columns must be in parentheses".to_string());
         }
 
         let columns_part = &columns_str[1..columns_str.len() - 1];
-        let columns: Vec<String> = columns_part
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .collect();
+        let mut columns = Vec::new();
+        let mut types = Vec::new();
+        for definition in columns_part.split(',') {
+            let words: Vec<&str> = definition.split_whitespace().collect();
+            match words.as_slice() {
+                [name] => {
+                    columns.push(name.to_string());
+                    types.push(ColumnType::Text);
+                },
+                [name, column_type] => {
+                    columns.push(name.to_string());
+                    types.push(column_type.parse()?);
+                },
+                _ => return Err(format!("Invalid column definition '{}'", definition.trim())),
+            }
+        }
 
-        self.storage.create_table(table_name, columns)?;
+        self.storage.create_typed_table(table_name, columns, types)?;
         Ok(format!("Table '{}' created successfully", table_name))
     }
 
-    fn handle_insert(&mut self, parts: &[&str]) -> Result<String, String> {
-        if parts.len() < 3 || parts[0] != "into" {
-            return Err("Invalid INSERT syntax".to_string());
+    /// Checks the privilege a plan needs and runs it with `values` bound.
+    fn run_plan(&mut self, plan: &Plan, values: &[&str]) -> Result<QueryResult, String> {
+        match plan {
+            Plan::Select { table, filter, descending } => {
+                self.require(Privilege::Select, Some(table))?;
+                let condition = filter.as_ref().map(|filter| bind_filter(filter, values)).transpose()?;
+                self.select(table, condition, *descending)
+            },
+            Plan::Insert { table, rows } => {
+                self.require(Privilege::Insert, Some(table))?;
+                let rows = rows.iter().map(|row| bind_row(row, values)).collect::<Result<Vec<Row>, String>>()?;
+                self.insert(table, rows).map(QueryResult::Message)
+            },
         }
+    }
 
-        let table_name = parts[1];
-        let values_str = parts[2..].join(" ");
+    fn insert(&mut self, table_name: &str, rows: Vec<Row>) -> Result<String, String> {
+        if rows.len() == 1 {
+            let row_id = self.insert_one(table_name, rows.into_iter().next().unwrap_or_default())?;
+            return Ok(format!("Row inserted with ID: {}", row_id));
+        }
 
-        if !values_str.starts_with('(') || !values_str.ends_with(')') {
-            return Err("Values must be in parentheses".to_string());
+        let count = rows.len();
+        for (index, row) in rows.into_iter().enumerate() {
+            self.insert_one(table_name, row)
+                .map_err(|e| format!("Row {}: {} ({} row(s) inserted before the error)", index + 1, e, index))?;
         }
+        Ok(format!("{} rows inserted into '{}'", count, table_name))
+    }
 
-        let values_part = &values_str[1..values_str.len() - 1];
-        let mut row = Row::new();
+    /// A `rowid` value picks the row's ID, as in dumps; otherwise the next
+    /// free one is used.
+    fn insert_one(&mut self, table_name: &str, mut row: Row) -> Result<usize, String> {
+        match row.data.remove(ROW_ID) {
+            Some(id) => self.storage.insert_row_at(table_name, parse_row_id(&id)?, row),
+            None => self.storage.insert_row(table_name, row),
+        }
+    }
 
-        for pair in values_part.split(',') {
-            let kv: Vec<&str> = pair.trim().split('=').collect();
-            if kv.len() != 2 {
-                return Err("Invalid key=value format".to_string());
-            }
-            row.insert(kv[0].trim().to_string(), kv[1].trim().to_string());
+    /// Reads a table, or with a condition only its matching rows: `column=value`,
+    /// or a comparison on the row ID, which reads only the matching range.
+    fn select(&mut self, table_name: &str, condition: Option<Condition>, descending: bool) -> Result<QueryResult, String> {
+        let mut rows = match condition {
+            None => self.storage.select_all(table_name)?,
+            Some(Condition::RowIds(range)) => self.storage.select_range(table_name, range)?,
+            Some(Condition::Equals(column, value)) => self.storage.select_where(table_name, &column, &value)?,
+        };
+        if descending {
+            rows.reverse();
         }
 
-        let row_id = self.storage.insert_row(table_name, row)?;
-        Ok(format!("Row inserted with ID: {}", row_id))
+        Ok(QueryResult::Rows {
+            table: table_name.to_string(),
+            columns: self.storage.describe_table(table_name)?.clone(),
+            rows,
+        })
     }
 
-    fn handle_select(&mut self, parts: &[&str]) -> Result<String, String> {
-        if parts.len() < 3 || parts[0] != "*" || parts[1] != "from" {
-            return Err("Invalid SELECT syntax".to_string());
+    /// DECLARE <name> CURSOR FOR SELECT ...
+    fn handle_declare(&mut self, parts: &[&str], query: &str) -> Result<String, String> {
+        let name = match parts {
+            [name, "cursor", "for", _, ..] => *name,
+            _ => return Err("Invalid DECLARE syntax: expected DECLARE <name> CURSOR FOR SELECT ...".to_string()),
+        };
+        // The SELECT comes from the original text, so quoted values keep their case.
+        let select = split_words(query)[4..].join(" ");
+        check_name("cursor", name)?;
+        let cursor = self.open_cursor(&select)?;
+        self.session_cursors()?.declare(name, cursor)?;
+        Ok(format!("Cursor '{}' declared", name))
+    }
+
+    /// FETCH [NEXT | ALL | <count>] [FROM | IN] <name>
+    fn handle_fetch(&mut self, parts: &[&str]) -> Result<QueryResult, String> {
+        let parts = match parts {
+            [rest @ .., from, name] if matches!(*from, "from" | "in") => [rest, &[*name]].concat(),
+            _ => parts.to_vec(),
+        };
+        let (count, name) = match parts.as_slice() {
+            [name] => (1, *name),
+            ["next", name] => (1, *name),
+            ["all", name] => (usize::MAX, *name),
+            [count, name] => (count.parse().map_err(|_| format!("Invalid FETCH count '{}'", count))?, *name),
+            _ => return Err("Invalid FETCH syntax: expected FETCH [NEXT | ALL | <count>] FROM <cursor>".to_string()),
+        };
+        // The cursor is taken out while it reads, since reading borrows the engine.
+        let mut cursor = self.session_cursors()?.get_mut(name)?.clone();
+        let result = self.fetch(&mut cursor, count)?;
+        *self.session_cursors()?.get_mut(name)? = cursor;
+        Ok(result)
+    }
+
+    /// CLOSE <name> or CLOSE ALL
+    fn handle_close(&mut self, parts: &[&str]) -> Result<String, String> {
+        match parts {
+            ["all"] => {
+                let closed = self.session_cursors()?.close_all();
+                Ok(format!("Closed {} cursor(s)", closed))
+            },
+            [name] => {
+                self.session_cursors()?.close(name)?;
+                Ok(format!("Cursor '{}' closed", name))
+            },
+            _ => Err("Invalid CLOSE syntax: expected CLOSE <cursor> or CLOSE ALL".to_string()),
         }
+    }
 
-        let table_name = parts[2];
+    fn session_cursors(&mut self) -> Result<&mut Cursors, String> {
+        self.cursors.as_deref_mut().ok_or_else(|| "Cursors can only be used in a session: the shell, atom exec or a TCP connection".to_string())
+    }
 
-        let rows = if parts.len() > 3 && parts[3] == "where" {
-            if parts.len() < 5 {
-                return Err("Invalid WHERE clause".to_string());
-            }
-            let condition = parts[4];
-            let condition_parts: Vec<&str> = condition.split('=').collect();
-            if condition_parts.len() != 2 {
-                return Err("Invalid WHERE condition".to_string());
-            }
-            self.storage.select_where(table_name, condition_parts[0], condition_parts[1])?
-        } else {
-            self.storage.select_all(table_name)?
-        };
+    /// PREPARE <name> AS <SELECT or INSERT>
+    fn handle_prepare(&mut self, query: &str) -> Result<String, String> {
+        let rest = query.get("prepare".len()..).unwrap_or("").trim_start();
+        let (name, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let (keyword, statement) = rest.trim_start().split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if !keyword.eq_ignore_ascii_case("as") || statement.trim().is_empty() {
+            return Err("Invalid PREPARE syntax: expected PREPARE <name> AS <statement>".to_string());
+        }
+        let name = name.to_lowercase();
+        check_name("prepared statement", &name)?;
+        let plan = self.prepare(statement)?;
+        let parameters = plan.parameters();
+        self.session_prepared()?.prepare(&name, plan)?;
+        Ok(format!("Statement '{}' prepared with {} parameter(s)", name, parameters))
+    }
 
-        let mut result = format!("Results from table '{}':\n", table_name);
-        for (id, row) in rows {
-            result.push_str(&format!("ID: {} | {:?}\n", id, row.data));
+    /// EXECUTE <name> [(value, ...)]
+    fn handle_execute(&mut self, query: &str) -> Result<QueryResult, String> {
+        let rest = query.get("execute".len()..).unwrap_or("").trim_start();
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '(').unwrap_or(rest.len());
+        let name = rest[..name_end].to_lowercase();
+        let arguments = rest[name_end..].trim();
+        if name.is_empty() || !(arguments.is_empty() || (arguments.starts_with('(') && arguments.ends_with(')'))) {
+            return Err("Invalid EXECUTE syntax: expected EXECUTE <name>(value, ...)".to_string());
         }
-        Ok(result)
+        let values: Vec<String> = tokenize(arguments)?.into_iter()
+            .map(|token| match token {
+                Token::Word(value) | Token::Quoted(value) => value,
+            })
+            .collect();
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+        let plan = self.session_prepared()?.get(&name)?.clone();
+        if values.len() != plan.parameters() {
+            return Err(format!("Statement '{}' takes {} parameter(s), got {}", name, plan.parameters(), values.len()));
+        }
+        self.run_plan(&plan, &values)
+    }
+
+    /// DEALLOCATE [PREPARE] <name> or DEALLOCATE ALL
+    fn handle_deallocate(&mut self, parts: &[&str]) -> Result<String, String> {
+        let parts = parts.strip_prefix(&["prepare"]).unwrap_or(parts);
+        match parts {
+            ["all"] => {
+                let deallocated = self.session_prepared()?.deallocate_all();
+                Ok(format!("Deallocated {} prepared statement(s)", deallocated))
+            },
+            [name] => {
+                self.session_prepared()?.deallocate(name)?;
+                Ok(format!("Statement '{}' deallocated", name))
+            },
+            _ => Err("Invalid DEALLOCATE syntax: expected DEALLOCATE <name> or DEALLOCATE ALL".to_string()),
+        }
+    }
+
+    fn session_prepared(&mut self) -> Result<&mut PreparedStatements, String> {
+        self.prepared.as_deref_mut().ok_or_else(|| "Prepared statements can only be used in a session: the shell, atom exec or a TCP connection".to_string())
     }
 
     fn handle_describe(&mut self, parts: &[&str]) -> Result<String, String> {
@@ -106,12 +398,26 @@ impl<'a> QueryEngine<'a> {
             return Err("Table name required".to_string());
         }
         let table_name = parts[0];
-        let columns = self.storage.describe_table(table_name)?;
-        Ok(format!("Table '{}' columns: {:?}", table_name, columns))
+        self.require(Privilege::Select, Some(table_name))?;
+        let columns = self.storage.describe_columns(table_name)?;
+        let description = if columns.iter().all(|(_, column_type)| *column_type == ColumnType::Text) {
+            let names: Vec<&String> = columns.iter().map(|(name, _)| name).collect();
+            format!("Table '{}' columns: {:?}", table_name, names)
+        } else {
+            let definitions: Vec<String> = columns.iter()
+                .map(|(name, column_type)| format!("{} {}", name, column_type))
+                .collect();
+            format!("Table '{}' columns: {:?}", table_name, definitions)
+        };
+        let indexes = self.storage.list_indexes(table_name)?;
+        if indexes.is_empty() {
+            return Ok(description);
+        }
+        Ok(format!("{}, indexes: {:?}", description, indexes))
     }
 
     fn handle_show(&mut self, parts: &[&str]) -> Result<String, String> {
-        if parts.get(0) == Some(&"tables") {
+        if parts.first() == Some(&"tables") {
             let tables = self.storage.list_tables();
             Ok(format!("Tables: {:?}", tables))
         } else {
@@ -120,11 +426,579 @@ impl<'a> QueryEngine<'a> {
     }
 
     fn handle_drop(&mut self, parts: &[&str]) -> Result<String, String> {
+        if parts.first() == Some(&"index") {
+            let (table_name, column) = parse_index(parts)?;
+            self.require(Privilege::Admin, None)?;
+            self.storage.drop_index(table_name, &column)?;
+            return Ok(format!("Index on '{}.{}' dropped successfully", table_name, column));
+        }
         if parts.len() < 2 || parts[0] != "table" {
             return Err("Invalid DROP TABLE syntax".to_string());
         }
         let table_name = parts[1];
+        self.require(Privilege::Admin, None)?;
         self.storage.drop_table(table_name)?;
+        Grants::new(self.storage).forget_table(table_name)?;
         Ok(format!("Table '{}' dropped successfully", table_name))
     }
+
+    /// CREATE USER <name> [WITH] PASSWORD '<password>', ALTER USER with the
+    /// same form, and DROP USER <name>.
+    fn handle_user(&mut self, query: &str) -> Result<String, String> {
+        let tokens = tokenize(query)?;
+        let (verb, name, rest) = match tokens.as_slice() {
+            [Token::Word(verb), _, Token::Word(name), rest @ ..] => (verb.as_str(), name, rest),
+            _ => return Err("Invalid user statement: expected a user name".to_string()),
+        };
+        let password = match rest {
+            [Token::Word(with), Token::Word(password), Token::Quoted(value)]
+                if with == "with" && password == "password" => Some(value),
+            [Token::Word(password), Token::Quoted(value)] if password == "password" => Some(value),
+            [] => None,
+            _ => return Err(invalid_user_syntax(verb)),
+        };
+        if verb != "alter" || self.user.as_deref() != Some(name.as_str()) {
+            self.require(Privilege::Admin, None)?;
+        }
+
+        let mut users = Users::new(self.storage);
+        match (verb, password) {
+            ("create", Some(password)) => {
+                users.create(name, password)?;
+                Ok(format!("User '{}' created", name))
+            },
+            ("alter", Some(password)) => {
+                users.set_password(name, password)?;
+                Ok(format!("Password changed for user '{}'", name))
+            },
+            ("drop", None) => {
+                users.drop(name)?;
+                Ok(format!("User '{}' dropped", name))
+            },
+            _ => Err(invalid_user_syntax(verb)),
+        }
+    }
+
+    /// CREATE ROLE <name> and DROP ROLE <name>.
+    fn handle_role(&mut self, parts: &[&str]) -> Result<String, String> {
+        let (verb, name) = match parts {
+            [verb, _, name] => (*verb, *name),
+            _ => return Err(format!("Invalid {} ROLE syntax: expected {} ROLE <name>",
+                parts[0].to_uppercase(), parts[0].to_uppercase())),
+        };
+        self.require(Privilege::Admin, None)?;
+        let mut grants = Grants::new(self.storage);
+        if verb == "create" {
+            grants.create_role(name)?;
+            Ok(format!("Role '{}' created", name))
+        } else {
+            grants.drop_role(name)?;
+            Ok(format!("Role '{}' dropped", name))
+        }
+    }
+
+    /// GRANT <privileges> ON <tables> TO <grantee>, GRANT ADMIN TO <grantee>,
+    /// GRANT <role> TO <grantee>, and REVOKE with FROM in place of TO.
+    /// Privileges are SELECT, INSERT or ALL [PRIVILEGES].
+    fn handle_grant(&mut self, parts: &[&str]) -> Result<String, String> {
+        let words: Vec<&str> = parts.iter()
+            .flat_map(|part| part.split(','))
+            .map(|word| word.trim_end_matches(';'))
+            .filter(|word| !word.is_empty())
+            .collect();
+        let granting = words[0] == "grant";
+        let (keyword, verb) = if granting { ("to", "Granted") } else { ("from", "Revoked") };
+        let invalid = || format!("Invalid {} syntax: expected {} <privileges> ON <table> {} <user or role>",
+            words[0].to_uppercase(), words[0].to_uppercase(), keyword.to_uppercase());
+
+        let grantee = match words.iter().position(|word| *word == keyword) {
+            Some(index) if index > 1 && index + 2 == words.len() => words[index + 1],
+            _ => return Err(invalid()),
+        };
+        let items = &words[1..words.len() - 2];
+        self.require(Privilege::Admin, None)?;
+        let mut grants = Grants::new(self.storage);
+
+        let Some(on) = items.iter().position(|word| *word == "on") else {
+            let mut changed = false;
+            for item in items {
+                changed |= match (*item, granting) {
+                    ("admin", true) => grants.grant(Privilege::Admin, None, grantee).map(|_| true)?,
+                    ("admin", false) => grants.revoke(Privilege::Admin, None, grantee)?,
+                    (role, true) => grants.grant_role(role, grantee).map(|_| true)?,
+                    (role, false) => grants.revoke_role(role, grantee)?,
+                };
+            }
+            let names: Vec<&str> = items.iter().map(|item| if *item == "admin" { "ADMIN" } else { item }).collect();
+            if !changed {
+                return Err(format!("'{}' holds none of {}", grantee, names.join(", ")));
+            }
+            return Ok(format!("{} {} {} {}", verb, names.join(", "), keyword, grantee));
+        };
+
+        let (privileges, tables) = (&items[..on], &items[on + 1..]);
+        let mut parsed = Vec::new();
+        for word in privileges {
+            match *word {
+                "all" => parsed.extend([Privilege::Select, Privilege::Insert]),
+                "privileges" if parsed.len() == 2 => {},
+                word => parsed.push(word.parse()?),
+            }
+        }
+        if parsed.is_empty() || tables.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut changed = false;
+        for table in tables {
+            for privilege in &parsed {
+                if granting {
+                    grants.grant(*privilege, Some(table), grantee)?;
+                    changed = true;
+                } else {
+                    changed |= grants.revoke(*privilege, Some(table), grantee)?;
+                }
+            }
+        }
+        let names: Vec<String> = parsed.iter().map(Privilege::to_string).collect();
+        if !changed {
+            return Err(format!("'{}' holds none of {} on {}", grantee, names.join(", "), tables.join(", ")));
+        }
+        Ok(format!("{} {} on {} {} {}", verb, names.join(", "), tables.join(", "), keyword, grantee))
+    }
+
+    /// Fails with a permission-denied error unless the session's user holds
+    /// `privilege` on `table` (or database-wide when `table` is `None`).
+    fn require(&mut self, privilege: Privilege, table: Option<&str>) -> Result<(), String> {
+        let Some(user) = &self.user else {
+            return Ok(());
+        };
+        if Grants::new(self.storage).allows(user, privilege, table)? {
+            return Ok(());
+        }
+        Err(permission_denied(user, privilege, table))
+    }
+
+    /// Writes a snapshot of the whole database. Callers hold the engine lock,
+    /// so the copy is consistent while other clients wait.
+    fn handle_backup(&mut self, query: &str) -> Result<String, String> {
+        let tokens = tokenize(query)?;
+        let path = match tokens.as_slice() {
+            [_, Token::Word(to), Token::Quoted(path)] if to == "to" => path,
+            _ => return Err("Invalid BACKUP syntax: expected BACKUP TO '<path>'".to_string()),
+        };
+        self.require(Privilege::Admin, None)?;
+        write_snapshot(self.storage, Path::new(path), &self.file_options)?;
+        Ok(format!("Backup written to '{}' at LSN {}", path, self.storage.lsn()))
+    }
+
+    fn handle_copy(&mut self, query: &str) -> Result<String, String> {
+        let mut tokens = tokenize(query)?.into_iter().skip(1);
+
+        let table_name = match tokens.next() {
+            Some(Token::Word(name)) => name,
+            _ => return Err("Invalid COPY syntax: expected a table name".to_string()),
+        };
+        let direction = match tokens.next() {
+            Some(Token::Word(word)) if word == "from" || word == "to" => word,
+            _ => return Err("Invalid COPY syntax: expected FROM or TO".to_string()),
+        };
+        let path = match tokens.next() {
+            Some(Token::Quoted(path)) => path,
+            _ => return Err("Invalid COPY syntax: expected a quoted file path".to_string()),
+        };
+        // COPY reads and writes files on the server, so it needs more than a table privilege.
+        self.require(Privilege::Admin, None)?;
+
+        let mut options = CsvOptions::default();
+        let mut format = "csv".to_string();
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Word(word) if word == "with" || word == "format" => {},
+                Token::Word(word) if matches!(word.as_str(), "csv" | "json" | "ndjson") => format = word,
+                Token::Word(word) if word == "header" => options.header = true,
+                Token::Word(word) if word == "delimiter" => options.delimiter = option_char(tokens.next(), "DELIMITER")?,
+                Token::Word(word) if word == "quote" => options.quote = option_char(tokens.next(), "QUOTE")?,
+                Token::Word(word) | Token::Quoted(word) => return Err(format!("Unknown COPY option '{}'", word)),
+            }
+        }
+
+        if direction == "from" {
+            let input = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+            let report = match format.as_str() {
+                "csv" => import_csv(self.storage, &table_name, &input, &options)?,
+                _ => import_json(self.storage, &table_name, &input)?,
+            };
+            Ok(report.summary(&table_name))
+        } else {
+            let (output, count) = match format.as_str() {
+                "csv" => export_csv(self.storage, &table_name, &options)?,
+                _ => export_json(self.storage, &table_name, format == "ndjson")?,
+            };
+            std::fs::write(&path, output).map_err(|e| format!("Cannot write '{}': {}", path, e))?;
+            Ok(format!("Copied {} row(s) from '{}' to '{}'", count, table_name, path))
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// Bare word, lowercased.
+    Word(String),
+    /// Single- or double-quoted literal with its case preserved.
+    Quoted(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || matches!(c, ',' | '(' | ')' | ';') {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            tokens.push(Token::Quoted(read_quoted(&mut chars, c)?));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || matches!(c, ',' | '(' | ')' | ';' | '\'' | '"') {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word.to_lowercase()));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads up to the closing `quote` (the opening one already consumed); a
+/// doubled quote stands for one quote character.
+fn read_quoted(chars: &mut Peekable<Chars>, quote: char) -> Result<String, String> {
+    let mut literal = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == quote && chars.peek() == Some(&quote) => {
+                chars.next();
+                literal.push(quote);
+            },
+            Some(c) if c == quote => return Ok(literal),
+            Some(c) => literal.push(c),
+            None => return Err("Unterminated quoted string".to_string()),
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn invalid_user_syntax(verb: &str) -> String {
+    match verb {
+        "drop" => "Invalid DROP USER syntax: expected DROP USER <name>".to_string(),
+        verb => format!("Invalid {} USER syntax: expected {} USER <name> PASSWORD '<password>'",
+            verb.to_uppercase(), verb.to_uppercase()),
+    }
+}
+
+/// Parses `INDEX ON <table> (<column>)`, the rest of CREATE INDEX and
+/// DROP INDEX, into the table and column names.
+fn parse_index<'q>(parts: &[&'q str]) -> Result<(&'q str, String), String> {
+    let invalid = || "Invalid INDEX syntax: expected INDEX ON <table> (<column>)".to_string();
+    let [_, on, table, column @ ..] = parts else {
+        return Err(invalid());
+    };
+    let column = column.join("");
+    let column = column.strip_prefix('(').and_then(|c| c.strip_suffix(')')).ok_or_else(invalid)?;
+    if *on != "on" || column.is_empty() || column.contains(',') {
+        return Err(invalid());
+    }
+    Ok((table, column.to_string()))
+}
+
+/// Parses `INSERT INTO <table> (col=value, ...)[, (col=value, ...)]`. Quoted
+/// values keep their case and may contain commas, parentheses and doubled
+/// quotes; bare values are lowercased like the rest of the statement.
+/// A bare `$n` is a parameter when `parameters` is set.
+fn parse_insert(query: &str, parameters: bool) -> Result<(String, Vec<PlannedRow>), String> {
+    let invalid = || "Invalid INSERT syntax".to_string();
+    let mut words = query.trim().splitn(3, char::is_whitespace);
+    if !words.next().is_some_and(|w| w.eq_ignore_ascii_case("insert")) {
+        return Err(invalid());
+    }
+    let rest = words.next().filter(|w| w.eq_ignore_ascii_case("into"))
+        .and(words.next())
+        .map(str::trim_start)
+        .ok_or_else(invalid)?;
+
+    let name_end = rest.find(|c: char| c.is_whitespace() || c == '(').unwrap_or(rest.len());
+    let table_name = rest[..name_end].to_lowercase();
+    if table_name.is_empty() {
+        return Err(invalid());
+    }
+
+    let not_parenthesized = || "Values must be in parentheses".to_string();
+    let invalid_pair = || "Invalid key=value format".to_string();
+    let mut chars = rest[name_end..].chars().peekable();
+    let mut rows = Vec::new();
+
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next() != Some('(') {
+            return Err(not_parenthesized());
+        }
+
+        let mut row = Vec::new();
+        loop {
+            let mut column = String::new();
+            while let Some(&c) = chars.peek() {
+                if matches!(c, '=' | ',' | ')') {
+                    break;
+                }
+                column.push(c);
+                chars.next();
+            }
+            if chars.next() != Some('=') || column.trim().is_empty() {
+                return Err(invalid_pair());
+            }
+
+            skip_whitespace(&mut chars);
+            let value = match chars.peek().copied() {
+                Some(quote) if quote == '\'' || quote == '"' => {
+                    chars.next();
+                    let value = read_quoted(&mut chars, quote)?;
+                    skip_whitespace(&mut chars);
+                    Operand::Value(value)
+                },
+                _ => {
+                    let mut value = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == ',' || c == ')' {
+                            break;
+                        }
+                        value.push(c);
+                        chars.next();
+                    }
+                    if value.contains('=') {
+                        return Err(invalid_pair());
+                    }
+                    Operand::parse(&value.trim().to_lowercase(), parameters)?
+                }
+            };
+            row.push((column.trim().to_lowercase(), value));
+
+            match chars.next() {
+                Some(',') => continue,
+                Some(')') => break,
+                _ => return Err(not_parenthesized()),
+            }
+        }
+        rows.push(row);
+
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => continue,
+            None | Some(';') => break,
+            _ => return Err(not_parenthesized()),
+        }
+    }
+    Ok((table_name, rows))
+}
+
+fn option_char(token: Option<Token>, option: &str) -> Result<char, String> {
+    let value = match token {
+        Some(Token::Quoted(value)) => value,
+        _ => return Err(format!("{} needs a quoted character", option)),
+    };
+    if value == "\\t" {
+        return Ok('\t');
+    }
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("{} must be a single character", option)),
+    }
+}
+
+/// Splits `* FROM <table> [WHERE <condition>] [ORDER BY rowid [ASC|DESC]]`,
+/// given as words of the original text, into the table, the condition and
+/// whether the order is descending. The condition keeps its original case.
+fn parse_select(words: &[&str]) -> Result<(String, Option<String>, bool), String> {
+    let lowered: Vec<String> = words.iter().map(|w| w.to_lowercase()).collect();
+    let parts: Vec<&str> = lowered.iter().map(String::as_str).collect();
+    if parts.len() < 3 || parts[0] != "*" || parts[1] != "from" {
+        return Err("Invalid SELECT syntax".to_string());
+    }
+
+    let table_name = parts[2].to_string();
+    let clauses = &parts[3..];
+    let order_at = clauses.iter().position(|w| *w == "order").unwrap_or(clauses.len());
+    let (filter, order) = clauses.split_at(order_at);
+
+    let descending = match order {
+        [] | ["order", "by", ROW_ID] | ["order", "by", ROW_ID, "asc"] => false,
+        ["order", "by", ROW_ID, "desc"] => true,
+        ["order", "by", _, ..] => return Err(format!("Only ORDER BY {} [ASC|DESC] is supported", ROW_ID)),
+        _ => return Err("Invalid ORDER BY clause".to_string()),
+    };
+
+    let condition = match filter {
+        [] => None,
+        ["where", ..] => Some(words[4..3 + order_at].join(" ")),
+        _ => return Err("Invalid SELECT syntax".to_string()),
+    };
+    Ok((table_name, condition, descending))
+}
+
+fn plan_select(words: &[&str], parameters: bool) -> Result<Plan, String> {
+    let (table, condition, descending) = parse_select(words)?;
+    Ok(Plan::Select {
+        table,
+        filter: condition.map(|condition| plan_condition(&condition, parameters)).transpose()?,
+        descending,
+    })
+}
+
+/// A WHERE condition with its parameters bound: a range of row IDs, or a
+/// column equal to a value.
+enum Condition {
+    RowIds(RowIdRange),
+    Equals(String, String),
+}
+
+/// Parses `column=value` or a row ID comparison. Values are read as INSERT
+/// reads them: quoted ones keep their case, bare ones are lowercased.
+fn plan_condition(condition: &str, parameters: bool) -> Result<Filter, String> {
+    if condition.is_empty() {
+        return Err("Invalid WHERE clause".to_string());
+    }
+    if let Some((low, high)) = split_row_id_range(&condition.to_lowercase())? {
+        let operand = |text| Operand::parse(text, parameters);
+        return Ok(Filter::RowIds(map_bound(low, operand)?, map_bound(high, operand)?));
+    }
+    let invalid = || "Invalid WHERE condition".to_string();
+    let (column, value) = condition.split_once('=').ok_or_else(invalid)?;
+    let column = column.trim().to_lowercase();
+    let value = value.trim();
+    if column.is_empty() {
+        return Err(invalid());
+    }
+    let value = match value.chars().next() {
+        Some(quote) if quote == '\'' || quote == '"' => {
+            let mut chars = value[1..].chars().peekable();
+            let literal = read_quoted(&mut chars, quote)?;
+            if chars.next().is_some() {
+                return Err(invalid());
+            }
+            Operand::Value(literal)
+        },
+        _ if value.contains('=') => return Err(invalid()),
+        _ => Operand::parse(&value.to_lowercase(), parameters)?,
+    };
+    Ok(Filter::Equals(column, value))
+}
+
+/// Splits on whitespace outside single or double quotes, so that a quoted
+/// value stays one word.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {},
+            None if c.is_whitespace() => {
+                if let Some(begin) = start.take() {
+                    words.push(&text[begin..index]);
+                }
+                continue;
+            },
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None => {},
+        }
+        start.get_or_insert(index);
+    }
+    if let Some(begin) = start {
+        words.push(&text[begin..]);
+    }
+    words
+}
+
+fn bind_filter(filter: &Filter, values: &[&str]) -> Result<Condition, String> {
+    match filter {
+        Filter::RowIds(low, high) => {
+            let row_id = |bound: &Bound<Operand>| map_bound(bound.as_ref(), |operand| parse_row_id(operand.bind(values)?));
+            Ok(Condition::RowIds((row_id(low)?, row_id(high)?)))
+        },
+        Filter::Equals(column, value) => Ok(Condition::Equals(column.clone(), value.bind(values)?.to_string())),
+    }
+}
+
+fn bind_row(row: &[(String, Operand)], values: &[&str]) -> Result<Row, String> {
+    let mut bound = Row::new();
+    for (column, value) in row {
+        bound.insert(column.clone(), value.bind(values)?.to_string());
+    }
+    Ok(bound)
+}
+
+fn map_bound<T, U>(bound: Bound<T>, f: impl FnOnce(T) -> Result<U, String>) -> Result<Bound<U>, String> {
+    Ok(match bound {
+        Bound::Included(value) => Bound::Included(f(value)?),
+        Bound::Excluded(value) => Bound::Excluded(f(value)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+
+/// Cursor and prepared statement names are plain identifiers; `all` is
+/// kept for CLOSE ALL and DEALLOCATE ALL.
+fn check_name(kind: &str, name: &str) -> Result<(), String> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("Invalid {} name '{}': use letters, digits and underscores", kind, name));
+    }
+    if name == "all" {
+        return Err(format!("'all' cannot name a {}", kind));
+    }
+    Ok(())
+}
+
+/// The text of the low and high bounds of a row ID condition.
+type RowIdBounds<'c> = (Bound<&'c str>, Bound<&'c str>);
+
+/// Splits `rowid` compared with `=`, `<`, `<=`, `>` or `>=`, or
+/// `rowid BETWEEN <low> AND <high>`, into the text of its bounds. Returns
+/// `None` for other conditions.
+fn split_row_id_range(condition: &str) -> Result<Option<RowIdBounds<'_>>, String> {
+    let Some(rest) = condition.strip_prefix(ROW_ID).map(str::trim_start) else {
+        return Ok(None);
+    };
+    if let Some(bounds) = rest.strip_prefix("between ") {
+        let (low, high) = bounds.split_once(" and ")
+            .ok_or_else(|| format!("Invalid BETWEEN: expected {} BETWEEN <low> AND <high>", ROW_ID))?;
+        return Ok(Some((Bound::Included(low.trim()), Bound::Included(high.trim()))));
+    }
+
+    let Some((operator, value)) = [">=", "<=", "=", ">", "<"].iter()
+        .find_map(|op| rest.strip_prefix(op).map(|value| (*op, value.trim()))) else {
+        return Ok(None);
+    };
+    Ok(Some(match operator {
+        "=" => (Bound::Included(value), Bound::Included(value)),
+        "<" => (Bound::Unbounded, Bound::Excluded(value)),
+        "<=" => (Bound::Unbounded, Bound::Included(value)),
+        ">" => (Bound::Excluded(value), Bound::Unbounded),
+        _ => (Bound::Included(value), Bound::Unbounded),
+    }))
+}
+
+fn parse_row_id(value: &str) -> Result<usize, String> {
+    value.trim().parse().map_err(|_| format!("'{}' is not a valid row ID", value.trim()))
 }
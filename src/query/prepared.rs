@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// Prepared statements one session may keep at once.
+pub const MAX_PREPARED: usize = 256;
+
+/// A value in a statement: written into it, or the parameter `$n`, bound
+/// when the statement runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Value(String),
+    Parameter(usize),
+}
+
+impl Operand {
+    /// `$n` is a parameter where `parameters` allows them, as in a statement
+    /// being prepared; anything else, and `$n` elsewhere, is the value itself.
+    pub fn parse(text: &str, parameters: bool) -> Result<Self, String> {
+        match text.strip_prefix('$') {
+            Some(digits) if parameters && !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) => {
+                match digits.parse() {
+                    Ok(0) | Err(_) => Err(format!("Invalid parameter '{}': parameters are numbered from $1", text)),
+                    Ok(index) => Ok(Operand::Parameter(index)),
+                }
+            },
+            _ => Ok(Operand::Value(text.to_string())),
+        }
+    }
+
+    /// The value of this operand with `values` bound to `$1`, `$2`, ...
+    pub fn bind<'v>(&'v self, values: &[&'v str]) -> Result<&'v str, String> {
+        match self {
+            Operand::Value(value) => Ok(value),
+            Operand::Parameter(index) => values.get(index - 1).copied()
+                .ok_or_else(|| format!("Parameter ${} has no value; parameters are bound with EXECUTE", index)),
+        }
+    }
+
+    fn parameter(&self) -> usize {
+        match self {
+            Operand::Value(_) => 0,
+            Operand::Parameter(index) => *index,
+        }
+    }
+}
+
+/// The columns and values of one row of an INSERT.
+pub type PlannedRow = Vec<(String, Operand)>;
+
+/// A WHERE condition before its parameters are bound.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Row IDs between two bounds.
+    RowIds(Bound<Operand>, Bound<Operand>),
+    /// A column equal to a value.
+    Equals(String, Operand),
+}
+
+/// A parsed statement, run again by binding its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    Select { table: String, filter: Option<Filter>, descending: bool },
+    Insert { table: String, rows: Vec<PlannedRow> },
+}
+
+impl Plan {
+    pub fn table(&self) -> &str {
+        match self {
+            Plan::Select { table, .. } | Plan::Insert { table, .. } => table,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(self, Plan::Insert { .. })
+    }
+
+    /// The highest `$n` in the plan, which is how many values it takes.
+    pub fn parameters(&self) -> usize {
+        let bound = |bound: &Bound<Operand>| match bound {
+            Bound::Included(operand) | Bound::Excluded(operand) => operand.parameter(),
+            Bound::Unbounded => 0,
+        };
+        match self {
+            Plan::Select { filter: None, .. } => 0,
+            Plan::Select { filter: Some(Filter::RowIds(low, high)), .. } => bound(low).max(bound(high)),
+            Plan::Select { filter: Some(Filter::Equals(_, operand)), .. } => operand.parameter(),
+            Plan::Insert { rows, .. } => rows.iter().flatten().map(|(_, operand)| operand.parameter()).max().unwrap_or(0),
+        }
+    }
+}
+
+/// The statements a session has prepared, by name, kept as plans so that
+/// running one again skips parsing.
+#[derive(Debug, Default)]
+pub struct PreparedStatements {
+    plans: BTreeMap<String, Plan>,
+}
+
+impl PreparedStatements {
+    pub fn prepare(&mut self, name: &str, plan: Plan) -> Result<(), String> {
+        if self.plans.contains_key(name) {
+            return Err(format!("Prepared statement '{}' already exists", name));
+        }
+        if self.plans.len() >= MAX_PREPARED {
+            return Err(format!("Too many prepared statements (at most {})", MAX_PREPARED));
+        }
+        self.plans.insert(name.to_string(), plan);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Plan, String> {
+        self.plans.get(name).ok_or_else(|| format!("Prepared statement '{}' does not exist", name))
+    }
+
+    pub fn deallocate(&mut self, name: &str) -> Result<(), String> {
+        self.plans.remove(name).map(|_| ()).ok_or_else(|| format!("Prepared statement '{}' does not exist", name))
+    }
+
+    /// Drops every prepared statement, returning how many there were.
+    pub fn deallocate_all(&mut self) -> usize {
+        let deallocated = self.plans.len();
+        self.plans.clear();
+        deallocated
+    }
+
+    /// Whether `query` is an EXECUTE of a statement that writes, so that
+    /// callers know to save afterwards.
+    pub fn is_write_query(&self, query: &str) -> bool {
+        let mut words = query.split(|c: char| c.is_whitespace() || c == '(');
+        words.next().is_some_and(|w| w.eq_ignore_ascii_case("execute"))
+            && words.find(|w| !w.is_empty())
+                .and_then(|name| self.plans.get(&name.to_lowercase()))
+                .is_some_and(Plan::is_write)
+    }
+}
@@ -0,0 +1,67 @@
+use crate::cli::format::json_object;
+use crate::database::Row;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryResult {
+    /// Rows in row ID order, with the table's columns in schema order.
+    Rows {
+        table: String,
+        columns: Vec<String>,
+        rows: Vec<(usize, Row)>,
+    },
+    Message(String),
+}
+
+impl QueryResult {
+    /// Returns the values of each row in column order.
+    pub fn values(&self) -> Vec<Vec<String>> {
+        match self {
+            QueryResult::Rows { columns, rows, .. } => rows.iter()
+                .map(|(_, row)| columns.iter().map(|c| row.get(c).cloned().unwrap_or_default()).collect())
+                .collect(),
+            QueryResult::Message(_) => Vec::new(),
+        }
+    }
+
+    /// Plain-text rendering used by the TCP server.
+    pub fn to_text(&self) -> String {
+        self.text_chunks(usize::MAX).collect()
+    }
+
+    /// The plain-text rendering in pieces: the first line, then up to
+    /// `rows_per_chunk` rows at a time, so that a large result can be sent
+    /// while the rest is still being rendered. Each row is one line holding
+    /// its ID and the JSON object the shell's `jsonl` format prints.
+    pub fn text_chunks(&self, rows_per_chunk: usize) -> impl Iterator<Item = String> + '_ {
+        let (first, columns, rows) = match self {
+            QueryResult::Rows { table, columns, rows } => (format!("Results from table '{}':\n", table), columns.as_slice(), rows.as_slice()),
+            QueryResult::Message(message) => (message.clone(), &[][..], &[][..]),
+        };
+        let chunks = rows.chunks(rows_per_chunk.max(1)).map(move |chunk| {
+            chunk.iter().map(|(id, row)| {
+                let values: Vec<String> = columns.iter().map(|c| row.get(c).cloned().unwrap_or_default()).collect();
+                format!("ID: {} | {}\n", id, json_object(columns, &values))
+            }).collect()
+        });
+        std::iter::once(first).chain(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_rendered_in_schema_order() {
+        let columns: Vec<String> = ["zeta", "alpha", "mid"].iter().map(|c| c.to_string()).collect();
+        let mut row = Row::new();
+        for (column, value) in columns.iter().zip(["1", "two\nlines", "say \"hi\""]) {
+            row.insert(column.clone(), value.to_string());
+        }
+        let result = QueryResult::Rows { table: "t".to_string(), columns, rows: vec![(7, row)] };
+        assert_eq!(
+            result.to_text(),
+            "Results from table 't':\nID: 7 | {\"zeta\": \"1\", \"alpha\": \"two\\nlines\", \"mid\": \"say \\\"hi\\\"\"}\n",
+        );
+    }
+}
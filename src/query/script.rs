@@ -0,0 +1,149 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub text: String,
+    /// 1-based line on which the statement starts.
+    pub line: usize,
+}
+
+/// Splits a script into statements on `;`, ignoring semicolons inside quoted
+/// strings and `--` line comments. A trailing statement without `;` is kept.
+pub fn split_statements(input: &str) -> Vec<Statement> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut start_line = 1;
+    let mut line = 1;
+    let mut quote: Option<char> = None;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+        }
+
+        match quote {
+            Some(q) => {
+                current.push(c);
+                if c == q {
+                    quote = None;
+                }
+            },
+            None if c == '-' && chars.peek() == Some(&'-') => {
+                for skipped in chars.by_ref() {
+                    if skipped == '\n' {
+                        line += 1;
+                        current.push('\n');
+                        break;
+                    }
+                }
+            },
+            None if c == ';' => {
+                push_statement(&mut statements, &current, start_line);
+                current.clear();
+            },
+            None => {
+                if current.trim().is_empty() && !c.is_whitespace() {
+                    start_line = line;
+                }
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+                current.push(c);
+            }
+        }
+    }
+
+    push_statement(&mut statements, &current, start_line);
+    statements
+}
+
+/// Whether `input` ends with a `;` outside quotes and comments, so that a
+/// multi-line entry can be executed.
+pub fn is_complete(input: &str) -> bool {
+    let mut complete = true;
+    let mut quote: Option<char> = None;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+            },
+            None if c == '-' && chars.peek() == Some(&'-') => {
+                for skipped in chars.by_ref() {
+                    if skipped == '\n' {
+                        break;
+                    }
+                }
+            },
+            None if c == ';' => complete = true,
+            None if c.is_whitespace() => {},
+            None => {
+                complete = false;
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+            }
+        }
+    }
+
+    complete && quote.is_none()
+}
+
+fn push_statement(statements: &mut Vec<Statement>, text: &str, line: usize) {
+    let text = text.trim();
+    if !text.is_empty() {
+        statements.push(Statement { text: text.to_string(), line });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(input: &str) -> Vec<String> {
+        split_statements(input).into_iter().map(|statement| statement.text).collect()
+    }
+
+    #[test]
+    fn statements_end_at_semicolons_outside_quotes() {
+        assert_eq!(texts("SHOW TABLES; DESCRIBE t;"), ["SHOW TABLES", "DESCRIBE t"]);
+        assert_eq!(texts("INSERT INTO t (a='x;y'); INSERT INTO t (a=\"p;q\")"), ["INSERT INTO t (a='x;y')", "INSERT INTO t (a=\"p;q\")"]);
+        assert_eq!(texts("INSERT INTO t (a='It''s; here', b=\"say \"\"hi\"\";\")"), ["INSERT INTO t (a='It''s; here', b=\"say \"\"hi\"\";\")"]);
+        assert_eq!(texts("INSERT INTO t (a='\";'); SHOW TABLES"), ["INSERT INTO t (a='\";')", "SHOW TABLES"]);
+        assert!(texts(" ;\n;; ").is_empty());
+        assert!(texts("").is_empty());
+    }
+
+    #[test]
+    fn comments_are_dropped_unless_quoted() {
+        let script = "-- schema; not a statement\nCREATE TABLE t (a); -- trailing; comment\nINSERT INTO t (a='--; kept')\n-- the end";
+        assert_eq!(texts(script), ["CREATE TABLE t (a)", "INSERT INTO t (a='--; kept')"]);
+        assert_eq!(texts("SELECT * FROM t -- no semicolon"), ["SELECT * FROM t"]);
+        // A quote in a comment opens nothing.
+        assert_eq!(texts("-- it's\nSHOW TABLES;"), ["SHOW TABLES"]);
+        assert_eq!(texts("SELECT * FROM t WHERE a=1-2;"), ["SELECT * FROM t WHERE a=1-2"]);
+    }
+
+    #[test]
+    fn statements_know_the_line_they_start_on() {
+        let script = "-- header\n\nCREATE TABLE t (a);\nINSERT INTO t\n  (a='two\nlines');\n\n  -- note\n  SHOW TABLES;";
+        let lines: Vec<usize> = split_statements(script).iter().map(|statement| statement.line).collect();
+        assert_eq!(lines, [3, 4, 9]);
+        assert_eq!(split_statements(script)[1].text, "INSERT INTO t\n  (a='two\nlines')");
+    }
+
+    #[test]
+    fn entries_are_complete_after_a_semicolon_outside_quotes_and_comments() {
+        assert!(is_complete(""));
+        assert!(is_complete("SHOW TABLES;"));
+        assert!(is_complete("SHOW TABLES;  \n"));
+        assert!(is_complete("SHOW TABLES; -- done"));
+        assert!(!is_complete("SHOW TABLES"));
+        assert!(!is_complete("INSERT INTO t (a='x;"));
+        assert!(!is_complete("INSERT INTO t (a='x;');  SELECT"));
+        assert!(!is_complete("SHOW TABLES -- ;"));
+        assert!(is_complete("INSERT INTO t (a='It''s');"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::database::{lock_storage, Grants, Privilege, SharedStorage};
+use crate::database::grants::permission_denied;
+use crate::database::users::{verify_password, Users};
+
+/// Failed logins a connection may make before it is closed.
+pub const MAX_LOGIN_ATTEMPTS: usize = 3;
+
+/// Failed logins allowed for one user name, or from one client address,
+/// before further logins for it are refused.
+pub const MAX_FAILED_LOGINS: usize = 10;
+
+/// How long logins stay refused after the last failure that counted.
+pub const LOGIN_LOCKOUT: Duration = Duration::from_secs(300);
+
+/// Failed logins by user name and by client address, shared by every
+/// connection to both servers so that reconnecting does not start the
+/// count over. Clones share one record.
+#[derive(Clone, Default)]
+pub struct LoginThrottle {
+    failures: Arc<Mutex<HashMap<String, (usize, Instant)>>>,
+}
+
+impl LoginThrottle {
+    pub fn new() -> Self {
+        LoginThrottle::default()
+    }
+
+    /// The error for a login as `user` from `peer` while either is locked out.
+    pub fn check(&self, user: &str, peer: &str, now: Instant) -> Result<(), String> {
+        let failures = self.lock();
+        for key in keys(user, peer) {
+            if let Some((count, last)) = failures.get(&key)
+                && *count >= MAX_FAILED_LOGINS
+                && now.duration_since(*last) < LOGIN_LOCKOUT {
+                return Err("Too many failed logins; try again later".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a failed login against both the user name and the address.
+    /// Failures older than `LOGIN_LOCKOUT` are forgotten.
+    pub fn record_failure(&self, user: &str, peer: &str, now: Instant) {
+        let mut failures = self.lock();
+        failures.retain(|_, (_, last)| now.duration_since(*last) < LOGIN_LOCKOUT);
+        for key in keys(user, peer) {
+            let (count, last) = failures.entry(key).or_insert((0, now));
+            *count += 1;
+            *last = now;
+        }
+    }
+
+    /// Clears the user's failures. The address keeps its own, so that an
+    /// account of one's own cannot be used to go on guessing others.
+    pub fn record_success(&self, user: &str) {
+        self.lock().remove(&user_key(user));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (usize, Instant)>> {
+        self.failures.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn user_key(user: &str) -> String {
+    format!("user {}", user)
+}
+
+/// Counts by IP address, since each connection comes from a new port.
+fn keys(user: &str, peer: &str) -> [String; 2] {
+    let address = peer.parse::<SocketAddr>().map(|address| address.ip().to_string()).unwrap_or_else(|_| peer.to_string());
+    [user_key(user), format!("address {}", address)]
+}
+
+/// Checks a login from `peer`, refusing it without looking at the password
+/// while the user or the address is locked out. The hash is looked up under
+/// the storage lock but verified after releasing it, so a slow hash does not
+/// stall other clients.
+pub fn authenticate(storage: &SharedStorage, logins: &LoginThrottle, peer: &str, user: &str, password: &str) -> Result<bool, String> {
+    logins.check(user, peer, Instant::now())?;
+    let hash = {
+        let mut storage_guard = lock_storage(storage);
+        Users::new(storage_guard.as_mut()).password_hash(user)?
+    };
+    let valid = match hash {
+        Some(hash) => verify_password(&hash, password)?,
+        None => false,
+    };
+    if valid {
+        logins.record_success(user);
+    } else {
+        logins.record_failure(user, peer, Instant::now());
+    }
+    Ok(valid)
+}
+
+/// Whether a session may run commands; errors reading the accounts deny access.
+pub fn is_authorized(storage: &SharedStorage, user: Option<&str>) -> bool {
+    let mut storage_guard = lock_storage(storage);
+    Users::new(storage_guard.as_mut()).allows(user).unwrap_or(false)
+}
+
+/// Checks a privilege for commands that do not go through the query engine.
+/// Sessions without a user run while no accounts exist and are not limited.
+pub fn check_privilege(storage: &SharedStorage, user: Option<&str>, privilege: Privilege, table: Option<&str>) -> Result<(), String> {
+    let Some(user) = user else {
+        return Ok(());
+    };
+    let mut storage_guard = lock_storage(storage);
+    if Grants::new(storage_guard.as_mut()).allows(user, privilege, table)? {
+        return Ok(());
+    }
+    Err(permission_denied(user, privilege, table))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::database::{Storage, StorageEngine};
+    use super::*;
+
+    fn storage_with(user: &str, password: &str) -> SharedStorage {
+        let mut storage: Box<dyn Storage> = Box::new(StorageEngine::new());
+        Users::new(storage.as_mut()).create(user, password).unwrap();
+        Arc::new(Mutex::new(storage))
+    }
+
+    #[test]
+    fn logins_are_checked_against_the_stored_hash() {
+        let storage = storage_with("alice", "s3cret");
+        let logins = LoginThrottle::new();
+        assert_eq!(authenticate(&storage, &logins, "127.0.0.1:4000", "alice", "s3cret"), Ok(true));
+        assert_eq!(authenticate(&storage, &logins, "127.0.0.1:4000", "alice", "S3cret"), Ok(false));
+        assert_eq!(authenticate(&storage, &logins, "127.0.0.1:4000", "bob", "s3cret"), Ok(false));
+        assert!(is_authorized(&storage, Some("alice")));
+        assert!(!is_authorized(&storage, Some("bob")));
+        assert!(!is_authorized(&storage, None));
+    }
+
+    #[test]
+    fn failures_count_per_user_across_addresses() {
+        let logins = LoginThrottle::new();
+        let now = Instant::now();
+        for n in 0..MAX_FAILED_LOGINS {
+            assert!(logins.check("alice", &format!("10.0.0.{}:5000", n), now).is_ok());
+            logins.record_failure("alice", &format!("10.0.0.{}:5000", n), now);
+        }
+        assert!(logins.check("alice", "10.0.0.99:5000", now).is_err());
+        assert!(logins.check("bob", "10.0.0.99:5000", now).is_ok());
+        assert!(logins.check("alice", "10.0.0.99:5000", now + LOGIN_LOCKOUT).is_ok());
+    }
+
+    #[test]
+    fn failures_count_per_address_across_users_and_ports() {
+        let logins = LoginThrottle::new();
+        let now = Instant::now();
+        for n in 0..MAX_FAILED_LOGINS {
+            logins.record_failure(&format!("user{}", n), &format!("10.0.0.1:{}", 5000 + n), now);
+        }
+        assert!(logins.check("alice", "10.0.0.1:6000", now).is_err());
+        assert!(logins.check("alice", "10.0.0.2:6000", now).is_ok());
+
+        // Logging in clears the user's count but not the address's.
+        logins.record_success("user0");
+        assert!(logins.check("user0", "10.0.0.2:6000", now).is_ok());
+        assert!(logins.check("user0", "10.0.0.1:6000", now).is_err());
+    }
+
+    #[test]
+    fn locked_out_logins_are_refused_even_with_the_right_password() {
+        let storage = storage_with("alice", "s3cret");
+        let logins = LoginThrottle::new();
+        for _ in 0..MAX_FAILED_LOGINS {
+            assert_eq!(authenticate(&storage, &logins, "10.0.0.1:5000", "nobody", "guess"), Ok(false));
+        }
+        assert!(authenticate(&storage, &logins, "10.0.0.1:5001", "alice", "s3cret").is_err());
+        assert_eq!(authenticate(&storage, &logins, "10.0.0.2:5000", "alice", "s3cret"), Ok(true));
+    }
+}
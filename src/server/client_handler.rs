@@ -1,86 +1,180 @@
-use std::io::{Write, BufRead, BufReader};
-use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
-use crate::database::{StorageEngine };
-use crate::query::{ QueryEngine };
-use crate::utils::demo_data::autosave_database;
-
-pub fn handle_tcp_client(mut stream: TcpStream, storage: Arc<Mutex<StorageEngine>>) {
-    let peer = stream.peer_addr().unwrap();
-    println!("TCP Client connected: {}", peer);
-
-    let _ = writeln!(stream, "Welcome to atomDB TCP Server!");
-    let _ = writeln!(stream, "Type SQL commands or 'help' for assistance. 'quit' to disconnect.");
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use crate::config::Config;
+use crate::database::{lock_storage, Privilege, SharedStorage};
+use crate::database::grants::is_permission_denied;
+use crate::query::{Cursor, Cursors, PreparedStatements, QueryEngine, QueryResult};
+use crate::utils::persistence::{autosave_database, load_database};
+use super::auth::{authenticate, check_privilege, is_authorized, LoginThrottle, MAX_LOGIN_ATTEMPTS};
+use super::listener::{run_blocking, send, with_idle_timeout};
+use super::shutdown::Shutdown;
+use super::tls::ClientStream;
 
-    let reader = BufReader::new(stream.try_clone().unwrap());
-    
-    for line in reader.lines() {
-        match line {
-            Ok(query) => {
-                let query = query.trim();
-                
-                if query.is_empty() {
-                    continue;
-                }
-                
-                if query.eq_ignore_ascii_case("quit") {
-                    let _ = writeln!(stream, "Goodbye!");
-                    break;
-                }
-                
-                if query.eq_ignore_ascii_case("help") {
-                    let help_text = r#"
+const HELP_TEXT: &str = r#"
 Available Commands:
-  CREATE TABLE <name> (col1, col2, ...)
+  AUTH <user> <password>
+  CREATE TABLE <name> (col1 [type], ...)
   INSERT INTO <table> (col1=val1, ...)
   SELECT * FROM <table>
   SELECT * FROM <table> WHERE col=val
+  DECLARE <cursor> CURSOR FOR SELECT ...
+  FETCH [NEXT | ALL | <count>] FROM <cursor>
+  CLOSE <cursor> | CLOSE ALL
+  PREPARE <name> AS SELECT ... WHERE col = $1
+  EXECUTE <name>(value, ...)
+  DEALLOCATE <name> | DEALLOCATE ALL
   DESCRIBE <table>
   SHOW TABLES
   DROP TABLE <table>
+  COPY <table> FROM|TO '<file.csv>' [WITH HEADER] [DELIMITER ','] [QUOTE '"']
+  COPY <table> FROM|TO '<file>' JSON|NDJSON
+  BACKUP TO '<file>'
   LOAD
+  CREATE USER <name> PASSWORD '<password>'
+  ALTER USER <name> PASSWORD '<password>'
+  DROP USER <name>
+  CREATE ROLE <name> / DROP ROLE <name>
+  GRANT SELECT, INSERT ON <table> TO <user or role>
+  GRANT ADMIN|<role> TO <user or role>
+  REVOKE ... FROM <user or role>
   quit
 
-Note: Database autosaves after every write operation
-"#;
-                    let _ = write!(stream, "{}", help_text);
-                    continue;
-                }
+Note: Database autosaves after every write operation"#;
+
+/// Rows read, rendered and sent at a time, both for a streamed SELECT and
+/// for the pieces of a FETCH result.
+const ROWS_PER_CHUNK: usize = 1024;
 
-                if query.eq_ignore_ascii_case("load") {
-                    let result = StorageEngine::load_from_binary_file("database.bin");
-                    let response = match result {
-                        Ok(loaded_storage) => {
-                            *storage.lock().unwrap() = loaded_storage;
-                            "Database loaded successfully".to_string()
-                        },
-                        Err(e) => format!("Error loading: {}", e),
-                    };
-                    let _ = writeln!(stream, "{}", response);
+/// Rendered pieces that may wait for a slow client before rendering pauses.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// What to send back for one line from the client.
+enum Reply {
+    Text(String),
+    Rows(QueryResult),
+    /// A SELECT, streamed a batch at a time.
+    Stream(Cursor),
+    /// Sent before disconnecting.
+    Close(String),
+}
+
+/// The state of one connection. Lines are handled on the blocking pool,
+/// since nearly all of them take the storage lock.
+struct Session {
+    peer: String,
+    storage: SharedStorage,
+    config: Arc<Config>,
+    user: Option<String>,
+    failed_logins: usize,
+    logins: LoginThrottle,
+    cursors: Cursors,
+    prepared: PreparedStatements,
+}
+
+pub async fn handle_tcp_client(stream: ClientStream, peer: String, storage: SharedStorage, config: Arc<Config>, shutdown: Shutdown, logins: LoginThrottle) {
+    println!("TCP Client connected: {}", peer);
+    let mut session = Session {
+        peer: peer.clone(),
+        storage,
+        config: Arc::clone(&config),
+        user: None,
+        failed_logins: 0,
+        logins,
+        cursors: Cursors::default(),
+        prepared: PreparedStatements::default(),
+    };
+    let mut reader = BufReader::new(stream);
+
+    let greeting = {
+        let storage = Arc::clone(&session.storage);
+        run_blocking(move || welcome_text(&storage)).await
+    };
+    let mut connected = match greeting {
+        Ok(greeting) => send(reader.get_mut(), &config, greeting.as_bytes()).await.is_ok(),
+        Err(e) => {
+            eprintln!("TCP Client {}: {}", peer, e);
+            false
+        }
+    };
+
+    // Room for the longest statement allowed and its line ending.
+    let max_line = config.limits.max_query_length.saturating_add(2);
+    let mut line = Vec::new();
+    while connected {
+        line.clear();
+        let mut bounded = (&mut reader).take(max_line as u64);
+        // A statement already received is answered before shutting down.
+        let read = tokio::select! {
+            biased;
+            _ = shutdown.wait() => None,
+            read = with_idle_timeout(&config, bounded.read_until(b'\n', &mut line)) => Some(read),
+        };
+        let Some(read) = read else {
+            let _ = send(reader.get_mut(), &config, b"Server shutting down; disconnecting\n").await;
+            break;
+        };
+        match read {
+            Ok(0) => break,
+            // The rest of the line is never read, so the connection cannot go on.
+            Ok(read) if read == max_line && !line.ends_with(b"\n") => {
+                let message = format!("Error: Query exceeds the maximum length of {} bytes; disconnecting\n", config.limits.max_query_length);
+                let _ = send(reader.get_mut(), &config, message.as_bytes()).await;
+                println!("TCP Client {} sent a statement over the maximum length", peer);
+                break;
+            },
+            // The connection closed partway through a line, which may have
+            // been cut short; running what arrived could do something else.
+            Ok(_) if !line.ends_with(b"\n") => {
+                println!("TCP Client {} closed the connection mid-statement", peer);
+                break;
+            },
+            Ok(_) => {
+                let query = match std::str::from_utf8(&line) {
+                    Ok(text) => text.trim().to_string(),
+                    Err(e) => {
+                        eprintln!("Error reading from TCP client {}: {}", peer, e);
+                        break;
+                    }
+                };
+                if query.is_empty() {
                     continue;
                 }
-
-                let is_write_operation = query.starts_with("create") || 
-                                       query.starts_with("insert") || 
-                                       query.starts_with("drop");
-
-                let response = {
-                    let mut storage_guard = storage.lock().unwrap();
-                    let mut query_engine = QueryEngine::new(&mut storage_guard);
-                    let result = match query_engine.execute(query) {
-                        Ok(result) => result,
-                        Err(err) => format!("Error: {}", err),
-                    };
-                    
-                    if is_write_operation {
-                        autosave_database(&storage_guard);
-                        println!("Database autosaved after write operation");
+                let handled = run_blocking(move || {
+                    let reply = session.handle(&query);
+                    (session, reply)
+                }).await;
+                let reply = match handled {
+                    Ok((handled_session, reply)) => {
+                        session = handled_session;
+                        reply
+                    },
+                    Err(e) => {
+                        eprintln!("TCP Client {}: {}", peer, e);
+                        let _ = send(reader.get_mut(), &config, b"Error: Internal error; disconnecting\n").await;
+                        break;
                     }
-                    
-                    result
                 };
-
-                let _ = writeln!(stream, "{}", response);
+                let stream = reader.get_mut();
+                connected = match reply {
+                    Reply::Text(text) => send(stream, &config, format!("{}\n", text).as_bytes()).await.is_ok(),
+                    Reply::Rows(result) => send_rows(stream, &config, result).await.is_ok(),
+                    Reply::Stream(cursor) => stream_rows(stream, &session, cursor).await.is_ok(),
+                    Reply::Close(text) => {
+                        let _ = send(stream, &config, format!("{}\n", text).as_bytes()).await;
+                        false
+                    },
+                };
+            },
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                let _ = send(reader.get_mut(), &config, b"Idle timeout; disconnecting\n").await;
+                println!("TCP Client {} timed out", peer);
+                break;
+            },
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                println!("TCP Client {} closed the connection without a TLS close_notify", peer);
+                break;
             },
             Err(e) => {
                 eprintln!("Error reading from TCP client {}: {}", peer, e);
@@ -89,5 +183,186 @@ Note: Database autosaves after every write operation
         }
     }
 
+    let _ = reader.get_mut().shutdown().await;
     println!("TCP Client {} disconnected", peer);
 }
+
+fn welcome_text(storage: &SharedStorage) -> String {
+    let mut text = "Welcome to atomDB TCP Server!\n\
+        Type SQL commands or 'help' for assistance. 'quit' to disconnect.\n".to_string();
+    if !is_authorized(storage, None) {
+        text.push_str("Authentication required: send AUTH <user> <password>\n");
+    }
+    text
+}
+
+/// Sends a result while it is rendered on the blocking pool. Rendering
+/// waits whenever `CHUNKS_IN_FLIGHT` pieces are queued for a slow client,
+/// so a large result never sits in memory as text all at once.
+async fn send_rows(stream: &mut ClientStream, config: &Config, result: QueryResult) -> std::io::Result<()> {
+    let (sender, mut receiver) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    tokio::task::spawn_blocking(move || {
+        for chunk in result.text_chunks(ROWS_PER_CHUNK) {
+            // The client went away; stop rendering.
+            if sender.blocking_send(chunk).is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(chunk) = receiver.recv().await {
+        send(stream, config, chunk.as_bytes()).await?;
+    }
+    send(stream, config, b"\n").await
+}
+
+/// Sends a SELECT one batch at a time. The database is locked only while
+/// a batch is read, so other clients carry on while a large result goes
+/// out, and each batch is sent before the next one is read. All the
+/// batches share one deadline, as the statement they make up would.
+async fn stream_rows(stream: &mut ClientStream, session: &Session, mut cursor: Cursor) -> std::io::Result<()> {
+    let deadline = session.config.query_deadline();
+    let mut first = true;
+    loop {
+        let storage = Arc::clone(&session.storage);
+        let config = Arc::clone(&session.config);
+        let user = session.user.clone();
+        let fetched = run_blocking(move || -> Result<_, String> {
+            let mut storage_guard = lock_storage(&storage);
+            let result = QueryEngine::with_file_options(storage_guard.as_mut(), config.file_options())
+                .as_user(user)
+                .with_deadline(deadline)
+                .fetch(&mut cursor, ROWS_PER_CHUNK)?;
+            drop(storage_guard);
+            let done = !matches!(&result, QueryResult::Rows { rows, .. } if rows.len() == ROWS_PER_CHUNK);
+            // The header goes out with the first batch only.
+            let text: String = result.text_chunks(usize::MAX).skip(if first { 0 } else { 1 }).collect();
+            Ok((cursor, text, done))
+        }).await;
+        let (next, text, done) = match fetched {
+            Ok(Ok(fetched)) => fetched,
+            Ok(Err(e)) => return send(stream, &session.config, format!("Error: {}\n", e).as_bytes()).await,
+            Err(e) => {
+                eprintln!("TCP Client {}: {}", session.peer, e);
+                return send(stream, &session.config, b"Error: Internal error\n").await;
+            }
+        };
+        cursor = next;
+        first = false;
+        send(stream, &session.config, text.as_bytes()).await?;
+        if done {
+            return send(stream, &session.config, b"\n").await;
+        }
+    }
+}
+
+impl Session {
+    fn handle(&mut self, query: &str) -> Reply {
+        if query.len() > self.config.limits.max_query_length {
+            return Reply::Text(format!("Error: Query exceeds the maximum length of {} bytes", self.config.limits.max_query_length));
+        }
+
+        if query.eq_ignore_ascii_case("quit") {
+            return Reply::Close("Goodbye!".to_string());
+        }
+
+        if query.eq_ignore_ascii_case("help") {
+            return Reply::Text(HELP_TEXT.to_string());
+        }
+
+        if let Some(credentials) = auth_arguments(query) {
+            return self.login(credentials);
+        }
+
+        if !is_authorized(&self.storage, self.user.as_deref()) {
+            return Reply::Text("Error: Authentication required: send AUTH <user> <password>".to_string());
+        }
+
+        if query.eq_ignore_ascii_case("load") {
+            return Reply::Text(self.load());
+        }
+
+        self.run_query(query)
+    }
+
+    fn login(&mut self, credentials: &str) -> Reply {
+        let mut words = credentials.splitn(2, char::is_whitespace);
+        let (name, password) = match (words.next(), words.next()) {
+            (Some(name), Some(password)) => (name.to_lowercase(), password.trim()),
+            _ => return Reply::Text("Error: Invalid AUTH syntax: expected AUTH <user> <password>".to_string()),
+        };
+        match authenticate(&self.storage, &self.logins, &self.peer, &name, password) {
+            Ok(true) => {
+                println!("TCP Client {} authenticated as {}", self.peer, name);
+                let reply = Reply::Text(format!("Authenticated as {}", name));
+                self.user = Some(name);
+                self.failed_logins = 0;
+                reply
+            },
+            Ok(false) => {
+                self.failed_logins += 1;
+                if self.failed_logins >= MAX_LOGIN_ATTEMPTS {
+                    return Reply::Close("Error: Invalid user name or password\nToo many failed logins; disconnecting".to_string());
+                }
+                Reply::Text("Error: Invalid user name or password".to_string())
+            },
+            Err(e) => Reply::Text(format!("Error: {}", e)),
+        }
+    }
+
+    fn load(&self) -> String {
+        if let Err(e) = check_privilege(&self.storage, self.user.as_deref(), Privilege::Admin, None) {
+            return format!("Error: {}", e);
+        }
+        match load_database(&self.config) {
+            Ok(loaded_storage) => {
+                *lock_storage(&self.storage) = loaded_storage;
+                "Database loaded successfully".to_string()
+            },
+            Err(e) => format!("Error loading: {}", e),
+        }
+    }
+
+    fn run_query(&mut self, query: &str) -> Reply {
+        let is_write_operation = QueryEngine::is_write_query(query) || self.prepared.is_write_query(query);
+        if is_write_operation && self.config.storage.read_only {
+            return Reply::Text("Error: Database is open in read-only mode".to_string());
+        }
+
+        let mut storage_guard = lock_storage(&self.storage);
+        let mut query_engine = QueryEngine::with_file_options(storage_guard.as_mut(), self.config.file_options())
+            .as_user(self.user.clone())
+            .with_deadline(self.config.query_deadline())
+            .with_cursors(&mut self.cursors)
+            .with_prepared(&mut self.prepared);
+        let is_select = query.split_whitespace().next().is_some_and(|w| w.eq_ignore_ascii_case("select"));
+        let result = if is_select {
+            query_engine.open_cursor(query).map(Reply::Stream)
+        } else {
+            query_engine.execute_query(query).map(|result| match result {
+                QueryResult::Message(message) => Reply::Text(message),
+                result => Reply::Rows(result),
+            })
+        };
+        let reply = match result {
+            Ok(reply) => reply,
+            Err(err) => {
+                if is_permission_denied(&err) {
+                    println!("TCP Client {}: {}", self.peer, err);
+                }
+                Reply::Text(format!("Error: {}", err))
+            },
+        };
+
+        if is_write_operation && autosave_database(storage_guard.as_mut(), &self.config) {
+            println!("Database autosaved after write operation");
+        }
+
+        reply
+    }
+}
+
+/// The text after a leading `AUTH` keyword, if the line is a login.
+fn auth_arguments(query: &str) -> Option<&str> {
+    let (command, rest) = query.split_once(char::is_whitespace).unwrap_or((query, ""));
+    command.eq_ignore_ascii_case("auth").then(|| rest.trim())
+}
@@ -0,0 +1,123 @@
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::Semaphore;
+use crate::config::Config;
+use crate::database::SharedStorage;
+use super::shutdown::Shutdown;
+use super::tls::{load_tls_config, ClientStream};
+
+/// Threads for statements, logins and rendering results. Storage sits
+/// behind one lock, so more of them would mostly wait on it.
+const BLOCKING_THREADS: usize = 64;
+
+/// The runtime both servers run on: a few threads wait on every socket,
+/// and storage work is handed to a separate pool with `run_blocking`.
+pub fn runtime() -> Result<Runtime, String> {
+    Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(BLOCKING_THREADS)
+        .thread_name("atom-server")
+        .build()
+        .map_err(|e| format!("Cannot start the server runtime: {}", e))
+}
+
+/// Accepts connections on `address` and serves each one with `handler` as
+/// its own task. Clients beyond `server.max_connections` get `rejection`
+/// (unless they expect TLS) and are closed; silent clients are dropped
+/// after `server.idle_timeout_secs`. Once `shutdown` is triggered it stops
+/// accepting and waits up to `server.shutdown_timeout_secs` for the open
+/// connections to finish.
+pub async fn run_listener<H, F>(name: &'static str, address: &str, storage: SharedStorage, config: Arc<Config>, shutdown: Shutdown, rejection: &'static [u8], handler: H) -> Result<(), String>
+where
+    H: Fn(ClientStream, String, SharedStorage, Arc<Config>, Shutdown) -> F + Clone + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let tls = load_tls_config(&config.server)?;
+    let listener = TcpListener::bind(address).await.map_err(|e| format!("Failed to bind {} server: {}", name, e))?;
+    let max_connections = config.server.max_connections.clamp(1, u32::MAX as usize) as u32;
+    let connections = Arc::new(Semaphore::new(max_connections as usize));
+    println!("{} server listening on {}{} (up to {} connections)",
+        name, address, if tls.is_some() { " with TLS" } else { "" }, max_connections);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.wait() => break,
+        };
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Failed to accept {} connection: {}", name, e);
+                // Usually out of file descriptors; give clients time to leave.
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let peer = peer.to_string();
+        let Ok(permit) = Arc::clone(&connections).try_acquire_owned() else {
+            eprintln!("Rejected {} client {}: {} connections open", name, peer, max_connections);
+            if tls.is_none() {
+                let _ = stream.try_write(rejection);
+            }
+            continue;
+        };
+
+        let storage = Arc::clone(&storage);
+        let config = Arc::clone(&config);
+        let shutdown = shutdown.clone();
+        let tls = tls.clone();
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            match with_idle_timeout(&config, ClientStream::accept(stream, tls.as_ref())).await {
+                Ok(stream) => handler(stream, peer, storage, config, shutdown).await,
+                Err(e) => eprintln!("TLS handshake with {} client {} failed: {}", name, peer, e),
+            }
+        });
+    }
+
+    drop(listener);
+    let open = max_connections as usize - connections.available_permits();
+    println!("{} server stopped accepting connections; waiting for {} to close", name, open);
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    // Every permit is back once the last connection has closed.
+    if tokio::time::timeout(timeout, connections.acquire_many(max_connections)).await.is_err() {
+        eprintln!("{} server: {} connection(s) still open after {} s; closing them",
+            name, max_connections as usize - connections.available_permits(), timeout.as_secs());
+    }
+    Ok(())
+}
+
+/// Runs storage work, or anything else that may block, off the threads
+/// that serve sockets.
+pub async fn run_blocking<T, F>(work: F) -> Result<T, String>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work).await.map_err(|e| format!("Worker failed: {}", e))
+}
+
+/// Waits on a read or write for at most `server.idle_timeout_secs`, failing
+/// with `ErrorKind::TimedOut` after that.
+pub async fn with_idle_timeout<T>(config: &Config, io: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    match config.idle_timeout() {
+        Some(timeout) => tokio::time::timeout(timeout, io).await
+            .unwrap_or_else(|_| Err(io::Error::from(ErrorKind::TimedOut))),
+        None => io.await,
+    }
+}
+
+/// Writes `data` in full. A client that stops reading holds up only its own
+/// task, until the idle timeout closes it.
+pub async fn send(stream: &mut ClientStream, config: &Config, data: &[u8]) -> io::Result<()> {
+    with_idle_timeout(config, async {
+        stream.write_all(data).await?;
+        stream.flush().await
+    }).await
+}
@@ -1,5 +1,15 @@
 pub mod tcp_server;
 pub mod client_handler;
+pub mod resp;
+pub mod resp_server;
+pub mod resp_handler;
+pub mod auth;
+pub mod tls;
+pub mod listener;
+pub mod shutdown;
 
-pub use tcp_server::start_tcp_server;
+pub use auth::LoginThrottle;
+pub use tcp_server::{serve_tcp, start_tcp_server};
 pub use client_handler::handle_tcp_client;
+pub use resp_server::{serve_resp, start_resp_server};
+pub use resp_handler::handle_resp_client;
@@ -0,0 +1,153 @@
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    /// Binary-safe: values are bytes, not necessarily UTF-8.
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<RespValue>),
+}
+
+impl RespValue {
+    pub fn ok() -> Self {
+        RespValue::Simple("OK".to_string())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            RespValue::Simple(s) => out.extend_from_slice(format!("+{}\r\n", one_line(s)).as_bytes()),
+            RespValue::Error(e) => out.extend_from_slice(format!("-{}\r\n", one_line(e)).as_bytes()),
+            RespValue::Integer(i) => out.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+            RespValue::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            RespValue::Bulk(Some(s)) => {
+                out.extend_from_slice(format!("${}\r\n", s.len()).as_bytes());
+                out.extend_from_slice(s);
+                out.extend_from_slice(b"\r\n");
+            },
+            RespValue::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode_into(out);
+                }
+            }
+        }
+    }
+}
+
+/// Reads one command, either as a RESP array of bulk strings or as an inline
+/// (space separated) command. Returns `Ok(None)` on EOF. A command longer
+/// than `max_length` bytes, counting its arguments, is a protocol error, and
+/// nothing beyond that is buffered. Arguments are kept as the bytes sent.
+pub async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R, max_length: usize) -> io::Result<Option<Vec<Vec<u8>>>> {
+    loop {
+        let line = match read_line(reader, max_length).await? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        if let Some(count) = line.strip_prefix('*') {
+            let count: i64 = parse_number(count)?;
+            // Every argument takes at least one byte.
+            if count > max_length as i64 {
+                return Err(protocol_error(&format!("Too many arguments (at most {})", max_length)));
+            }
+            let mut args = Vec::new();
+            let mut remaining = max_length;
+            for _ in 0..count.max(0) {
+                let arg = read_bulk(reader, remaining).await?;
+                remaining -= arg.len().min(remaining);
+                args.push(arg);
+            }
+            return Ok(Some(args));
+        }
+
+        let args: Vec<Vec<u8>> = line.split_whitespace().map(|s| s.as_bytes().to_vec()).collect();
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+/// Reads one bulk string of at most `max_length` bytes.
+async fn read_bulk<R: AsyncBufRead + Unpin>(reader: &mut R, max_length: usize) -> io::Result<Vec<u8>> {
+    let header = read_line(reader, max_length).await?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed mid-command"))?;
+    let len = header.strip_prefix('$')
+        .ok_or_else(|| protocol_error(&format!("Expected '$', got '{}'", header)))?;
+    let len: usize = parse_number(len)?;
+    if len > max_length {
+        return Err(protocol_error("Command exceeds the maximum length"));
+    }
+
+    let mut data = vec![0; len + 2];
+    reader.read_exact(&mut data).await?;
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Reads a line of at most `max_length` bytes before its line ending.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, max_length: usize) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let limit = max_length.saturating_add(2) as u64;
+    if reader.take(limit).read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        // A line cut short by the connection closing is never run.
+        if (line.len() as u64) < limit {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed mid-command"));
+        }
+        return Err(protocol_error("Command exceeds the maximum length"));
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> io::Result<T> {
+    s.trim().parse().map_err(|_| protocol_error(&format!("Invalid length '{}'", s)))
+}
+
+/// Status and error lines end at the first line break, so any inside the
+/// text, which may come from the client, are replaced.
+fn one_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Protocol error: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bulk_strings_keep_their_bytes() {
+        let mut input: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\n\xff\x00\r\n\r\n";
+        let args = read_command(&mut input, 100).await.unwrap().unwrap();
+        assert_eq!(args, [b"SET".to_vec(), b"k".to_vec(), b"\xff\x00\r\n".to_vec()]);
+        assert_eq!(RespValue::Bulk(Some(args[2].clone())).encode(), b"$4\r\n\xff\x00\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn oversized_commands_are_rejected() {
+        let mut input: &[u8] = b"*1\r\n$101\r\n";
+        assert!(read_command(&mut input, 100).await.is_err());
+        let mut input: &[u8] = b"*101\r\n";
+        assert!(read_command(&mut input, 100).await.is_err());
+    }
+
+    #[test]
+    fn errors_stay_on_one_line() {
+        let reply = RespValue::Error("ERR unknown command 'X\r\n+OK'".to_string()).encode();
+        assert_eq!(reply, b"-ERR unknown command 'X  +OK'\r\n");
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::ErrorKind;
+use std::sync::Arc;
+use tokio::io::{AsyncWriteExt, BufReader};
+use crate::config::Config;
+use crate::database::{lock_storage, Deadline, Grants, KvStore, Privilege, SharedStorage};
+use crate::database::grants::permission_denied;
+use crate::database::kv::KV_TABLE;
+use crate::utils::persistence::autosave_database;
+use super::auth::{authenticate, is_authorized, LoginThrottle, MAX_LOGIN_ATTEMPTS};
+use super::listener::{run_blocking, send, with_idle_timeout};
+use super::shutdown::Shutdown;
+use super::resp::{read_command, RespValue};
+use super::tls::ClientStream;
+
+/// The state of one connection. Commands run on the blocking pool, since
+/// they take the storage lock.
+struct Session {
+    peer: String,
+    storage: SharedStorage,
+    config: Arc<Config>,
+    user: Option<String>,
+    failed_logins: usize,
+    logins: LoginThrottle,
+}
+
+pub async fn handle_resp_client(stream: ClientStream, peer: String, storage: SharedStorage, config: Arc<Config>, shutdown: Shutdown, logins: LoginThrottle) {
+    println!("RESP client connected: {}", peer);
+    let mut reader = BufReader::new(stream);
+    let mut session = Session { peer: peer.clone(), storage, config: Arc::clone(&config), user: None, failed_logins: 0, logins };
+
+    loop {
+        // RESP has no way to tell a client why it is closed; a command
+        // already received is answered first.
+        let read = tokio::select! {
+            biased;
+            _ = shutdown.wait() => break,
+            read = with_idle_timeout(&config, read_command(&mut reader, config.limits.max_query_length)) => read,
+        };
+        let args = match read {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                println!("RESP client {} timed out", peer);
+                break;
+            },
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                println!("RESP client {} closed the connection mid-command", peer);
+                break;
+            },
+            Err(e) => {
+                let _ = send(reader.get_mut(), &config, &RespValue::Error(format!("ERR {}", e)).encode()).await;
+                eprintln!("Error reading from RESP client {}: {}", peer, e);
+                break;
+            }
+        };
+
+        let handled = run_blocking(move || {
+            let (reply, close) = session.handle(&args);
+            (session, reply.encode(), close)
+        }).await;
+        let (reply, close) = match handled {
+            Ok((handled_session, reply, close)) => {
+                session = handled_session;
+                (reply, close)
+            },
+            Err(e) => {
+                eprintln!("RESP client {}: {}", peer, e);
+                let _ = send(reader.get_mut(), &config, &RespValue::Error("ERR internal error".to_string()).encode()).await;
+                break;
+            }
+        };
+        if send(reader.get_mut(), &config, &reply).await.is_err() || close {
+            break;
+        }
+    }
+
+    let _ = reader.get_mut().shutdown().await;
+    println!("RESP client {} disconnected", peer);
+}
+
+impl Session {
+    /// The reply to one command, and whether to disconnect after sending it.
+    fn handle(&mut self, args: &[Vec<u8>]) -> (RespValue, bool) {
+        let Some(command) = args.first().map(|command| String::from_utf8_lossy(command).to_uppercase()) else {
+            return (RespValue::Error("ERR empty command".to_string()), false);
+        };
+        if command == "QUIT" {
+            return (RespValue::ok(), true);
+        }
+
+        if command == "AUTH" {
+            let credentials = match args {
+                [_, name, password] => text(name).and_then(|name| Ok((name, text(password)?))).map(Some),
+                _ => Ok(None),
+            };
+            let reply = match credentials {
+                Ok(Some((name, password))) => match authenticate(&self.storage, &self.logins, &self.peer, &name.to_lowercase(), password) {
+                    Ok(true) => {
+                        self.user = Some(name.to_lowercase());
+                        self.failed_logins = 0;
+                        RespValue::ok()
+                    },
+                    Ok(false) => {
+                        self.failed_logins += 1;
+                        RespValue::Error("WRONGPASS invalid username-password pair".to_string())
+                    },
+                    Err(e) => RespValue::Error(format!("ERR {}", e)),
+                },
+                Ok(None) => RespValue::Error("ERR AUTH needs a user name and a password".to_string()),
+                Err(e) => RespValue::Error(format!("ERR {}", e)),
+            };
+            return (reply, self.failed_logins >= MAX_LOGIN_ATTEMPTS);
+        }
+
+        if !is_authorized(&self.storage, self.user.as_deref()) {
+            return (RespValue::Error("NOAUTH Authentication required.".to_string()), false);
+        }
+
+        (execute_command(&self.storage, &self.config, self.user.as_deref(), &command, &args[1..]), false)
+    }
+}
+
+fn execute_command(storage: &SharedStorage, config: &Config, user: Option<&str>, command: &str, args: &[Vec<u8>]) -> RespValue {
+    let is_write_operation = matches!(command, "SET" | "DEL" | "HSET" | "EXPIRE");
+    if is_write_operation && config.storage.read_only {
+        return RespValue::Error("READONLY You can't write against a read only database".to_string());
+    }
+
+    let mut storage_guard = lock_storage(storage);
+    // Keys live in the kv table: reading them needs SELECT on it and changing them INSERT.
+    if let Some(user) = user.filter(|_| !matches!(command, "PING" | "ECHO" | "COMMAND")) {
+        let privilege = if is_write_operation { Privilege::Insert } else { Privilege::Select };
+        match Grants::new(storage_guard.as_mut()).allows(user, privilege, Some(KV_TABLE)) {
+            Ok(true) => {},
+            Ok(false) => return RespValue::Error(format!("NOPERM {}", permission_denied(user, privilege, Some(KV_TABLE)))),
+            Err(e) => return RespValue::Error(format!("ERR {}", e)),
+        }
+    }
+    storage_guard.set_deadline(config.query_deadline());
+    let mut kv = KvStore::new(storage_guard.as_mut());
+    let result = run_command(&mut kv, command, args);
+    storage_guard.set_deadline(Deadline::default());
+
+    if is_write_operation && result.is_ok() {
+        autosave_database(storage_guard.as_mut(), config);
+    }
+
+    result.unwrap_or_else(|err| {
+        if err.starts_with("WRONGTYPE") {
+            RespValue::Error(err)
+        } else {
+            RespValue::Error(format!("ERR {}", err))
+        }
+    })
+}
+
+/// Values are passed through as bytes; keys, hash fields and the other
+/// arguments must be text.
+fn run_command(kv: &mut KvStore, command: &str, args: &[Vec<u8>]) -> Result<RespValue, String> {
+    match command {
+        "PING" => Ok(match args.first() {
+            Some(message) => RespValue::Bulk(Some(message.clone())),
+            None => RespValue::Simple("PONG".to_string()),
+        }),
+        "ECHO" => {
+            check_arity(command, args, 1, 1)?;
+            Ok(RespValue::Bulk(Some(args[0].clone())))
+        },
+        "COMMAND" => Ok(RespValue::Array(Vec::new())),
+        "GET" => {
+            check_arity(command, args, 1, 1)?;
+            Ok(RespValue::Bulk(kv.get(text(&args[0])?)?))
+        },
+        "SET" => {
+            check_arity(command, args, 2, 4)?;
+            let options = args[2..].iter().map(|option| text(option)).collect::<Result<Vec<_>, _>>()?;
+            let ttl_ms = parse_set_options(&options)?;
+            kv.set(text(&args[0])?, &args[1], ttl_ms)?;
+            Ok(RespValue::ok())
+        },
+        "DEL" => {
+            check_arity(command, args, 1, usize::MAX)?;
+            let mut removed = 0;
+            for key in args {
+                if kv.del(text(key)?)? {
+                    removed += 1;
+                }
+            }
+            Ok(RespValue::Integer(removed))
+        },
+        "EXISTS" => {
+            check_arity(command, args, 1, usize::MAX)?;
+            let mut found = 0;
+            for key in args {
+                if kv.exists(text(key)?)? {
+                    found += 1;
+                }
+            }
+            Ok(RespValue::Integer(found))
+        },
+        "KEYS" => {
+            check_arity(command, args, 1, 1)?;
+            let keys = kv.keys(text(&args[0])?)?;
+            Ok(RespValue::Array(keys.into_iter().map(|k| RespValue::Bulk(Some(k.into_bytes()))).collect()))
+        },
+        "HSET" => {
+            if args.len() < 3 || args.len().is_multiple_of(2) {
+                return Err(wrong_arity(command));
+            }
+            let key = text(&args[0])?;
+            let mut added = 0;
+            for pair in args[1..].chunks(2) {
+                if kv.hset(key, text(&pair[0])?, &pair[1])? {
+                    added += 1;
+                }
+            }
+            Ok(RespValue::Integer(added))
+        },
+        "HGETALL" => {
+            check_arity(command, args, 1, 1)?;
+            let fields = kv.hgetall(text(&args[0])?)?;
+            Ok(RespValue::Array(fields.into_iter()
+                .flat_map(|(field, value)| [RespValue::Bulk(Some(field.into_bytes())), RespValue::Bulk(Some(value))])
+                .collect()))
+        },
+        "EXPIRE" => {
+            check_arity(command, args, 2, 2)?;
+            let seconds: i64 = text(&args[1])?.parse()
+                .map_err(|_| "value is not an integer or out of range".to_string())?;
+            Ok(RespValue::Integer(kv.expire(text(&args[0])?, seconds)? as i64))
+        },
+        _ => Err(format!("unknown command '{}'", command)),
+    }
+}
+
+fn parse_set_options(options: &[&str]) -> Result<Option<u64>, String> {
+    match options {
+        [] => Ok(None),
+        [unit, amount] => {
+            let amount: u64 = amount.parse()
+                .map_err(|_| "value is not an integer or out of range".to_string())?;
+            match unit.to_uppercase().as_str() {
+                "EX" => amount.checked_mul(1000).map(Some)
+                    .ok_or_else(|| "invalid expire time in 'set' command".to_string()),
+                "PX" => Ok(Some(amount)),
+                _ => Err("syntax error".to_string()),
+            }
+        },
+        _ => Err("syntax error".to_string()),
+    }
+}
+
+fn check_arity(command: &str, args: &[Vec<u8>], min: usize, max: usize) -> Result<(), String> {
+    if args.len() < min || args.len() > max {
+        return Err(wrong_arity(command));
+    }
+    Ok(())
+}
+
+fn wrong_arity(command: &str) -> String {
+    format!("wrong number of arguments for '{}' command", command.to_lowercase())
+}
+
+fn text(arg: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(arg).map_err(|_| "keys, fields and options must be valid UTF-8".to_string())
+}
@@ -0,0 +1,21 @@
+use std::sync::Arc;
+use crate::config::Config;
+use crate::database::SharedStorage;
+use super::auth::LoginThrottle;
+use super::listener::{run_listener, runtime};
+use super::resp_handler::handle_resp_client;
+use super::shutdown::Shutdown;
+
+pub fn start_resp_server(storage: SharedStorage, config: Arc<Config>) {
+    let result = runtime().and_then(|runtime| runtime.block_on(serve_resp(storage, config, Shutdown::new(), LoginThrottle::new())));
+    if let Err(e) = result {
+        eprintln!("Cannot start RESP server: {}", e);
+    }
+}
+
+pub async fn serve_resp(storage: SharedStorage, config: Arc<Config>, shutdown: Shutdown, logins: LoginThrottle) -> Result<(), String> {
+    let address = config.resp_listen_address();
+    let rejection = b"-ERR max number of clients reached\r\n";
+    let handler = move |stream, peer, storage, config, shutdown| handle_resp_client(stream, peer, storage, config, shutdown, logins.clone());
+    run_listener("RESP", &address, storage, config, shutdown, rejection, handler).await
+}
@@ -0,0 +1,51 @@
+use std::io;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Tells the servers to stop accepting clients and close the ones they
+/// have once their current statement is answered. Clones share one signal.
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Shutdown { sender: Arc::new(sender), receiver }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Resolves once `trigger` has been called.
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        let _ = receiver.wait_for(|stopping| *stopping).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+/// Waits for SIGINT (Ctrl-C) or SIGTERM and returns its name.
+pub async fn wait_for_signal() -> io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|_| "Ctrl-C")
+    }
+}
@@ -1,24 +1,21 @@
-use std::net::TcpListener;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use crate::database::StorageEngine;
+use std::sync::Arc;
+use crate::config::Config;
+use crate::database::SharedStorage;
+use super::auth::LoginThrottle;
 use super::client_handler::handle_tcp_client;
+use super::listener::{run_listener, runtime};
+use super::shutdown::Shutdown;
 
-pub fn start_tcp_server(storage: Arc<Mutex<StorageEngine>>) {
-    let listener = TcpListener::bind("0.0.0.0:6969").expect("Failed to bind TCP server");
-    println!("TCP Server listening on 0.0.0.0:6969");
-    
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let storage_clone = Arc::clone(&storage);
-                thread::spawn(move || {
-                    handle_tcp_client(stream, storage_clone);
-                });
-            },
-            Err(e) => {
-                eprintln!("Failed to accept TCP connection: {}", e);
-            }
-        }
+pub fn start_tcp_server(storage: SharedStorage, config: Arc<Config>) {
+    let result = runtime().and_then(|runtime| runtime.block_on(serve_tcp(storage, config, Shutdown::new(), LoginThrottle::new())));
+    if let Err(e) = result {
+        eprintln!("Cannot start TCP server: {}", e);
     }
 }
+
+pub async fn serve_tcp(storage: SharedStorage, config: Arc<Config>, shutdown: Shutdown, logins: LoginThrottle) -> Result<(), String> {
+    let address = config.listen_address();
+    let rejection = b"Error: Too many connections; try again later\n";
+    let handler = move |stream, peer, storage, config, shutdown| handle_tcp_client(stream, peer, storage, config, shutdown, logins.clone());
+    run_listener("TCP", &address, storage, config, shutdown, rejection, handler).await
+}
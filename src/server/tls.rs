@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use crate::config::settings::ServerConfig as ServerSettings;
+
+/// A client connection, in the clear or over TLS.
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl ClientStream {
+    /// Wraps an accepted socket, completing the TLS handshake first when
+    /// `tls` is set so that a bad handshake is reported before any I/O.
+    pub async fn accept(stream: TcpStream, tls: Option<&TlsAcceptor>) -> io::Result<Self> {
+        match tls {
+            Some(tls) => Ok(ClientStream::Tls(Box::new(tls.accept(stream).await?))),
+            None => Ok(ClientStream::Plain(stream)),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            // A close without a TLS close_notify fails with UnexpectedEof,
+            // since the data before it may have been cut short by an attacker.
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    /// Sends the TLS close_notify before closing the socket.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Builds the TLS settings shared by both servers, or `None` when no
+/// certificate is configured.
+pub fn load_tls_config(settings: &ServerSettings) -> Result<Option<TlsAcceptor>, String> {
+    let (cert_file, key_file) = match (&settings.tls_cert_file, &settings.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        (None, None) if settings.tls_client_ca_file.is_some() => {
+            return Err("server.tls_client_ca_file needs tls_cert_file and tls_key_file".to_string());
+        },
+        (None, None) => return Ok(None),
+        _ => return Err("TLS needs both server.tls_cert_file and server.tls_key_file".to_string()),
+    };
+
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = match &settings.tls_client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_file)? {
+                roots.add(cert).map_err(|e| format!("Invalid CA certificate in '{}': {}", ca_file.display(), e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| e.to_string())?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder.with_single_cert(read_certs(cert_file)?, read_key(key_file)?)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot read '{}': {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate file '{}': {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in '{}'", path.display()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot read '{}': {}", path.display(), e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("Invalid key file '{}': {}", path.display(), e))?
+        .ok_or_else(|| format!("No private key found in '{}'", path.display()))
+}
@@ -0,0 +1,157 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub quote: char,
+    pub header: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            quote: '"',
+            header: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvRecord {
+    /// 1-based line on which the record starts.
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// Parses CSV text into records. Quoted fields may contain delimiters,
+/// doubled quotes and line breaks; blank lines are skipped.
+pub fn parse(input: &str, options: &CsvOptions) -> Result<Vec<CsvRecord>, String> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut was_quoted = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == options.quote {
+                if chars.peek() == Some(&options.quote) {
+                    chars.next();
+                    field.push(c);
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+            continue;
+        }
+
+        match c {
+            c if c == options.quote && field.is_empty() && !was_quoted => {
+                in_quotes = true;
+                was_quoted = true;
+            },
+            c if c == options.delimiter => {
+                fields.push(std::mem::take(&mut field));
+                was_quoted = false;
+            },
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if !(fields.len() == 1 && fields[0].is_empty() && !was_quoted) {
+                    records.push(CsvRecord { line: record_line, fields: std::mem::take(&mut fields) });
+                }
+                fields.clear();
+                was_quoted = false;
+                line += 1;
+                record_line = line;
+            },
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Unterminated quoted field starting on line {}", record_line));
+    }
+    if !field.is_empty() || !fields.is_empty() || was_quoted {
+        fields.push(field);
+        records.push(CsvRecord { line: record_line, fields });
+    }
+    Ok(records)
+}
+
+/// Formats one record, quoting fields that contain the delimiter, the quote
+/// character or a line break.
+pub fn format_record(fields: &[String], options: &CsvOptions) -> String {
+    let quote = options.quote.to_string();
+    let formatted: Vec<String> = fields.iter()
+        .map(|field| {
+            if field.contains([options.delimiter, options.quote, '\n', '\r']) {
+                format!("{}{}{}", quote, field.replace(&quote, &quote.repeat(2)), quote)
+            } else {
+                field.clone()
+            }
+        })
+        .collect();
+    let mut record = formatted.join(&options.delimiter.to_string());
+    record.push('\n');
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(input: &str, options: &CsvOptions) -> Vec<Vec<String>> {
+        parse(input, options).unwrap().into_iter().map(|record| record.fields).collect()
+    }
+
+    #[test]
+    fn quoted_fields_hold_delimiters_quotes_and_line_breaks() {
+        let input = "1,\"Smith, Jo\",\"say \"\"hi\"\"\"\n2,\"two\nlines\",x\r\n3,,\"\"\n";
+        assert_eq!(fields(input, &CsvOptions::default()), [
+            vec!["1", "Smith, Jo", "say \"hi\""],
+            vec!["2", "two\nlines", "x"],
+            vec!["3", "", ""],
+        ]);
+        let lines: Vec<usize> = parse(input, &CsvOptions::default()).unwrap().iter().map(|record| record.line).collect();
+        assert_eq!(lines, [1, 2, 4]);
+    }
+
+    #[test]
+    fn blank_lines_are_skipped_but_a_quoted_empty_field_is_a_record() {
+        let records = parse("a\n\n\"\"\n\nb", &CsvOptions::default()).unwrap();
+        let found: Vec<(usize, Vec<String>)> = records.into_iter().map(|record| (record.line, record.fields)).collect();
+        assert_eq!(found, [(1, vec!["a".to_string()]), (3, vec![String::new()]), (5, vec!["b".to_string()])]);
+        assert!(parse("", &CsvOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn delimiter_and_quote_are_configurable() {
+        let options = CsvOptions { delimiter: ';', quote: '\'', header: false };
+        assert_eq!(fields("a;'b;c';'it''s'\n", &options), [vec!["a", "b;c", "it's"]]);
+        // A quote that does not start the field is kept as it is.
+        assert_eq!(fields("5\"4,x\n", &CsvOptions::default()), [vec!["5\"4", "x"]]);
+    }
+
+    #[test]
+    fn unterminated_quotes_are_an_error() {
+        let error = parse("1,ok\n2,\"open\n3,x\n", &CsvOptions::default()).unwrap_err();
+        assert_eq!(error, "Unterminated quoted field starting on line 2");
+    }
+
+    #[test]
+    fn formatted_records_parse_back_unchanged() {
+        let options = CsvOptions::default();
+        let record: Vec<String> = ["plain", "a,b", "say \"hi\"", "two\nlines", ""].map(String::from).to_vec();
+        let text = format_record(&record, &options);
+        assert_eq!(text, "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\n");
+        assert_eq!(fields(&text, &options), [record]);
+    }
+}
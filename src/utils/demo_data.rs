@@ -1,6 +1,6 @@
-use crate::database::{StorageEngine, Row};
+use crate::database::{Row, Storage};
 
-pub fn setup_demo_data(storage: &mut StorageEngine) -> Result<(), String> {
+pub fn setup_demo_data(storage: &mut dyn Storage) -> Result<(), String> {
     storage.create_table("users", vec![
         "id".to_string(),
         "name".to_string(),
@@ -25,9 +25,3 @@ pub fn setup_demo_data(storage: &mut StorageEngine) -> Result<(), String> {
 
     Ok(())
 }
-
-pub fn autosave_database(storage: &StorageEngine) {
-    if let Err(e) = storage.save_to_binary_file("database.bin") {
-        eprintln!("Autosave failed: {}", e);
-    }
-}
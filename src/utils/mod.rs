@@ -1,4 +1,6 @@
 
 pub mod error;
 pub mod demo_data;
+pub mod persistence;
+pub mod csv;
 pub use error::DatabaseError;
\ No newline at end of file
@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crate::config::{Config, EngineKind, PersistenceMode};
+use crate::database::{FileOptions, KeyRing, Storage, StorageEngine};
+use crate::database::paged::PagedEngine;
+use crate::database::{encryption, wal};
+use super::demo_data::setup_demo_data;
+
+pub fn load_database(config: &Config) -> Result<Box<dyn Storage>, Box<dyn std::error::Error>> {
+    if config.storage.engine == EngineKind::Paged {
+        return Ok(open_paged(config)?.0);
+    }
+    let path = config.data_path();
+    let mut storage = read_snapshot(&path, config.key_ring())?;
+    if config.storage.migrate_unencrypted && config.key_ring().is_enabled() && !config.storage.read_only && !is_sealed(&path) {
+        write_snapshot(&storage, &path, &config.file_options())?;
+        eprintln!("Encrypted {}; storage.migrate_unencrypted can be turned off again", path.display());
+    }
+    storage.set_limits(config.storage_limits());
+    attach_write_log(&mut storage, config)?;
+    Ok(Box::new(storage))
+}
+
+/// Loads the configured data file. A missing file yields an empty database,
+/// seeded with demo data when `seed_demo` is set; a file that exists but
+/// cannot be read is an error rather than being silently replaced.
+pub fn open_database(config: &Config, seed_demo: bool) -> Result<Box<dyn Storage>, String> {
+    if config.storage.engine == EngineKind::Paged {
+        let (mut storage, created) = open_paged(config)?;
+        if seed_demo && created {
+            setup_demo_data(storage.as_mut())?;
+        }
+        return Ok(storage);
+    }
+
+    let path = config.data_path();
+    if path.exists() {
+        return load_database(config)
+            .map_err(|e| format!("Cannot load '{}': {}", path.display(), e));
+    }
+
+    let mut storage = StorageEngine::new();
+    storage.set_limits(config.storage_limits());
+    let replayed = attach_write_log(&mut storage, config)?;
+    if seed_demo && replayed == 0 {
+        setup_demo_data(&mut storage)?;
+    }
+    Ok(Box::new(storage))
+}
+
+/// Opens the page directory, creating it if needed. Also returns whether the
+/// database is new.
+fn open_paged(config: &Config) -> Result<(Box<dyn Storage>, bool), String> {
+    if config.storage.write_log {
+        return Err("The write log needs engine = \"memory\"; the paged engine writes changes to its page files".to_string());
+    }
+    if config.storage.persistence == PersistenceMode::Memory {
+        return Err("The paged engine keeps its data on disk and cannot run with persistence = \"memory\"".to_string());
+    }
+    if config.key_ring().is_enabled() {
+        return Err("Encryption needs engine = \"memory\"; the paged engine does not encrypt its page files".to_string());
+    }
+    let (mut storage, created) = PagedEngine::open(&config.data_path(), config.storage.buffer_pool_pages)?;
+    storage.set_limits(config.storage_limits());
+    Ok((Box::new(storage), created))
+}
+
+/// When the write log is enabled, replays the changes it holds beyond the
+/// data file's LSN (those made since the last save) and starts journaling.
+/// Unless read-only, a log written in the clear or under a previous key is
+/// first sealed with the current key. Returns the number of changes replayed.
+fn attach_write_log(storage: &mut StorageEngine, config: &Config) -> Result<usize, String> {
+    if !config.storage.write_log {
+        return Ok(0);
+    }
+    let path = config.write_log_path();
+    let options = config.file_options();
+    let records = if config.storage.read_only {
+        wal::read_log(&path, &options.keys)?
+    } else {
+        wal::read_and_reseal(&path, &options)?
+    };
+
+    let start = storage.lsn();
+    if let Some(first) = records.iter().find(|record| record.lsn > start).filter(|record| record.lsn != start + 1) {
+        return Err(format!("The write log '{}' starts at LSN {} but the data file is at LSN {}; \
+            restore a backup with atom recover", path.display(), first.lsn, start));
+    }
+    let mut replayed = 0;
+    for record in records.iter().filter(|record| record.lsn > start) {
+        storage.apply(record)?;
+        replayed += 1;
+    }
+    if replayed > 0 {
+        eprintln!("Replayed {} change(s) from {}", replayed, path.display());
+    }
+    storage.enable_journal();
+    Ok(replayed)
+}
+
+/// Appends the changes made since the last flush to the write log.
+pub fn flush_write_log(storage: &mut dyn Storage, config: &Config) -> Result<(), String> {
+    let records = storage.take_journal();
+    if config.storage.read_only || !config.storage.write_log {
+        return Ok(());
+    }
+    wal::append(&config.write_log_path(), &records, &config.file_options())
+}
+
+/// Writes the data file regardless of the persistence mode, after flushing
+/// the write log. The file is written next to its final location and renamed
+/// into place, so readers never observe a half-written file, and a write log
+/// it now holds all of is archived once it reaches `wal::SEGMENT_SIZE`.
+/// Backends with files of their own are checkpointed instead.
+pub fn save_database(storage: &mut dyn Storage, config: &Config) -> Result<(), String> {
+    if config.storage.read_only {
+        return Err("Database is open in read-only mode".to_string());
+    }
+    flush_write_log(storage, config)?;
+    if storage.checkpoint()? {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&config.storage.data_dir)
+        .map_err(|e| format!("Cannot create '{}': {}", config.storage.data_dir.display(), e))?;
+
+    write_snapshot(storage, &config.data_path(), &config.file_options())?;
+    if config.storage.write_log {
+        wal::rotate(&config.write_log_path(), storage.lsn(), wal::SEGMENT_SIZE, config.key_ring())?;
+    }
+    Ok(())
+}
+
+/// Writes a consistent copy of the database to `path` atomically,
+/// compressed and encrypted as `options` ask.
+pub fn write_snapshot(storage: &dyn Storage, path: &Path, options: &FileOptions) -> Result<(), String> {
+    let temp_path = path.with_extension("tmp");
+    let data = options.keys.seal(storage.to_binary(options.compression)?)?;
+    std::fs::write(&temp_path, data).map_err(|e| format!("Cannot write '{}': {}", temp_path.display(), e))?;
+    std::fs::rename(&temp_path, path).map_err(|e| format!("Cannot write '{}': {}", path.display(), e))
+}
+
+/// Reads a data file or backup, decrypting it if it is encrypted.
+pub fn read_snapshot(path: &Path, keys: &KeyRing) -> Result<StorageEngine, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let data = keys.open(&data)?;
+    StorageEngine::deserialize_binary(&data).map_err(|e| e.to_string())
+}
+
+/// Whether the file starts with the header of encrypted data.
+fn is_sealed(path: &Path) -> bool {
+    let mut magic = [0u8; encryption::MAGIC.len()];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && KeyRing::is_encrypted(&magic)
+}
+
+/// Called after every write operation: flushes the write log and, in
+/// `autosave` mode, saves the data file. Returns whether a save happened.
+pub fn autosave_database(storage: &mut dyn Storage, config: &Config) -> bool {
+    if let Err(e) = flush_write_log(storage, config) {
+        eprintln!("Write log failed: {}", e);
+    }
+    if config.storage.read_only || config.storage.persistence != PersistenceMode::Autosave {
+        return false;
+    }
+    report_save(save_database(storage, config))
+}
+
+/// Saves on shutdown unless the database is memory-only. Returns whether a save happened.
+pub fn final_save_database(storage: &mut dyn Storage, config: &Config) -> bool {
+    if config.storage.read_only || config.storage.persistence == PersistenceMode::Memory {
+        return false;
+    }
+    report_save(save_database(storage, config))
+}
+
+/// Size of a data file, or the total size of the files in a page directory.
+pub fn file_size(path: &Path) -> u64 {
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries.flatten().map(|entry| file_size(&entry.path())).sum(),
+        Err(_) => std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+    }
+}
+
+fn report_save(result: Result<(), String>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Autosave failed: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use atom::config::{Config, PersistenceMode};
+use atom::database::users::Users;
+use atom::database::SharedStorage;
+use atom::server::shutdown::Shutdown;
+use atom::server::{serve_resp, serve_tcp, LoginThrottle};
+use atom::{Storage, StorageEngine};
+use tokio::runtime::Runtime;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Both servers on free ports, sharing one login throttle, with one user.
+/// They stop when the runtime is dropped.
+struct Servers {
+    config: Arc<Config>,
+    _runtime: Runtime,
+}
+
+impl Servers {
+    fn start() -> Self {
+        let mut config = Config::default();
+        config.server.bind_address = "127.0.0.1".to_string();
+        config.server.port = free_port();
+        config.server.resp_port = free_port();
+        config.storage.persistence = PersistenceMode::Memory;
+        let config = Arc::new(config);
+
+        let mut storage: Box<dyn Storage> = Box::new(StorageEngine::new());
+        Users::new(storage.as_mut()).create("alice", "s3cret").unwrap();
+        let storage: SharedStorage = Arc::new(Mutex::new(storage));
+
+        let runtime = Runtime::new().unwrap();
+        let shutdown = Shutdown::new();
+        let logins = LoginThrottle::new();
+        runtime.spawn(serve_tcp(Arc::clone(&storage), Arc::clone(&config), shutdown.clone(), logins.clone()));
+        runtime.spawn(serve_resp(storage, Arc::clone(&config), shutdown.clone(), logins));
+        Servers { config, _runtime: runtime }
+    }
+
+    fn connect(&self, port: u16) -> BufReader<TcpStream> {
+        let started = Instant::now();
+        loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+                    return BufReader::new(stream);
+                },
+                Err(e) if started.elapsed() > Duration::from_secs(10) => panic!("cannot connect: {}", e),
+                Err(_) => std::thread::sleep(Duration::from_millis(20)),
+            }
+        }
+    }
+
+    /// A TCP connection, past the greeting.
+    fn tcp(&self) -> BufReader<TcpStream> {
+        let mut client = self.connect(self.config.server.port);
+        let mut greeting = String::new();
+        while !greeting.contains("Authentication required") {
+            assert_ne!(client.read_line(&mut greeting).unwrap(), 0, "{}", greeting);
+        }
+        client
+    }
+}
+
+fn ask(client: &mut BufReader<TcpStream>, line: &str) -> String {
+    client.get_mut().write_all(format!("{}\n", line).as_bytes()).unwrap();
+    let mut reply = String::new();
+    client.read_line(&mut reply).unwrap();
+    reply
+}
+
+fn resp_auth(client: &mut BufReader<TcpStream>, user: &str, password: &str) -> String {
+    let command = format!("*3\r\n$4\r\nAUTH\r\n${}\r\n{}\r\n${}\r\n{}\r\n", user.len(), user, password.len(), password);
+    client.get_mut().write_all(command.as_bytes()).unwrap();
+    let mut reply = String::new();
+    client.read_line(&mut reply).unwrap();
+    reply
+}
+
+#[test]
+fn tcp_sessions_log_in_before_running_statements() {
+    let servers = Servers::start();
+    let mut client = servers.tcp();
+    assert!(ask(&mut client, "SHOW TABLES").starts_with("Error: Authentication required"));
+    assert!(ask(&mut client, "AUTH alice wrong").starts_with("Error: Invalid user name or password"));
+    assert!(ask(&mut client, "AUTH alice s3cret").starts_with("Authenticated as alice"));
+    assert!(!ask(&mut client, "SHOW TABLES").starts_with("Error"));
+
+    let mut client = servers.tcp();
+    for _ in 0..2 {
+        assert!(ask(&mut client, "AUTH alice wrong").starts_with("Error: Invalid user name or password"));
+    }
+    assert!(ask(&mut client, "AUTH alice wrong").starts_with("Error: Invalid user name or password"));
+    let mut goodbye = String::new();
+    client.read_line(&mut goodbye).unwrap();
+    assert_eq!(goodbye, "Too many failed logins; disconnecting\n");
+    assert_eq!(client.read_line(&mut String::new()).unwrap(), 0);
+}
+
+#[test]
+fn failed_logins_count_across_connections_and_servers() {
+    let servers = Servers::start();
+    let mut resp = servers.connect(servers.config.server.resp_port);
+    assert_eq!(resp_auth(&mut resp, "alice", "s3cret"), "+OK\r\n");
+
+    // Reconnecting after each failure does not start the count over.
+    for n in 0..10 {
+        let mut client = servers.tcp();
+        assert!(ask(&mut client, &format!("AUTH guess{} wrong", n)).starts_with("Error: Invalid user name or password"));
+    }
+    let mut client = servers.tcp();
+    assert!(ask(&mut client, "AUTH alice s3cret").starts_with("Error: Too many failed logins; try again later"));
+    let mut resp = servers.connect(servers.config.server.resp_port);
+    assert_eq!(resp_auth(&mut resp, "alice", "s3cret"), "-ERR Too many failed logins; try again later\r\n");
+}
@@ -0,0 +1,39 @@
+use atom::database::paged::PagedEngine;
+use atom::{QueryEngine, Storage, StorageEngine};
+
+fn ids(storage: &dyn Storage) -> Vec<usize> {
+    storage.select_all("t").unwrap().into_iter().map(|(id, _)| id).collect()
+}
+
+/// Leaves rows 0, 3 and 4, compacts, and checks that they keep their IDs.
+fn compact_keeps_row_ids(storage: &mut dyn Storage) {
+    let mut engine = QueryEngine::new(storage);
+    engine.execute("CREATE TABLE t (k)").unwrap();
+    engine.execute("INSERT INTO t (k=a), (k=b), (k=b), (k=c), (k=d)").unwrap();
+    storage.delete_where("t", "k", "b").unwrap();
+    let before = storage.select_all("t").unwrap();
+    assert_eq!(ids(storage), [0, 3, 4]);
+
+    storage.compact().unwrap();
+    assert_eq!(storage.select_all("t").unwrap(), before);
+    let mut engine = QueryEngine::new(storage);
+    assert!(engine.execute("SELECT * FROM t WHERE rowid = 3").unwrap().contains("\"c\""));
+    engine.execute("INSERT INTO t (k=e)").unwrap();
+    let after = ids(storage);
+    assert_eq!(after.len(), 4);
+    assert!(after[3] > 4);
+}
+
+#[test]
+fn memory_compact_keeps_row_ids() {
+    compact_keeps_row_ids(&mut StorageEngine::new());
+}
+
+#[test]
+fn paged_compact_keeps_row_ids() {
+    let dir = std::env::temp_dir().join(format!("atom-compact-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (mut engine, _) = PagedEngine::open(&dir, 8).unwrap();
+    compact_keeps_row_ids(&mut engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,43 @@
+use atom::database::dump::dump_sql;
+use atom::query::split_statements;
+use atom::{QueryEngine, Storage, StorageEngine};
+
+fn restore(script: &str) -> StorageEngine {
+    let mut restored = StorageEngine::new();
+    for statement in split_statements(script) {
+        QueryEngine::new(&mut restored).execute_query(&statement.text).unwrap();
+    }
+    restored
+}
+
+#[test]
+fn dump_and_restore_keep_row_ids() {
+    let mut storage = StorageEngine::new();
+    let mut engine = QueryEngine::new(&mut storage);
+    engine.execute("CREATE TABLE people (name, age INTEGER)").unwrap();
+    engine.execute("INSERT INTO people (name='Alice', age=30), (name=bob, age=25), (name='O''Brien', age=41), (name=carol, age=22)").unwrap();
+    storage.delete_where("people", "name", "bob").unwrap();
+    storage.delete_where("people", "name", "Alice").unwrap();
+
+    let script = dump_sql(&storage, &[], 2).unwrap();
+    let mut restored = restore(&script);
+    assert_eq!(restored.select_all("people").unwrap(), storage.select_all("people").unwrap());
+    assert_eq!(dump_sql(&restored, &[], 2).unwrap(), script);
+
+    // A new row gets an ID after every restored one.
+    let highest = storage.select_all("people").unwrap().iter().map(|(id, _)| *id).max().unwrap();
+    QueryEngine::new(&mut restored).execute("INSERT INTO people (name=dave, age=50)").unwrap();
+    let ids: Vec<usize> = restored.select_all("people").unwrap().iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids.len(), 3);
+    assert!(ids.iter().filter(|id| **id > highest).count() == 1);
+}
+
+#[test]
+fn insert_with_a_taken_row_id_fails() {
+    let mut storage = StorageEngine::new();
+    let mut engine = QueryEngine::new(&mut storage);
+    engine.execute("CREATE TABLE t (a)").unwrap();
+    engine.execute("INSERT INTO t (rowid=7, a=x)").unwrap();
+    assert!(engine.execute("INSERT INTO t (rowid=7, a=y)").is_err());
+    assert_eq!(storage.select_all("t").unwrap().len(), 1);
+}
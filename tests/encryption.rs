@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+use atom::database::wal::{self, Change, LogRecord};
+use atom::database::{FileOptions, KeyRing};
+use atom::utils::persistence::{open_database, write_snapshot};
+use atom::{Config, Storage, StorageEngine};
+
+const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("atom-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn encrypted_config(dir: &std::path::Path, migrate: bool) -> Config {
+    let mut config = Config::default();
+    config.storage.data_dir = dir.to_path_buf();
+    config.storage.write_log = true;
+    config.storage.encryption_key_file = Some(dir.join("key"));
+    config.storage.migrate_unencrypted = migrate;
+    config.reload_keys().unwrap();
+    config
+}
+
+#[test]
+fn unencrypted_files_are_refused_until_migrated() {
+    let dir = temp_dir("encryption-migration");
+    std::fs::write(dir.join("key"), KEY).unwrap();
+
+    // A data file and a write log from before encryption was enabled.
+    let config = encrypted_config(&dir, false);
+    let mut storage = StorageEngine::new();
+    storage.create_table("t", vec!["k".to_string()]).unwrap();
+    write_snapshot(&storage, &config.data_path(), &FileOptions::default()).unwrap();
+    let change = Change::CreateTable { table: "u".to_string(), columns: vec!["k".to_string()], types: vec![Default::default()] };
+    wal::append(&config.write_log_path(), &[LogRecord::new(2, change)], &FileOptions::default()).unwrap();
+
+    let error = open_database(&config, false).err().unwrap();
+    assert!(error.contains("migrate_unencrypted"), "{}", error);
+
+    let config = encrypted_config(&dir, true);
+    let storage = open_database(&config, false).unwrap();
+    assert!(storage.has_table("t") && storage.has_table("u"));
+    assert!(KeyRing::is_encrypted(&std::fs::read(config.data_path()).unwrap()));
+    assert!(std::fs::read(config.write_log_path()).unwrap().windows(12).all(|window| window != b"create_table"));
+
+    // Once migrated, the files open without the opt-in.
+    let storage = open_database(&encrypted_config(&dir, false), false).unwrap();
+    assert!(storage.has_table("t") && storage.has_table("u"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,41 @@
+use atom::database::grants::is_permission_denied;
+use atom::{QueryEngine, StorageEngine};
+
+/// Runs `sql` as `user`, or as the local shell when `None`.
+fn run(storage: &mut StorageEngine, user: Option<&str>, sql: &str) -> Result<String, String> {
+    QueryEngine::new(storage).as_user(user.map(str::to_string)).execute(sql)
+}
+
+fn denied(result: Result<String, String>) -> bool {
+    result.is_err_and(|e| is_permission_denied(&e))
+}
+
+#[test]
+fn statements_are_checked_against_grants() {
+    let mut storage = StorageEngine::new();
+    for sql in [
+        "CREATE TABLE users (name)",
+        "INSERT INTO users (name=ann)",
+        "CREATE USER alice PASSWORD 's3cret'",
+        "CREATE USER root PASSWORD 'r00t'",
+        "CREATE ROLE analyst",
+        "GRANT SELECT ON users TO analyst",
+        "GRANT analyst TO alice",
+        "GRANT ADMIN TO root",
+    ] {
+        run(&mut storage, None, sql).unwrap();
+    }
+    let alice = Some("alice");
+
+    assert!(run(&mut storage, alice, "SELECT * FROM users").unwrap().contains("ann"));
+    assert!(denied(run(&mut storage, alice, "INSERT INTO users (name=bob)")));
+    assert!(denied(run(&mut storage, alice, "DROP TABLE users")));
+    assert!(denied(run(&mut storage, alice, "SELECT * FROM atom_users")));
+    assert!(denied(run(&mut storage, alice, "GRANT ADMIN TO alice")));
+
+    run(&mut storage, Some("root"), "GRANT INSERT ON users TO analyst").unwrap();
+    run(&mut storage, alice, "INSERT INTO users (name=bob)").unwrap();
+    run(&mut storage, Some("root"), "REVOKE analyst FROM alice").unwrap();
+    assert!(denied(run(&mut storage, alice, "SELECT * FROM users")));
+    assert!(run(&mut storage, Some("root"), "SELECT * FROM atom_users").unwrap().contains("alice"));
+}
@@ -0,0 +1,158 @@
+use atom::database::compression::Compression;
+use atom::database::dump::dump_sql;
+use atom::database::paged::PagedEngine;
+use atom::database::wal::{Change, LogRecord};
+use atom::query::script::split_statements;
+use atom::{QueryEngine, Row, Storage, StorageEngine};
+
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("atom-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn row(k: &str, v: &str) -> Row {
+    let mut row = Row::new();
+    row.insert("k".to_string(), k.to_string());
+    row.insert("v".to_string(), v.to_string());
+    row
+}
+
+/// What `select_where` must return, found by scanning every row.
+fn scanned(storage: &dyn Storage, column: &str, value: &str) -> Vec<(usize, Row)> {
+    storage.select_all("t").unwrap().into_iter()
+        .filter(|(_, row)| row.get(column).is_some_and(|v| v == value))
+        .collect()
+}
+
+fn assert_index_matches(storage: &dyn Storage) {
+    for key in 0..7 {
+        let key = format!("k{}", key);
+        let ids: Vec<usize> = scanned(storage, "k", &key).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(storage.index_lookup("t", "k", &key).unwrap(), Some(ids));
+        assert_eq!(storage.select_where("t", "k", &key).unwrap(), scanned(storage, "k", &key));
+    }
+}
+
+/// Builds an index, changes the table under it, and checks lookups against scans.
+fn index_follows_writes(storage: &mut dyn Storage) {
+    storage.create_table("t", vec!["k".to_string(), "v".to_string()]).unwrap();
+    for n in 0..300 {
+        storage.insert_row("t", row(&format!("k{}", n % 7), &"x".repeat(n % 200))).unwrap();
+    }
+    assert_eq!(storage.index_lookup("t", "k", "k1").unwrap(), None);
+    storage.create_index("t", "k").unwrap();
+    assert!(storage.create_index("t", "k").is_err());
+    assert!(storage.create_index("t", "missing").is_err());
+    assert_eq!(storage.list_indexes("t").unwrap(), ["k"]);
+    assert_index_matches(storage);
+
+    let matching = scanned(storage, "k", "k3").len();
+    assert_eq!(storage.delete_where("t", "k", "k3").unwrap(), matching);
+    storage.update_where("t", "k", "k5", "k", "k6").unwrap();
+    storage.update_where("t", "k", "k2", "v", &"y".repeat(900)).unwrap();
+    storage.insert_row_at("t", 5000, row("k2", "z")).unwrap();
+    assert!(storage.select_where("t", "k", "k3").unwrap().is_empty());
+    assert!(storage.select_where("t", "k", "k5").unwrap().is_empty());
+    assert_index_matches(storage);
+
+    storage.compact().unwrap();
+    assert_index_matches(storage);
+
+    storage.drop_index("t", "k").unwrap();
+    assert!(storage.drop_index("t", "k").is_err());
+    assert_eq!(storage.index_lookup("t", "k", "k1").unwrap(), None);
+    assert!(storage.list_indexes("t").unwrap().is_empty());
+}
+
+#[test]
+fn memory_index_follows_writes() {
+    index_follows_writes(&mut StorageEngine::new());
+}
+
+#[test]
+fn paged_index_follows_writes() {
+    let dir = temp_dir("indexes");
+    let (mut engine, _) = PagedEngine::open(&dir, 4).unwrap();
+    index_follows_writes(&mut engine);
+
+    // The catalog keeps the index list; its contents are read again on first use.
+    engine.create_index("t", "k").unwrap();
+    engine.checkpoint().unwrap();
+    drop(engine);
+    let (engine, _) = PagedEngine::open(&dir, 4).unwrap();
+    assert_eq!(engine.list_indexes("t").unwrap(), ["k"]);
+    assert_index_matches(&engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn indexes_survive_the_data_file_and_the_write_log() {
+    let mut storage = StorageEngine::new();
+    storage.enable_journal();
+    storage.create_table("t", vec!["k".to_string(), "v".to_string()]).unwrap();
+    storage.insert_row("t", row("k1", "a")).unwrap();
+    storage.create_index("t", "k").unwrap();
+    storage.insert_row("t", row("k1", "b")).unwrap();
+
+    let loaded = StorageEngine::deserialize_binary(&storage.serialize_binary(Compression::None).unwrap()).unwrap();
+    assert_eq!(loaded.list_indexes("t").unwrap(), ["k"]);
+    assert_index_matches(&loaded);
+    let loaded = StorageEngine::deserialize(&storage.serialize().unwrap()).unwrap();
+    assert_index_matches(&loaded);
+
+    let journal = storage.take_journal();
+    assert!(journal.iter().any(|record| record.change == Change::CreateIndex { table: "t".to_string(), column: "k".to_string() }));
+    let mut replayed = StorageEngine::new();
+    for record in &journal {
+        replayed.apply(record).unwrap();
+    }
+    assert_index_matches(&replayed);
+    replayed.apply(&LogRecord::new(replayed.lsn() + 1, Change::DropIndex { table: "t".to_string(), column: "k".to_string() })).unwrap();
+    assert!(replayed.list_indexes("t").unwrap().is_empty());
+}
+
+#[test]
+fn merge_copies_indexes_from_any_backend() {
+    let dir = temp_dir("indexes-merge");
+    let (mut paged, _) = PagedEngine::open(&dir, 4).unwrap();
+    paged.create_table("t", vec!["k".to_string(), "v".to_string()]).unwrap();
+    paged.insert_row_at("t", 7, row("k1", "a")).unwrap();
+    paged.create_index("t", "k").unwrap();
+
+    let mut memory = StorageEngine::new();
+    assert_eq!(memory.merge(&paged, false).unwrap(), ["t"]);
+    assert_eq!(memory.list_indexes("t").unwrap(), ["k"]);
+    assert_eq!(memory.index_lookup("t", "k", "k1").unwrap(), Some(vec![7]));
+    assert!(memory.merge(&paged, false).is_err());
+
+    memory.insert_row("t", row("k1", "b")).unwrap();
+    assert_eq!(paged.merge(&memory, true).unwrap(), ["t"]);
+    assert_eq!(paged.list_indexes("t").unwrap(), ["k"]);
+    assert_eq!(paged.select_where("t", "k", "k1").unwrap(), memory.select_where("t", "k", "k1").unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn indexes_are_created_in_sql_and_dumped() {
+    let mut storage = StorageEngine::new();
+    let mut engine = QueryEngine::new(&mut storage);
+    engine.execute("CREATE TABLE t (k, v)").unwrap();
+    engine.execute("INSERT INTO t (k=a, v=1)").unwrap();
+    assert!(engine.execute("CREATE INDEX t (k)").is_err());
+    engine.execute("CREATE INDEX ON t (k)").unwrap();
+    assert!(engine.execute("DESCRIBE t").unwrap().contains("indexes: [\"k\"]"));
+    assert!(engine.execute("SELECT * FROM t WHERE k=a").unwrap().contains("\"1\""));
+
+    let dump = dump_sql(&storage, &[], 100).unwrap();
+    assert!(dump.contains("CREATE INDEX ON t (k);"));
+    let mut restored = StorageEngine::new();
+    for statement in split_statements(&dump) {
+        QueryEngine::new(&mut restored).execute(&statement.text).unwrap();
+    }
+    assert_eq!(restored.list_indexes("t").unwrap(), ["k"]);
+
+    let mut engine = QueryEngine::new(&mut restored);
+    engine.execute("DROP INDEX ON t (k)").unwrap();
+    assert!(engine.execute("DROP INDEX ON t (k)").is_err());
+}
@@ -0,0 +1,90 @@
+use std::ops::Bound;
+use std::path::PathBuf;
+use atom::database::paged::PagedEngine;
+use atom::database::RowIdRange;
+use atom::{Row, Storage};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("atom-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn row(key: usize, length: usize) -> Row {
+    let mut row = Row::new();
+    row.insert("k".to_string(), format!("k{}", key % 7));
+    row.insert("v".to_string(), "x".repeat(length));
+    row
+}
+
+/// Reads `range` a batch at a time the way a cursor does.
+fn read_batches(engine: &PagedEngine, mut range: RowIdRange, filter: Option<(&str, &str)>, descending: bool, limit: usize) -> Vec<usize> {
+    let mut ids = Vec::new();
+    loop {
+        let rows = engine.select_batch("t", range, filter, descending, limit).unwrap();
+        assert!(rows.len() <= limit);
+        let Some((last, _)) = rows.last() else { return ids };
+        if descending {
+            range.1 = Bound::Excluded(*last);
+        } else {
+            range.0 = Bound::Excluded(*last);
+        }
+        ids.extend(rows.iter().map(|(id, _)| *id));
+    }
+}
+
+fn expected(engine: &PagedEngine, range: RowIdRange, filter: Option<(&str, &str)>, descending: bool) -> Vec<usize> {
+    let mut ids: Vec<usize> = engine.select_all("t").unwrap().into_iter()
+        .filter(|(id, row)| range_contains(range, *id) && filter.is_none_or(|(column, value)| row.get(column).is_some_and(|v| v == value)))
+        .map(|(id, _)| id)
+        .collect();
+    if descending {
+        ids.reverse();
+    }
+    ids
+}
+
+fn range_contains(range: RowIdRange, id: usize) -> bool {
+    std::ops::RangeBounds::contains(&range, &id)
+}
+
+fn assert_batches_match(engine: &PagedEngine) {
+    let ranges = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(100), Bound::Excluded(400)),
+        (Bound::Excluded(250), Bound::Included(251)),
+    ];
+    for range in ranges {
+        for filter in [None, Some(("k", "k5"))] {
+            for descending in [false, true] {
+                for limit in [1, 9, 37, 1000] {
+                    assert_eq!(read_batches(engine, range, filter, descending, limit), expected(engine, range, filter, descending));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn batches_match_a_full_scan() {
+    let dir = temp_dir("paged-batches");
+    let (mut engine, _) = PagedEngine::open(&dir, 4).unwrap();
+    engine.create_table("t", vec!["k".to_string(), "v".to_string()]).unwrap();
+    for key in 0..600 {
+        engine.insert_row("t", row(key, key * 37 % 300)).unwrap();
+    }
+    assert_batches_match(&engine);
+
+    // Deletes leave gaps, and rows that grow out of their page move.
+    engine.delete_where("t", "k", "k3").unwrap();
+    engine.update_where("t", "k", "k5", "v", &"y".repeat(900)).unwrap();
+    engine.insert_row_at("t", 2000, row(5, 10)).unwrap();
+    assert_batches_match(&engine);
+
+    // Reopened, the first batch learns each page's row IDs again.
+    engine.checkpoint().unwrap();
+    drop(engine);
+    let (engine, _) = PagedEngine::open(&dir, 4).unwrap();
+    assert_batches_match(&engine);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use atom::database::paged::PagedEngine;
+use atom::{Row, Storage};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("atom-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn row(key: usize) -> Row {
+    let mut row = Row::new();
+    row.insert("k".to_string(), format!("k{}", key % 7));
+    row.insert("v".to_string(), "x".repeat(300));
+    row
+}
+
+fn ids(engine: &PagedEngine, table: &str) -> Vec<usize> {
+    engine.select_all(table).unwrap().into_iter().map(|(id, _)| id).collect()
+}
+
+fn heap_files(dir: &Path) -> usize {
+    std::fs::read_dir(dir).unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "heap"))
+        .count()
+}
+
+/// Two tables with rows, checkpointed, in a pool small enough that writes
+/// evict dirty pages to the heap files.
+fn populated(dir: &Path) -> PagedEngine {
+    let (mut engine, _) = PagedEngine::open(dir, 2).unwrap();
+    for table in ["a", "b"] {
+        engine.create_table(table, vec!["k".to_string(), "v".to_string()]).unwrap();
+        for n in 0..40 {
+            engine.insert_row(table, row(n)).unwrap();
+        }
+    }
+    engine.checkpoint().unwrap();
+    engine
+}
+
+/// Changes both tables and the table list, evicting pages along the way.
+fn change_everything(engine: &mut PagedEngine) {
+    for n in 0..200 {
+        engine.insert_row("a", row(n)).unwrap();
+    }
+    engine.delete_where("a", "k", "k3").unwrap();
+    engine.update_where("a", "k", "k4", "v", &"y".repeat(900)).unwrap();
+    engine.drop_table("b").unwrap();
+    engine.create_table("c", vec!["k".to_string()]).unwrap();
+    engine.compact().unwrap();
+}
+
+#[test]
+fn crash_after_evictions_keeps_row_ids_unique() {
+    let dir = temp_dir("paged-crash");
+    let mut engine = populated(&dir);
+    for n in 0..200 {
+        engine.insert_row("a", row(n)).unwrap();
+    }
+    // Dropped without a checkpoint: only the evicted pages reached the heap.
+    drop(engine);
+
+    let (mut engine, _) = PagedEngine::open(&dir, 2).unwrap();
+    let before = ids(&engine, "a");
+    assert!(before.len() > 40, "no evicted rows were found");
+    assert!(engine.check().is_empty(), "{:?}", engine.check());
+    let id = engine.insert_row("a", row(0)).unwrap();
+    assert!(!before.contains(&id));
+    assert_eq!(ids(&engine, "a").into_iter().collect::<HashSet<_>>().len(), before.len() + 1);
+    assert!(engine.check().is_empty(), "{:?}", engine.check());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rollback_restores_only_the_pages_changed() {
+    let dir = temp_dir("paged-rollback");
+    let mut engine = populated(&dir);
+    let (a, b) = (engine.select_all("a").unwrap(), engine.select_all("b").unwrap());
+
+    engine.begin().unwrap();
+    assert_eq!(heap_files(&dir.join("rollback")), 0);
+    assert_eq!(std::fs::metadata(dir.join("rollback").join("undo")).unwrap().len(), 0);
+    change_everything(&mut engine);
+    engine.rollback().unwrap();
+
+    assert!(!dir.join("rollback").exists());
+    assert_eq!(engine.select_all("a").unwrap(), a);
+    assert_eq!(engine.select_all("b").unwrap(), b);
+    assert!(!engine.has_table("c"));
+    assert_eq!(heap_files(&dir), 2);
+    assert!(engine.check().is_empty(), "{:?}", engine.check());
+
+    engine.begin().unwrap();
+    change_everything(&mut engine);
+    engine.commit().unwrap();
+    assert!(!dir.join("rollback").exists());
+    assert!(!engine.has_table("b"));
+    assert_eq!(heap_files(&dir), 2);
+    let a = engine.select_all("a").unwrap();
+    drop(engine);
+    let (engine, _) = PagedEngine::open(&dir, 2).unwrap();
+    assert_eq!(engine.select_all("a").unwrap(), a);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn crash_inside_a_transaction_rolls_it_back_on_open() {
+    let dir = temp_dir("paged-crash-transaction");
+    let mut engine = populated(&dir);
+    let (a, b) = (engine.select_all("a").unwrap(), engine.select_all("b").unwrap());
+    engine.begin().unwrap();
+    change_everything(&mut engine);
+    engine.checkpoint().unwrap();
+    for n in 0..100 {
+        engine.insert_row("a", row(n)).unwrap();
+    }
+    drop(engine);
+
+    let (engine, _) = PagedEngine::open(&dir, 2).unwrap();
+    assert!(!dir.join("rollback").exists());
+    assert_eq!(engine.select_all("a").unwrap(), a);
+    assert_eq!(engine.select_all("b").unwrap(), b);
+    assert!(!engine.has_table("c"));
+    assert!(engine.check().is_empty(), "{:?}", engine.check());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
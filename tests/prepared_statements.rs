@@ -0,0 +1,31 @@
+use atom::{QueryEngine, Storage, StorageEngine};
+
+#[test]
+fn dollar_values_are_literals_outside_prepared_statements() {
+    let mut storage = StorageEngine::new();
+    let mut engine = QueryEngine::new(&mut storage);
+    engine.execute("CREATE TABLE prices (item, price)").unwrap();
+    engine.execute("INSERT INTO prices (item=tea, price=$1)").unwrap();
+    assert!(engine.execute("SELECT * FROM prices WHERE price = $1").unwrap().contains("tea"));
+
+    let mut cursors = Default::default();
+    let mut engine = QueryEngine::new(&mut storage).with_cursors(&mut cursors);
+    engine.execute("DECLARE c CURSOR FOR SELECT * FROM prices WHERE price = $1").unwrap();
+    assert!(engine.execute("FETCH ALL FROM c").unwrap().contains("tea"));
+    assert_eq!(storage.select_all("prices").unwrap()[0].1.get("price").unwrap(), "$1");
+}
+
+#[test]
+fn dollar_values_are_parameters_when_prepared() {
+    let mut storage = StorageEngine::new();
+    let mut prepared = Default::default();
+    let mut engine = QueryEngine::new(&mut storage).with_prepared(&mut prepared);
+    engine.execute("CREATE TABLE prices (item, price)").unwrap();
+    engine.execute("PREPARE add AS INSERT INTO prices (item=$1, price=$2)").unwrap();
+    engine.execute("EXECUTE add('Coffee', 3)").unwrap();
+    assert!(engine.execute("EXECUTE add(tea)").is_err());
+
+    let rows = engine.execute_with("SELECT * FROM prices WHERE item = $1", &["Coffee"]).unwrap();
+    assert!(rows.to_text().contains("Coffee"));
+    assert!(engine.execute_with("SELECT * FROM prices WHERE item = $1", &[]).is_err());
+}
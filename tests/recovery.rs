@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use atom::cli::args::RecoverArgs;
+use atom::cli::commands::recover;
+use atom::database::wal;
+use atom::utils::persistence::{flush_write_log, open_database, save_database, write_snapshot};
+use atom::{Config, Row, Storage};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("atom-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn logged_config(dir: &Path) -> Config {
+    let mut config = Config::default();
+    config.storage.data_dir = dir.to_path_buf();
+    config.storage.write_log = true;
+    config
+}
+
+/// Inserts rows `from..to` and flushes them to the write log.
+fn insert(storage: &mut dyn Storage, config: &Config, from: usize, to: usize) {
+    for n in from..to {
+        let mut row = Row::new();
+        row.insert("k".to_string(), n.to_string());
+        storage.insert_row("t", row).unwrap();
+    }
+    flush_write_log(storage, config).unwrap();
+}
+
+#[test]
+fn recovery_reads_segments_archived_since_the_backup() {
+    let dir = temp_dir("recovery");
+    let config = logged_config(&dir);
+    let backup = dir.join("backup.bin");
+
+    let mut storage = open_database(&config, false).unwrap();
+    storage.create_table("t", vec!["k".to_string()]).unwrap();
+    insert(storage.as_mut(), &config, 0, 2);
+    write_snapshot(storage.as_ref(), &backup, &config.file_options()).unwrap();
+
+    // A save below the segment size leaves the log alone; once the log is
+    // archived, startup reads only what was written after it.
+    insert(storage.as_mut(), &config, 2, 6);
+    save_database(storage.as_mut(), &config).unwrap();
+    assert!(config.write_log_path().exists());
+    let segment = wal::rotate(&config.write_log_path(), storage.lsn(), 0, config.key_ring()).unwrap().unwrap();
+    insert(storage.as_mut(), &config, 6, 8);
+    drop(storage);
+    assert_eq!(wal::read_log(&config.write_log_path(), config.key_ring()).unwrap().len(), 2);
+    let storage = open_database(&config, false).unwrap();
+    assert_eq!((storage.lsn(), storage.select_all("t").unwrap().len()), (9, 8));
+    drop(storage);
+
+    // Back to LSN 5, inside the archived segment: it and the current log are
+    // set aside, so neither the next start nor a later recovery reads them.
+    let args = RecoverArgs { backup: backup.clone(), log: None, until_lsn: Some(5), until: None, force: true };
+    recover(Arc::new(config.clone()), &args).unwrap();
+    assert!(!segment.exists() && !config.write_log_path().exists());
+    assert!(wal::segments(&config.write_log_path()).unwrap().is_empty());
+    let storage = open_database(&config, false).unwrap();
+    assert_eq!((storage.lsn(), storage.select_all("t").unwrap().len()), (5, 4));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn startup_refuses_a_log_that_does_not_follow_the_data_file() {
+    let dir = temp_dir("recovery-gap");
+    let config = logged_config(&dir);
+    let mut storage = open_database(&config, false).unwrap();
+    storage.create_table("t", vec!["k".to_string()]).unwrap();
+    insert(storage.as_mut(), &config, 0, 3);
+    save_database(storage.as_mut(), &config).unwrap();
+    wal::rotate(&config.write_log_path(), storage.lsn(), 0, config.key_ring()).unwrap().unwrap();
+    insert(storage.as_mut(), &config, 3, 4);
+    drop(storage);
+
+    // Without the data file, the current log alone cannot rebuild the database.
+    std::fs::remove_file(config.data_path()).unwrap();
+    let error = open_database(&config, false).err().unwrap();
+    assert!(error.contains("starts at LSN 5") && error.contains("atom recover"), "{}", error);
+    std::fs::remove_dir_all(&dir).unwrap();
+}